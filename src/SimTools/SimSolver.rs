@@ -13,17 +13,37 @@ use plotters::prelude::*;
 pub mod simmodel;
 use simmodel::{*};
 
+pub mod simsource;
+pub mod simblock;
+pub mod simgraph;
+pub mod simanalysis;
+pub mod simdesign;
+pub mod simfreq;
+pub mod simresponse;
+pub mod simconvert;
+pub mod simreduce;
+pub mod simdiscrete;
+pub mod simhybrid;
+pub mod simpid;
+pub mod simnonlinear;
+pub mod simdelay;
+pub mod simlookup;
+pub mod simfnmodel;
+pub mod simlinearize;
+pub mod simsteady;
+
 
 #[derive(Debug)]
 pub struct Simulator<T> 
 where T: Model
 {
     simtime: f64, // シミュレーション時間
-    delta_t: f64, // 刻み幅Δt
+    delta_t: f64, // 刻み幅Δt（可変刻みのソルバでは記録する時刻の間隔）
     simsize: usize,
     solvertype: SolverType, // 計算手法
     model: T,
     simstorage: HashMap<String, Vec<f64>>, // 計測する信号名とデータ配列の組み合わせ
+    solver_failure: Option<(f64, &'static str)>, // 可変刻みのソルバが最初に計算を打ち切った (ステップ開始時刻, 理由)
 }
 
 impl<T> Simulator<T> 
@@ -52,6 +72,7 @@ where T: Model
             solvertype: solvertype,
             model: model,
             simstorage: storage,
+            solver_failure: None,
         }
     }
 
@@ -59,9 +80,13 @@ where T: Model
         let signalinfo = self.model.get_signals_info();
 
         for idx in 1..self.simsize {
+            let t = (idx - 1) as f64 * self.delta_t; // ステップ開始時刻
             self.simstorage.get_mut("time").unwrap().push(idx as f64 * self.delta_t); // 時刻の記録
 
-            self.model.calc_nextstate(self.delta_t, &self.solvertype); // 1ステップ進める
+            self.model.calc_nextstate(t, self.delta_t, &self.solvertype); // 1ステップ進める
+            if let (None, Some(reason)) = (self.solver_failure, self.model.get_step_control().failure) {
+                self.solver_failure = Some((t, reason)); // 打ち切った後も最後の有限の状態のまま記録を続ける
+            }
             let signals = self.model.get_allsignals(); // 現在の状態を取得する 
             for (i, e) in signalinfo.iter().enumerate() {
                 self.simstorage.get_mut(&e.to_string()).unwrap().push(signals[i]); // 計算結果を記録
@@ -69,6 +94,19 @@ where T: Model
         }
     }
 
+    pub fn get_simdata(&self, signal: &str) -> Option<&Vec<f64>> { // 記録した信号の時系列（時刻は"time"）
+        self.simstorage.get(signal)
+    }
+
+    pub fn get_model(&self) -> &T {
+        &self.model
+    }
+
+    /* ソルバが計算を打ち切ったステップの開始時刻と理由（打ち切らなかったときはNone） */
+    pub fn get_solver_failure(&self) -> Option<(f64, &'static str)> {
+        self.solver_failure
+    }
+
     pub fn export_sim(&self, filepath: &str) { // csv形式として吐き出す
         let mut file = BufWriter::new(File::create(filepath).unwrap());
 
@@ -103,43 +141,47 @@ where T: Model
 
     fn timeplot_subfn(&self, dirname: &str, signal: &String, pltsize: (u32, u32)) {
         let filename = format!("./{}/{}.png", dirname, signal);
-
-        let plt = BitMapBackend::new(&filename, pltsize).into_drawing_area();
-        plt.fill(&WHITE).unwrap();
-    
-        let font = ("sans-serif", 20);
-
         let timeaxis: &Vec<f64> = self.simstorage.get("time").unwrap();
         let valuaxis: &Vec<f64> = self.simstorage.get(signal).unwrap();
-        let (y_min, y_max) = valuaxis.iter()
-                         .fold(
-                           (0.0/0.0, 0.0/0.0),
-                           |(m,n), v| (v.min(m), v.max(n))
-                          ); // f64はNaNがあるためordが実装されていない。min, maxを使うための工夫が必要⇒https://qiita.com/lo48576/items/343ca40a03c3b86b67cb
-
-        let xrange = 0.0..self.simtime; 
-        let yrange = y_min..y_max;
-      
-        let mut chart = ChartBuilder::on(&plt)
-          .caption(&signal, font.into_font()) // キャプションのフォントやサイズ
-          .margin(10)                         // 上下左右全ての余白
-          .x_label_area_size(16)              // x軸ラベル部分の余白
-          .y_label_area_size(42)              // y軸ラベル部分の余白
-          .build_cartesian_2d(                // x軸とy軸の数値の範囲を指定する
-            xrange,                           // x軸の範囲
-            yrange)                           // y軸の範囲
-          .unwrap();
-    
-        // x軸y軸、グリッド線などを描画
-        chart.configure_mesh().draw().unwrap();
-
-        let line_series = LineSeries::new(
-            timeaxis.iter()
-                    .zip(valuaxis.iter())
-                    .map(|(x, y)| (*x, *y)),
-                &RED);
-
-        chart.draw_series(line_series).unwrap();
-
+        plot_timeseries(&filename, signal, timeaxis, valuaxis, self.simtime, pltsize);
     }
-}
\ No newline at end of file
+}
+
+/* 時系列を1枚のグラフに描く（Simulatorとマルチレートのシミュレータで共通） */
+fn plot_timeseries(filename: &str, caption: &str, timeaxis: &[f64], valuaxis: &[f64], simtime: f64, pltsize: (u32, u32)) {
+    let plt = BitMapBackend::new(filename, pltsize).into_drawing_area();
+    plt.fill(&WHITE).unwrap();
+
+    let font = ("sans-serif", 20);
+
+    let (y_min, y_max) = valuaxis.iter()
+                     .fold(
+                       (0.0/0.0, 0.0/0.0),
+                       |(m,n), v| (v.min(m), v.max(n))
+                      ); // f64はNaNがあるためordが実装されていない。min, maxを使うための工夫が必要⇒https://qiita.com/lo48576/items/343ca40a03c3b86b67cb
+
+    let xrange = 0.0..simtime; 
+    let yrange = y_min..y_max;
+  
+    let mut chart = ChartBuilder::on(&plt)
+      .caption(caption, font.into_font()) // キャプションのフォントやサイズ
+      .margin(10)                         // 上下左右全ての余白
+      .x_label_area_size(16)              // x軸ラベル部分の余白
+      .y_label_area_size(42)              // y軸ラベル部分の余白
+      .build_cartesian_2d(                // x軸とy軸の数値の範囲を指定する
+        xrange,                           // x軸の範囲
+        yrange)                           // y軸の範囲
+      .unwrap();
+
+    // x軸y軸、グリッド線などを描画
+    chart.configure_mesh().draw().unwrap();
+
+    let line_series = LineSeries::new(
+        timeaxis.iter()
+                .zip(valuaxis.iter())
+                .map(|(x, y)| (*x, *y)),
+            &RED);
+
+    chart.draw_series(line_series).unwrap();
+
+}
@@ -0,0 +1,418 @@
+/* 線形モデルの解析 */
+// 可制御性・可観測性の判定とグラミアンの計算、極・零点・直流ゲイン・安定性の確認
+
+extern crate nalgebra as na;
+use na::{DMatrix, Complex, ComplexField};
+use std::fmt;
+
+use super::simmodel::{*};
+
+/* 行列の数値的なランク（特異値がtolより大きいものの数） */
+pub fn matrix_rank(m: &DMatrix<f64>, tol: f64) -> usize {
+    if m.nrows() == 0 || m.ncols() == 0 {
+        return 0;
+    }
+    m.singular_values().iter().filter(|s| **s > tol).count()
+}
+
+/* 全ての固有値の実部が負か（連続時間で漸近安定か） */
+pub fn is_hurwitz(a: &DMatrix<f64>) -> bool {
+    a.clone().complex_eigenvalues().iter().all(|e| e.re < 0.0)
+}
+
+/* 連続時間リアプノフ方程式 A X + X A^T + Q = 0 を解く
+   クロネッカー積で n^2 元の連立一次方程式にして解くので、小～中規模のモデル向け */
+pub fn solve_lyapunov(a: &DMatrix<f64>, q: &DMatrix<f64>) -> Result<DMatrix<f64>, &'static str> {
+    let n = a.nrows();
+    if a.ncols() != n || q.nrows() != n || q.ncols() != n {
+        return Err("リアプノフ方程式の行列のサイズが違います。");
+    }
+
+    let eye = DMatrix::<f64>::identity(n, n);
+    let lhs = eye.kronecker(a) + a.kronecker(&eye);
+    let rhs = -DMatrix::from_column_slice(n * n, 1, q.as_slice());
+    let vec_x = lhs.lu().solve(&rhs).ok_or("リアプノフ方程式の解が一意に定まりません。")?;
+
+    let x = DMatrix::from_column_slice(n, n, vec_x.as_slice());
+    Ok((&x + x.transpose()) / 2.0) // 丸め誤差で崩れた対称性を戻す
+}
+
+impl SpaceStateModel {
+    /* 可制御性行列 [B, AB, A^2B, ..., A^(n-1)B] */
+    pub fn ctrb_matrix(&self) -> DMatrix<f64> {
+        let a = self.get_mat_a();
+        let b = self.get_mat_b();
+        let n = a.nrows();
+        let m = b.ncols();
+
+        let mut uc = DMatrix::<f64>::zeros(n, n * m);
+        let mut blk = b.clone();
+        for i in 0..n {
+            uc.slice_mut((0, i * m), (n, m)).copy_from(&blk);
+            blk = a * blk;
+        }
+        uc
+    }
+
+    /* 可観測性行列 [C; CA; CA^2; ...; CA^(n-1)] */
+    pub fn obsv_matrix(&self) -> DMatrix<f64> {
+        let a = self.get_mat_a();
+        let c = self.get_mat_c();
+        let n = a.nrows();
+        let p = c.nrows();
+
+        let mut uo = DMatrix::<f64>::zeros(n * p, n);
+        let mut blk = c.clone();
+        for i in 0..n {
+            uo.slice_mut((i * p, 0), (p, n)).copy_from(&blk);
+            blk = blk * a;
+        }
+        uo
+    }
+
+    pub fn ctrb_rank(&self, tol: f64) -> usize {
+        matrix_rank(&self.ctrb_matrix(), tol)
+    }
+
+    pub fn obsv_rank(&self, tol: f64) -> usize {
+        matrix_rank(&self.obsv_matrix(), tol)
+    }
+
+    pub fn is_controllable(&self, tol: f64) -> bool {
+        self.ctrb_rank(tol) == self.get_mat_a().nrows()
+    }
+
+    pub fn is_observable(&self, tol: f64) -> bool {
+        self.obsv_rank(tol) == self.get_mat_a().nrows()
+    }
+
+    /* 可制御性グラミアン Wc （A Wc + Wc A^T + B B^T = 0 の解、Aが安定な場合のみ） */
+    pub fn ctrb_gramian(&self) -> Result<DMatrix<f64>, &'static str> {
+        let a = self.get_mat_a();
+        if !is_hurwitz(a) {
+            return Err("安定でないモデルのグラミアンは求められません。");
+        }
+        let b = self.get_mat_b();
+        solve_lyapunov(a, &(b * b.transpose()))
+    }
+
+    /* 可観測性グラミアン Wo （A^T Wo + Wo A + C^T C = 0 の解、Aが安定な場合のみ） */
+    pub fn obsv_gramian(&self) -> Result<DMatrix<f64>, &'static str> {
+        let a = self.get_mat_a();
+        if !is_hurwitz(a) {
+            return Err("安定でないモデルのグラミアンは求められません。");
+        }
+        let c = self.get_mat_c();
+        solve_lyapunov(&a.transpose(), &(c.transpose() * c))
+    }
+}
+
+const POLISH_ITER: usize = 3;   // 多項式の根のニュートン法による修正回数
+
+/* 多項式の値（係数は高次から順に並べる） */
+pub fn poly_eval(coef: &[f64], s: Complex<f64>) -> Complex<f64> {
+    coef.iter().fold(Complex::new(0.0, 0.0), |acc, c| acc * s + c)
+}
+
+/* 多項式の根（係数は高次から順に並べる）
+   コンパニオン行列の固有値を求めた後、ニュートン法で精度を上げる */
+pub fn poly_roots(coef: &[f64]) -> Vec<Complex<f64>> {
+    let coef = match coef.iter().position(|c| *c != 0.0) { // 最高次の0を取り除く
+        Some(i) => &coef[i..],
+        None => return vec![],
+    };
+    let nzero = coef.iter().rev().take_while(|c| **c == 0.0).count(); // 定数項側の0は s = 0 の根
+    let coef = &coef[..coef.len() - nzero];
+    let deg = coef.len() - 1;
+
+    let mut roots = vec![Complex::new(0.0, 0.0); nzero];
+    if deg == 0 {
+        return roots;
+    }
+
+    let mut comp = DMatrix::<f64>::zeros(deg, deg);
+    for c in 0..deg {
+        comp[(0, c)] = -coef[c + 1] / coef[0];
+    }
+    for r in 1..deg {
+        comp[(r, r - 1)] = 1.0;
+    }
+
+    let dcoef: Vec<f64> = coef[..deg].iter().enumerate().map(|(i, c)| c * (deg - i) as f64).collect(); // 導関数の係数
+    for root in comp.complex_eigenvalues().iter() {
+        let mut z = *root;
+        for _ in 0..POLISH_ITER {
+            let dp = poly_eval(&dcoef, z);
+            if dp.modulus() == 0.0 {
+                break;
+            }
+            let znew = z - poly_eval(coef, z) / dp;
+            if !(poly_eval(coef, znew).modulus() < poly_eval(coef, z).modulus()) {
+                break;
+            }
+            z = znew;
+        }
+        roots.push(z);
+    }
+    roots
+}
+
+/* 極の減衰比と固有角周波数 */
+#[derive(Debug, Clone)]
+pub struct PoleInfo {
+    pub pole: Complex<f64>,
+    pub wn: f64,    // 固有角周波数 |p| [rad/s]
+    pub zeta: f64,  // 減衰比 -Re(p) / |p| （原点の極では-1とする）
+}
+
+/* 極ごとの減衰比と固有角周波数を求める */
+pub fn damp(poles: &[Complex<f64>]) -> Vec<PoleInfo> {
+    poles.iter().map(|p| {
+        let wn = p.modulus();
+        let zeta = if wn > 0.0 { -p.re / wn } else { -1.0 };
+        PoleInfo { pole: *p, wn: wn, zeta: zeta }
+    }).collect()
+}
+
+/* 極の一覧を表にして表示する（println!("{}", DampTable(&model.damp())) のように使う） */
+pub struct DampTable<'a>(pub &'a [PoleInfo]);
+
+impl<'a> fmt::Display for DampTable<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:>24} {:>12} {:>12}", "pole", "zeta", "wn[rad/s]")?;
+        for pi in self.0 {
+            writeln!(f, "{:>11.4e} {:>+11.4e}j {:>12.4e} {:>12.4e}", pi.pole.re, pi.pole.im, pi.zeta, pi.wn)?;
+        }
+        Ok(())
+    }
+}
+
+/* 行列の行(または列)空間を、特異値がtol以下の部分と大きい部分に分けた直交行列
+   行方向: U^T M = [0; M2] となる U、列方向: M V = [0, M2] となる V と、そのランクを返す */
+fn split_by_rank(m: &DMatrix<f64>, tol: f64, rowwise: bool) -> (DMatrix<f64>, usize) {
+    let m = if rowwise { m.clone() } else { m.transpose() };
+    let (nr, nc) = m.shape();
+    if nr == 0 || nc == 0 {
+        return (DMatrix::<f64>::identity(nr, nr), 0);
+    }
+
+    // 完全な直交基底を得るため、列を0で埋めて横長にしてから特異値分解する
+    let mut padded = DMatrix::<f64>::zeros(nr, nc.max(nr));
+    padded.slice_mut((0, 0), (nr, nc)).copy_from(&m);
+    let svd = padded.svd(true, false);
+    let u = svd.u.unwrap();
+    let rank = svd.singular_values.iter().filter(|s| **s > tol).count();
+
+    // 特異値は降順なので、ランク分の列を後ろに回す
+    let mut q = DMatrix::<f64>::zeros(nr, nr);
+    q.slice_mut((0, 0), (nr, nr - rank)).copy_from(&u.slice((0, rank), (nr, nr - rank)));
+    q.slice_mut((0, nr - rank), (nr, rank)).copy_from(&u.slice((0, 0), (nr, rank)));
+    (q, rank)
+}
+
+/* 零点を変えずに、D行列が行フルランクになるまで状態の次数を下げる
+   (Emami-Naeini & Van Dooren の縮約法) */
+fn reduce_system(a: DMatrix<f64>, b: DMatrix<f64>, c: DMatrix<f64>, d: DMatrix<f64>, tol: f64)
+    -> (DMatrix<f64>, DMatrix<f64>, DMatrix<f64>, DMatrix<f64>) {
+    let (mut a, mut b, mut c, mut d) = (a, b, c, d);
+    loop {
+        let n = a.nrows();
+        let m = b.ncols();
+        let p = c.nrows();
+
+        // U^T D = [0; Dμ] （Dμは行フルランク）
+        let (u, r) = split_by_rank(&d, tol, true);
+        let tau = p - r;
+        if tau == 0 {
+            return (a, b, c, d);
+        }
+        let uc = u.transpose() * &c;
+        let ud = u.transpose() * &d;
+        let c_tau = uc.rows(0, tau).into_owned();
+        let c_mu = uc.rows(tau, r).into_owned();
+        let d_mu = ud.rows(tau, r).into_owned();
+
+        // Cτ V = [0, Cρ] （Cρは列フルランク）
+        let (v, rho) = split_by_rank(&c_tau, tol, false);
+        if rho == 0 { // 恒等的に0の出力は取り除く
+            c = c_mu;
+            d = d_mu;
+            continue;
+        }
+        let n1 = n - rho;
+        let av = v.transpose() * &a * &v;
+        let bv = v.transpose() * &b;
+        let cv = &c_mu * &v;
+
+        let mut cnew = DMatrix::<f64>::zeros(rho + r, n1);
+        cnew.slice_mut((0, 0), (rho, n1)).copy_from(&av.slice((n1, 0), (rho, n1)));
+        cnew.slice_mut((rho, 0), (r, n1)).copy_from(&cv.slice((0, 0), (r, n1)));
+        let mut dnew = DMatrix::<f64>::zeros(rho + r, m);
+        dnew.slice_mut((0, 0), (rho, m)).copy_from(&bv.slice((n1, 0), (rho, m)));
+        dnew.slice_mut((rho, 0), (r, m)).copy_from(&d_mu);
+
+        a = av.slice((0, 0), (n1, n1)).into_owned();
+        b = bv.slice((0, 0), (n1, m)).into_owned();
+        c = cnew;
+        d = dnew;
+    }
+}
+
+impl SpaceStateModel {
+    /* 極（A行列の固有値） */
+    pub fn poles(&self) -> Vec<Complex<f64>> {
+        self.get_mat_a().clone().complex_eigenvalues().iter().cloned().collect()
+    }
+
+    /* 不変零点（MIMOでは伝達零点、非可制御・非可観測なモードも含む）
+       システム行列 [A - sI, B; C, D] のランクが落ちる s を求める */
+    pub fn zeros(&self) -> Result<Vec<Complex<f64>>, &'static str> {
+        let (a, b, c, d) = (self.get_mat_a(), self.get_mat_b(), self.get_mat_c(), self.get_mat_d());
+        let tol = 1e-10 * (1.0 + a.norm() + b.norm() + c.norm() + d.norm());
+
+        // Dが行フルランクになるまで縮約し、双対システムでも同様に縮約する
+        let (a, b, c, d) = reduce_system(a.clone(), b.clone(), c.clone(), d.clone(), tol);
+        let (at, ct, bt, dt) = reduce_system(a.transpose(), c.transpose(), b.transpose(), d.transpose(), tol);
+        let (a, b, c, d) = (at.transpose(), bt.transpose(), ct.transpose(), dt.transpose());
+
+        if a.nrows() == 0 {
+            return Ok(vec![]);
+        }
+        if d.nrows() != d.ncols() {
+            return Err("システム行列の正規ランクが落ちているため零点を求められません。");
+        }
+        let dinv = d.try_inverse().ok_or("システム行列の正規ランクが落ちているため零点を求められません。")?;
+        Ok((a - b * dinv * c).complex_eigenvalues().iter().cloned().collect())
+    }
+
+    /* 直流ゲイン D - C A^(-1) B */
+    pub fn dcgain(&self) -> Result<DMatrix<f64>, &'static str> {
+        let ainv_b = self.get_mat_a().clone().lu().solve(self.get_mat_b()).ok_or("原点に極があるため直流ゲインが無限大です。")?;
+        Ok(self.get_mat_d() - self.get_mat_c() * ainv_b)
+    }
+
+    pub fn damp(&self) -> Vec<PoleInfo> {
+        damp(&self.poles())
+    }
+
+    pub fn is_stable(&self) -> bool {
+        is_hurwitz(self.get_mat_a())
+    }
+}
+
+impl TransFuncModel {
+    /* 極（分母多項式の根） */
+    pub fn poles(&self) -> Vec<Complex<f64>> {
+        poly_roots(self.get_den())
+    }
+
+    /* 零点（分子多項式の根） */
+    pub fn zeros(&self) -> Vec<Complex<f64>> {
+        poly_roots(self.get_num())
+    }
+
+    /* 直流ゲイン G(0) */
+    pub fn dcgain(&self) -> Result<f64, &'static str> {
+        let den0 = *self.get_den().last().unwrap();
+        if den0 == 0.0 {
+            return Err("原点に極があるため直流ゲインが無限大です。");
+        }
+        Ok(self.get_num().last().copied().unwrap_or(0.0) / den0)
+    }
+
+    pub fn damp(&self) -> Vec<PoleInfo> {
+        damp(&self.poles())
+    }
+
+    pub fn is_stable(&self) -> bool {
+        self.poles().iter().all(|p| p.re < 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* 2次系 x'' + 0.4 x' + 4 x = u, y = x */
+    fn oscillator() -> SpaceStateModel {
+        let mut model = SpaceStateModel::new(2, 1, 1);
+        model.set_mat_a(&[0.0, 1.0, -4.0, -0.4]).unwrap();
+        model.set_mat_b(&[0.0, 1.0]).unwrap();
+        model.set_mat_c(&[1.0, 0.0]).unwrap();
+        model
+    }
+
+    #[test]
+    fn rank_detects_uncontrollable_and_unobservable_modes() {
+        let model = oscillator();
+        assert!(model.is_controllable(1e-9) && model.is_observable(1e-9));
+
+        // 2つの状態が独立に減衰し、入力は1つ目だけ、出力は2つ目だけに現れる
+        let mut split = SpaceStateModel::new(2, 1, 1);
+        split.set_mat_a(&[-1.0, 0.0, 0.0, -2.0]).unwrap();
+        split.set_mat_b(&[1.0, 0.0]).unwrap();
+        split.set_mat_c(&[0.0, 1.0]).unwrap();
+        assert_eq!((split.ctrb_rank(1e-9), split.obsv_rank(1e-9)), (1, 1));
+    }
+
+    #[test]
+    fn gramians_satisfy_lyapunov_equation() {
+        let model = oscillator();
+        let (a, b, c) = (model.get_mat_a(), model.get_mat_b(), model.get_mat_c());
+        let wc = model.ctrb_gramian().unwrap();
+        let wo = model.obsv_gramian().unwrap();
+        assert!((a * &wc + &wc * a.transpose() + b * b.transpose()).amax() < 1e-10);
+        assert!((a.transpose() * &wo + &wo * a + c.transpose() * c).amax() < 1e-10);
+
+        let mut unstable = oscillator();
+        unstable.set_mat_a(&[0.0, 1.0, 4.0, 0.0]).unwrap();
+        assert!(unstable.ctrb_gramian().is_err());
+    }
+
+    /* 根を実部の昇順に並べる */
+    fn sorted(mut roots: Vec<Complex<f64>>) -> Vec<Complex<f64>> {
+        roots.sort_by(|a, b| a.re.partial_cmp(&b.re).unwrap().then(a.im.partial_cmp(&b.im).unwrap()));
+        roots
+    }
+
+    #[test]
+    fn poles_zeros_and_dcgain_of_transfer_function() {
+        // G(s) = (s + 3) / ((s + 1)(s + 2))
+        let (num, den) = ([1.0, 3.0], [1.0, 3.0, 2.0]);
+        let tf = TransFuncModel::new(&num, &den);
+        let ss = SpaceStateModel::from_tf(&num, &den).unwrap();
+
+        for poles in [tf.poles(), ss.poles()] {
+            let poles = sorted(poles);
+            assert!((poles[0].re + 2.0).abs() < 1e-9 && (poles[1].re + 1.0).abs() < 1e-9);
+        }
+        for zeros in [tf.zeros(), ss.zeros().unwrap()] {
+            assert_eq!(zeros.len(), 1);
+            assert!((zeros[0] - Complex::new(-3.0, 0.0)).modulus() < 1e-9);
+        }
+        assert!((tf.dcgain().unwrap() - 1.5).abs() < 1e-12);
+        assert!((ss.dcgain().unwrap()[0] - 1.5).abs() < 1e-12);
+        assert!(tf.is_stable() && ss.is_stable());
+    }
+
+    #[test]
+    fn damp_gives_natural_frequency_and_damping_ratio() {
+        let info = oscillator().damp();
+        assert!(info.iter().all(|p| (p.wn - 2.0).abs() < 1e-9 && (p.zeta - 0.1).abs() < 1e-9));
+
+        let roots = sorted(poly_roots(&[1.0, 0.0, -4.0]));
+        assert!((roots[0].re + 2.0).abs() < 1e-12 && (roots[1].re - 2.0).abs() < 1e-12);
+
+        let integrator = TransFuncModel::new(&[1.0], &[1.0, 0.0]);
+        assert!(integrator.dcgain().is_err());
+    }
+
+    #[test]
+    fn damp_table_has_one_row_per_pole() {
+        let table = format!("{}", DampTable(&oscillator().damp()));
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].contains("zeta"));
+        assert!(lines[1].contains("2.0000e0") && lines[1].contains("1.0000e-1"));
+    }
+}
@@ -0,0 +1,414 @@
+/* ブロック線図の結合 */
+// 線形モデル同士は等価な状態空間モデルに、一般のモデル同士は状態ベクトルをまとめた合成モデルにする
+
+extern crate nalgebra as na;
+use na::DMatrix;
+
+use super::simmodel::{*};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeedbackType {
+    Negative,   // 負帰還 e = r - y_h
+    Positive,   // 正帰還 e = r + y_h
+}
+
+impl FeedbackType {
+    fn sign(&self) -> f64 {
+        match self {
+            FeedbackType::Negative => -1.0,
+            FeedbackType::Positive => 1.0,
+        }
+    }
+}
+
+/* 対角ブロック行列 [[m1, 0], [0, m2]] を作る */
+fn block_diag(m1: &DMatrix<f64>, m2: &DMatrix<f64>) -> DMatrix<f64> {
+    let mut m = DMatrix::<f64>::zeros(m1.nrows() + m2.nrows(), m1.ncols() + m2.ncols());
+    m.slice_mut((0, 0), m1.shape()).copy_from(m1);
+    m.slice_mut(m1.shape(), m2.shape()).copy_from(m2);
+    m
+}
+
+/* 縦に並べた行列 [m1; m2] を作る */
+fn vstack(m1: &DMatrix<f64>, m2: &DMatrix<f64>) -> DMatrix<f64> {
+    let mut m = DMatrix::<f64>::zeros(m1.nrows() + m2.nrows(), m1.ncols());
+    m.slice_mut((0, 0), m1.shape()).copy_from(m1);
+    m.slice_mut((m1.nrows(), 0), m2.shape()).copy_from(m2);
+    m
+}
+
+/* 横に並べた行列 [m1, m2] を作る */
+fn hstack(m1: &DMatrix<f64>, m2: &DMatrix<f64>) -> DMatrix<f64> {
+    let mut m = DMatrix::<f64>::zeros(m1.nrows(), m1.ncols() + m2.ncols());
+    m.slice_mut((0, 0), m1.shape()).copy_from(m1);
+    m.slice_mut((0, m1.ncols()), m2.shape()).copy_from(m2);
+    m
+}
+
+impl SpaceStateModel {
+    /* 直列結合 u → self → other → y （状態は [x_self; x_other]） */
+    pub fn series(&self, other: &SpaceStateModel) -> Result<SpaceStateModel, &'static str> {
+        if self.get_output_dim() != other.get_input_dim() {
+            return Err("前段の出力次数と後段の入力次数が違います。");
+        }
+
+        let (a1, b1, c1, d1) = (self.get_mat_a(), self.get_mat_b(), self.get_mat_c(), self.get_mat_d());
+        let (a2, b2, c2, d2) = (other.get_mat_a(), other.get_mat_b(), other.get_mat_c(), other.get_mat_d());
+
+        let mut mat_a = block_diag(a1, a2);
+        mat_a.slice_mut((a1.nrows(), 0), (a2.nrows(), a1.ncols())).copy_from(&(b2 * c1));
+        let mat_b = vstack(b1, &(b2 * d1));
+        let mat_c = hstack(&(d2 * c1), c2);
+        let mat_d = d2 * d1;
+
+        let mut model = SpaceStateModel::from_matrices(mat_a, mat_b, mat_c, mat_d)?;
+        model.set_state(vstack(self.get_state(), other.get_state()));
+        Ok(model)
+    }
+
+    /* 並列結合 y = y_self + y_other （状態は [x_self; x_other]） */
+    pub fn parallel(&self, other: &SpaceStateModel) -> Result<SpaceStateModel, &'static str> {
+        if self.get_input_dim() != other.get_input_dim() || self.get_output_dim() != other.get_output_dim() {
+            return Err("並列結合するモデルの入出力次数が違います。");
+        }
+
+        let mat_a = block_diag(self.get_mat_a(), other.get_mat_a());
+        let mat_b = vstack(self.get_mat_b(), other.get_mat_b());
+        let mat_c = hstack(self.get_mat_c(), other.get_mat_c());
+        let mat_d = self.get_mat_d() + other.get_mat_d();
+
+        let mut model = SpaceStateModel::from_matrices(mat_a, mat_b, mat_c, mat_d)?;
+        model.set_state(vstack(self.get_state(), other.get_state()));
+        Ok(model)
+    }
+
+    /* フィードバック結合（selfが前向き要素、otherがフィードバック要素）
+       e = r ± y_other, y = y_self, u_other = y （状態は [x_self; x_other]） */
+    pub fn feedback(&self, other: &SpaceStateModel, fbtype: FeedbackType) -> Result<SpaceStateModel, &'static str> {
+        if self.get_output_dim() != other.get_input_dim() || other.get_output_dim() != self.get_input_dim() {
+            return Err("フィードバック結合するモデルの入出力次数が合っていません。");
+        }
+
+        let s = fbtype.sign();
+        let (a1, b1, c1, d1) = (self.get_mat_a(), self.get_mat_b(), self.get_mat_c(), self.get_mat_d());
+        let (a2, b2, c2, d2) = (other.get_mat_a(), other.get_mat_b(), other.get_mat_c(), other.get_mat_d());
+        let odim = self.get_output_dim();
+        let idim = self.get_input_dim();
+
+        // 直達項による代数ループ (I - s D1 D2) y = C1 x1 + s D1 C2 x2 + D1 r を解く
+        let e = (DMatrix::<f64>::identity(odim, odim) - d1 * d2 * s)
+            .try_inverse()
+            .ok_or("直達項による代数ループが解けません。")?;
+        let c_y = &e * hstack(c1, &(d1 * c2 * s));    // y = C_y x + D_y r
+        let d_y = &e * d1;
+        let c_e = hstack(&DMatrix::<f64>::zeros(idim, a1.ncols()), &(c2 * s)) + d2 * &c_y * s; // e = C_e x + D_e r
+        let d_e = DMatrix::<f64>::identity(idim, idim) + d2 * &d_y * s;
+
+        let mat_a = block_diag(a1, a2) + vstack(&(b1 * &c_e), &(b2 * &c_y));
+        let mat_b = vstack(&(b1 * &d_e), &(b2 * &d_y));
+
+        let mut model = SpaceStateModel::from_matrices(mat_a, mat_b, c_y, d_y)?;
+        model.set_state(vstack(self.get_state(), other.get_state()));
+        Ok(model)
+    }
+}
+
+#[derive(Debug)]
+enum Connection {
+    Series,
+    Parallel,
+    Feedback(FeedbackType),
+}
+
+/* 一般のモデル同士を結合した合成モデル
+   2つのモデルの状態をまとめた [x1; x2] を1つの状態ベクトルとして積分する
+   内部のモデルの calc_nextstate は呼ばれず、積分には slopefunc_u と outputfunc が使われる
+   積分で変化しない状態（離散時間モデルなど）は、積分の後に内部のモデルの update_discrete で進める */
+#[derive(Debug)]
+pub struct CompositeModel<M1, M2>
+where M1: Model, M2: Model
+{
+    connection: Connection,
+    model1: M1,
+    model2: M2,
+    names: (String, String),    // 信号名の接頭辞
+    t: f64,                     // 現在時刻（記録する信号の計算に使う）
+    x: DMatrix<f64>,            // 結合した状態ベクトル [x1; x2]
+    u: DMatrix<f64>,            // 合成モデルへの入力
+    step_control: StepControl,  // 可変刻みのソルバの状態
+}
+
+impl<M1, M2> CompositeModel<M1, M2>
+where M1: Model, M2: Model
+{
+    /* 直列結合 u → model1 → model2 → y */
+    pub fn series(model1: M1, model2: M2) -> Result<Self, &'static str> {
+        if model1.get_output_dim() != model2.get_input_dim() {
+            return Err("前段の出力次数と後段の入力次数が違います。");
+        }
+        Ok(Self::build(Connection::Series, model1, model2))
+    }
+
+    /* 並列結合 y = y1 + y2 */
+    pub fn parallel(model1: M1, model2: M2) -> Result<Self, &'static str> {
+        if model1.get_input_dim() != model2.get_input_dim() || model1.get_output_dim() != model2.get_output_dim() {
+            return Err("並列結合するモデルの入出力次数が違います。");
+        }
+        Ok(Self::build(Connection::Parallel, model1, model2))
+    }
+
+    /* フィードバック結合（model1が前向き要素、model2がフィードバック要素） */
+    pub fn feedback(model1: M1, model2: M2, fbtype: FeedbackType) -> Result<Self, &'static str> {
+        if model1.get_output_dim() != model2.get_input_dim() || model2.get_output_dim() != model1.get_input_dim() {
+            return Err("フィードバック結合するモデルの入出力次数が合っていません。");
+        }
+        if model1.has_feedthrough() && model2.has_feedthrough() {
+            return Err("両方のモデルに直達項があるため代数ループになります。");
+        }
+        Ok(Self::build(Connection::Feedback(fbtype), model1, model2))
+    }
+
+    fn build(connection: Connection, model1: M1, model2: M2) -> Self {
+        let x = vstack(model1.get_state(), model2.get_state());
+        let u = DMatrix::from_element(model1.get_input_dim(), 1, 0.0);
+        let names = match connection {
+            Connection::Feedback(_) => ("G".to_string(), "H".to_string()),
+            _ => ("G1".to_string(), "G2".to_string()),
+        };
+
+        let mut model = Self {
+            connection: connection,
+            model1: model1,
+            model2: model2,
+            names: names,
+            t: 0.0,
+            x: x,
+            u: u,
+            step_control: StepControl::default(),
+        };
+        model.update_signals();
+        model
+    }
+
+    /* 内部モデルの信号名の接頭辞を設定する（"G1.x_0" のように記録される） */
+    pub fn set_names(&mut self, name1: &str, name2: &str) {
+        self.names = (name1.to_string(), name2.to_string());
+    }
+
+    pub fn get_models(&self) -> (&M1, &M2) {
+        (&self.model1, &self.model2)
+    }
+
+    fn split_state(&self, x: &DMatrix<f64>) -> (DMatrix<f64>, DMatrix<f64>) {
+        let n1 = self.model1.get_state().nrows();
+        let n2 = x.nrows() - n1;
+        (x.rows(0, n1).into_owned(), x.rows(n1, n2).into_owned())
+    }
+
+    /* 結合状態xと入力uから各モデルへの入力と合成モデルの出力を求める (u1, u2, y) */
+    fn calc_ports(&self, t: f64, x1: &DMatrix<f64>, x2: &DMatrix<f64>, u: &DMatrix<f64>) -> (DMatrix<f64>, DMatrix<f64>, DMatrix<f64>) {
+        match self.connection {
+            Connection::Series => {
+                let y1 = self.model1.outputfunc(t, x1, u);
+                let y2 = self.model2.outputfunc(t, x2, &y1);
+                (u.clone(), y1, y2)
+            },
+            Connection::Parallel => {
+                let y = self.model1.outputfunc(t, x1, u) + self.model2.outputfunc(t, x2, u);
+                (u.clone(), u.clone(), y)
+            },
+            Connection::Feedback(fbtype) => {
+                let s = fbtype.sign();
+                if !self.model1.has_feedthrough() {
+                    // 前向き要素の出力が入力に依存しないので、先に出力を求める
+                    let y1 = self.model1.outputfunc(t, x1, &DMatrix::from_element(self.model1.get_input_dim(), 1, 0.0));
+                    let yh = self.model2.outputfunc(t, x2, &y1);
+                    (u + yh * s, y1.clone(), y1)
+                } else {
+                    // フィードバック要素の出力が入力に依存しないので、先に偏差を求める
+                    let yh = self.model2.outputfunc(t, x2, &DMatrix::from_element(self.model2.get_input_dim(), 1, 0.0));
+                    let e = u + yh * s;
+                    let y1 = self.model1.outputfunc(t, x1, &e);
+                    (e, y1.clone(), y1)
+                }
+            },
+        }
+    }
+
+    /* 内部モデルの状態と入力を現在の値にそろえる（記録される信号を一致させるため） */
+    fn update_signals(&mut self) {
+        let (x1, x2) = self.split_state(&self.x);
+        let (u1, u2, _) = self.calc_ports(self.t, &x1, &x2, &self.u);
+        self.model1.set_state(x1);
+        self.model2.set_state(x2);
+        self.model1.set_input(u1.as_slice());
+        self.model2.set_input(u2.as_slice());
+    }
+}
+
+impl<M1, M2> Model for CompositeModel<M1, M2>
+where M1: Model, M2: Model
+{
+    fn slopefunc(&self, t: f64, x: &DMatrix<f64>) -> DMatrix<f64> {
+        self.slopefunc_u(t, x, &self.u)
+    }
+
+    fn get_signals_info(&self) -> Vec<String> {
+        let mut series = (0..self.get_input_dim()).map(|i| format!("u_{}", i)).collect::<Vec<String>>();
+        series.append(&mut self.model1.get_signals_info().iter().map(|s| format!("{}.{}", self.names.0, s)).collect::<Vec<String>>());
+        series.append(&mut self.model2.get_signals_info().iter().map(|s| format!("{}.{}", self.names.1, s)).collect::<Vec<String>>());
+        series.append(&mut (0..self.get_output_dim()).map(|i| format!("y_{}", i)).collect::<Vec<String>>());
+        series
+    }
+
+    fn set_state(&mut self, newstate: DMatrix<f64>) {
+        self.x = newstate;
+        self.update_signals();
+    }
+
+    fn get_state(&self) -> &DMatrix<f64> {
+        &self.x
+    }
+
+    fn get_allsignals(&self) -> Vec<f64> {
+        let mut signals = self.u.iter().map(|u| *u).collect::<Vec<f64>>();
+        signals.append(&mut self.model1.get_allsignals());
+        signals.append(&mut self.model2.get_allsignals());
+        signals.append(&mut self.outputfunc(self.t, &self.x, &self.u).iter().map(|y| *y).collect::<Vec<f64>>());
+        signals
+    }
+
+    fn calc_nextstate(&mut self, t: f64, delta_t : f64, solvertype: &SolverType) {
+        self.update_discrete(t, 0.0); // 時刻tちょうどのサンプル（時刻0など）はその時刻の入力で処理する
+        let newstate = solve_nextstate(self, t, delta_t, solvertype);
+        self.t = t + delta_t;
+        self.set_state(newstate);
+        self.update_discrete(t, delta_t);
+    }
+
+    fn update_discrete(&mut self, t: f64, delta_t: f64) {
+        self.model1.update_discrete(t, delta_t);
+        self.model2.update_discrete(t, delta_t);
+        self.x = vstack(self.model1.get_state(), self.model2.get_state()); // 更新した内部のモデルの状態を結合状態に戻す
+        self.update_signals();
+    }
+
+    fn get_input_dim(&self) -> usize {
+        self.model1.get_input_dim()
+    }
+
+    fn get_input(&self) -> DMatrix<f64> {
+        self.u.clone()
+    }
+
+    fn set_input(&mut self, u: &[f64]) {
+        self.u = DMatrix::from_column_slice(u.len(), 1, u);
+        self.update_signals();
+    }
+
+    fn slopefunc_u(&self, t: f64, x: &DMatrix<f64>, u: &DMatrix<f64>) -> DMatrix<f64> {
+        let (x1, x2) = self.split_state(x);
+        let (u1, u2, _) = self.calc_ports(t, &x1, &x2, u);
+        vstack(&self.model1.slopefunc_u(t, &x1, &u1), &self.model2.slopefunc_u(t, &x2, &u2))
+    }
+
+    fn get_output_dim(&self) -> usize {
+        match self.connection {
+            Connection::Series => self.model2.get_output_dim(),
+            _ => self.model1.get_output_dim(),
+        }
+    }
+
+    fn outputfunc(&self, t: f64, x: &DMatrix<f64>, u: &DMatrix<f64>) -> DMatrix<f64> {
+        let (x1, x2) = self.split_state(x);
+        let (_, _, y) = self.calc_ports(t, &x1, &x2, u);
+        y
+    }
+
+    fn has_feedthrough(&self) -> bool {
+        match self.connection {
+            Connection::Series => self.model1.has_feedthrough() && self.model2.has_feedthrough(),
+            Connection::Parallel => self.model1.has_feedthrough() || self.model2.has_feedthrough(),
+            Connection::Feedback(_) => self.model1.has_feedthrough(),
+        }
+    }
+
+    fn get_step_control(&self) -> StepControl {
+        self.step_control
+    }
+
+    fn set_step_control(&mut self, control: StepControl) {
+        self.step_control = control;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::simdiscrete::DiscreteSpaceStateModel;
+
+    /* 1次遅れ k / (s + a) */
+    fn lag(k: f64, a: f64) -> SpaceStateModel {
+        let mut model = SpaceStateModel::new(1, 1, 1);
+        model.set_mat_a(&[-a]).unwrap();
+        model.set_mat_b(&[k]).unwrap();
+        model.set_mat_c(&[1.0]).unwrap();
+        model
+    }
+
+    fn run<M: Model>(model: &mut M, t_end: f64) -> f64 {
+        model.set_input(&[1.0]);
+        let steps = (t_end / 0.01).round() as usize;
+        for i in 0..steps {
+            model.calc_nextstate(i as f64 * 0.01, 0.01, &SolverType::RungeKutta);
+        }
+        let u = model.get_input();
+        model.outputfunc(t_end, model.get_state(), &u)[0]
+    }
+
+    #[test]
+    fn linear_connections_have_expected_dc_gain() {
+        // G1 = 1/(s+1), G2 = 2/(s+2) はどちらも直流ゲイン1
+        let (g1, g2) = (lag(1.0, 1.0), lag(2.0, 2.0));
+        assert!((run(&mut g1.series(&g2).unwrap(), 20.0) - 1.0).abs() < 1e-6);
+        assert!((run(&mut g1.parallel(&g2).unwrap(), 20.0) - 2.0).abs() < 1e-6);
+        assert!((run(&mut g1.feedback(&g2, FeedbackType::Negative).unwrap(), 20.0) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn composite_model_matches_linear_connection() {
+        let (g1, g2) = (lag(1.0, 1.0), lag(2.0, 2.0));
+        let pairs = [
+            (g1.series(&g2).unwrap(), CompositeModel::series(g1.clone(), g2.clone()).unwrap()),
+            (g1.parallel(&g2).unwrap(), CompositeModel::parallel(g1.clone(), g2.clone()).unwrap()),
+            (g1.feedback(&g2, FeedbackType::Positive).unwrap(), CompositeModel::feedback(g1.clone(), g2.clone(), FeedbackType::Positive).unwrap()),
+        ];
+        for (mut linear, mut composite) in pairs {
+            let (y1, y2) = (run(&mut linear, 3.0), run(&mut composite, 3.0));
+            assert!((y1 - y2).abs() < 1e-9, "{} != {}", y1, y2);
+        }
+    }
+
+    #[test]
+    fn composite_model_steps_discrete_inner_model() {
+        // 積算器 x[k+1] = x[k] + u[k] (ts = 0.1) の後ろにゲイン1の静的なモデルをつなぐ
+        let one = DMatrix::from_element(1, 1, 1.0);
+        let acc = DiscreteSpaceStateModel::from_matrices(one.clone(), one.clone(), one.clone(), DMatrix::zeros(1, 1), 0.1).unwrap();
+        let mut gain = SpaceStateModel::new(0, 1, 1);
+        gain.set_mat_d(&[1.0]).unwrap();
+        let mut composite = CompositeModel::series(acc.clone(), gain).unwrap();
+        let mut standalone = acc;
+        let (y1, y2) = (run(&mut standalone, 1.0), run(&mut composite, 1.0));
+        assert!((y1 - 10.0).abs() < 1e-9, "{}", y1);
+        assert!((y1 - y2).abs() < 1e-9, "{} != {}", y1, y2);
+    }
+
+    #[test]
+    fn connections_check_dimensions() {
+        let g = lag(1.0, 1.0);
+        let g2 = SpaceStateModel::new(1, 2, 1);
+        assert!(g.series(&g2).is_err());
+        assert!(g.parallel(&g2).is_err());
+        assert!(CompositeModel::feedback(g.clone(), g2, FeedbackType::Negative).is_err());
+    }
+}
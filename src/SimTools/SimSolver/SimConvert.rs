@@ -0,0 +1,315 @@
+/* モデルの変換 */
+// 状態空間モデルから伝達関数行列への変換、正準形・モード形・ジョルダン形への座標変換
+
+extern crate nalgebra as na;
+use na::{DMatrix, DVector, Complex, ComplexField};
+
+use super::simmodel::{*};
+use super::simanalysis::{*};
+use super::simdesign::{poly_from_roots};
+
+/* 伝達関数行列（全要素で分母を共通にした形） */
+#[derive(Debug, Clone)]
+pub struct TfMatrix {
+    pub num: Vec<Vec<Vec<f64>>>,    // num[i][j]: 入力jから出力iへの分子多項式の係数（降べきの順）
+    pub den: Vec<f64>,              // 共通の分母多項式 det(sI - A) の係数（降べきの順）
+}
+
+impl TfMatrix {
+    /* 入力jから出力iへの伝達関数 */
+    pub fn get_tf(&self, i: usize, j: usize) -> Result<TransFuncModel, &'static str> {
+        if i >= self.num.len() || j >= self.num[i].len() {
+            return Err("入出力の番号が範囲外です。");
+        }
+        if self.den.len() < 2 {
+            return Err("次数が0になりました。");
+        }
+        Ok(TransFuncModel::new(&self.num[i][j], &self.den))
+    }
+}
+
+/* 分母多項式の係数を最高次が1になるように正規化し、分子を分母と同じ長さに揃える */
+fn normalize_tf(num: &[f64], den: &[f64]) -> Result<(Vec<f64>, Vec<f64>), &'static str> {
+    if den.len() < 2 {
+        return Err("次数が0になりました。");
+    }
+    if num.len() > den.len() {
+        return Err("プロパーな伝達関数ではありません。");
+    }
+    if den[0] == 0.0 {
+        return Err("分母多項式の最高次の係数が0です。");
+    }
+
+    let mut padded = vec![0.0; den.len() - num.len()];
+    padded.extend_from_slice(num);
+    Ok((padded.iter().map(|b| b / den[0]).collect(), den.iter().map(|a| a / den[0]).collect()))
+}
+
+/* 分母多項式から作る上三角のハンケル行列 W （正準形への変換行列に使う） */
+fn hankel_from_den(den: &[f64]) -> DMatrix<f64> {
+    let n = den.len() - 1;
+    DMatrix::from_fn(n, n, |i, j| if i + j + 1 <= n { den[n - (i + j + 1)] } else { 0.0 })
+}
+
+/* 実ジョルダン形への変換行列 T （x = T z）と、対角化できたか（ジョルダン鎖が無いか）
+   固有値はtol以内のものを同じとみなす。複素固有値は重複のないもののみ、実固有値は固有値ごとにジョルダンブロック1個まで対応 */
+fn real_jordan_basis(a: &DMatrix<f64>, tol: f64) -> Result<(DMatrix<f64>, bool), &'static str> {
+    let n = a.nrows();
+    let mut eigs: Vec<Complex<f64>> = a.clone().complex_eigenvalues().iter().cloned().collect();
+    eigs.sort_by(|p, q| q.re.partial_cmp(&p.re).unwrap().then(q.im.partial_cmp(&p.im).unwrap()));
+
+    // 近い固有値をまとめる
+    let mut clusters: Vec<(Complex<f64>, usize)> = vec![];
+    let mut used = vec![false; n];
+    for i in 0..n {
+        if used[i] {
+            continue;
+        }
+        let members: Vec<usize> = (i..n).filter(|j| !used[*j] && (eigs[*j] - eigs[i]).modulus() <= tol).collect();
+        let mean = members.iter().fold(Complex::new(0.0, 0.0), |acc, j| acc + eigs[*j]) / members.len() as f64;
+        for j in members.iter() {
+            used[*j] = true;
+        }
+        clusters.push((mean, members.len()));
+    }
+
+    let mut cols: Vec<DVector<f64>> = vec![];
+    let mut diagonal = true;
+    for (lambda, k) in clusters.iter() {
+        if lambda.im.abs() > tol {
+            if lambda.im < 0.0 { // 共役の片方だけから実数の2列を作る
+                continue;
+            }
+            if *k > 1 {
+                return Err("重複した複素固有値のジョルダン形には対応していません。");
+            }
+
+            // (A - λI) の零空間（最小特異値の右特異ベクトル）
+            let m = a.map(|v| Complex::new(v, 0.0)) - DMatrix::<Complex<f64>>::from_diagonal_element(n, n, *lambda);
+            let svd = m.svd(false, true);
+            let v = svd.v_t.unwrap().row(n - 1).adjoint();
+            cols.push(v.map(|c| c.re));
+            cols.push(v.map(|c| c.im));
+            continue;
+        }
+
+        let nmat = a - DMatrix::<f64>::from_diagonal_element(n, n, lambda.re);
+        let geo = n - matrix_rank(&nmat, tol);
+        if geo >= *k { // 対角化できる：零空間の基底をそのまま使う
+            let v_t = nmat.svd(false, true).v_t.unwrap();
+            for r in 0..*k {
+                cols.push(v_t.row(n - 1 - r).transpose());
+            }
+            continue;
+        }
+        if geo != 1 {
+            return Err("複数のジョルダンブロックを持つ固有値には対応していません。");
+        }
+
+        // N^k の零空間から N^(k-1) v が最大になる v を選び、v, Nv, ..., N^(k-1)v の鎖を作る
+        let mut npow = DMatrix::<f64>::identity(n, n);
+        for _ in 0..(*k - 1) {
+            npow = &npow * &nmat;
+        }
+        let v_t = (&npow * &nmat).svd(false, true).v_t.unwrap();
+        let top = (0..*k)
+            .map(|r| v_t.row(n - 1 - r).transpose())
+            .max_by(|p, q| (&npow * p).norm().partial_cmp(&(&npow * q).norm()).unwrap())
+            .unwrap();
+
+        let mut chain = vec![top];
+        for _ in 1..*k {
+            let next = &nmat * chain.last().unwrap();
+            chain.push(next);
+        }
+        chain.reverse(); // 固有ベクトルを先頭にすると上側に1が並ぶ
+        cols.extend(chain);
+        diagonal = false;
+    }
+
+    let t = DMatrix::from_columns(&cols);
+    if matrix_rank(&t, 1e-12 * t.norm()) < n {
+        return Err("変換行列が正則になりませんでした。");
+    }
+    Ok((t, diagonal))
+}
+
+impl SpaceStateModel {
+    /* 伝達関数行列 G(s) = C (sI - A)^(-1) B + D
+       分子は num_ij = det(sI - A + B_j C_i) - det(sI - A) + D_ij det(sI - A) で求める */
+    pub fn ss2tf(&self) -> TfMatrix {
+        let (a, b, c, d) = (self.get_mat_a(), self.get_mat_b(), self.get_mat_c(), self.get_mat_d());
+        let charpoly = |m: &DMatrix<f64>| poly_from_roots(m.clone().complex_eigenvalues().as_slice());
+        let den = charpoly(a);
+
+        let num = (0..c.nrows()).map(|i| {
+            (0..b.ncols()).map(|j| {
+                let pj = charpoly(&(a - b.column(j) * c.row(i)));
+                let mut nij: Vec<f64> = pj.iter().zip(den.iter())
+                    .map(|(p, q)| p - q + d[(i, j)] * q)
+                    .collect();
+
+                // 丸め誤差で残った小さな係数を0にする
+                let scale = nij.iter().fold(0.0f64, |m, v| m.max(v.abs()));
+                for v in nij.iter_mut() {
+                    if v.abs() <= 1e-10 * scale {
+                        *v = 0.0;
+                    }
+                }
+                nij
+            }).collect()
+        }).collect();
+
+        TfMatrix { num: num, den: den }
+    }
+
+    /* 可制御正準形 （A の最下行が -a0 ... -a(n-1)、B = [0 ... 0 1]^T） */
+    pub fn ctrb_canonical(num: &[f64], den: &[f64]) -> Result<Self, &'static str> {
+        let (num, den) = normalize_tf(num, den)?;
+        let n = den.len() - 1;
+
+        let mut a = DMatrix::<f64>::zeros(n, n);
+        for r in 0..n - 1 {
+            a[(r, r + 1)] = 1.0;
+        }
+        for col in 0..n {
+            a[(n - 1, col)] = -den[n - col];
+        }
+        let mut b = DMatrix::<f64>::zeros(n, 1);
+        b[(n - 1, 0)] = 1.0;
+        let c = DMatrix::from_fn(1, n, |_, col| num[n - col] - num[0] * den[n - col]);
+        let d = DMatrix::from_element(1, 1, num[0]);
+
+        SpaceStateModel::from_matrices(a, b, c, d)
+    }
+
+    /* 可観測正準形 （可制御正準形の双対、C = [0 ... 0 1]） */
+    pub fn obsv_canonical(num: &[f64], den: &[f64]) -> Result<Self, &'static str> {
+        let ctrb = SpaceStateModel::ctrb_canonical(num, den)?;
+        SpaceStateModel::from_matrices(
+            ctrb.get_mat_a().transpose(),
+            ctrb.get_mat_c().transpose(),
+            ctrb.get_mat_b().transpose(),
+            ctrb.get_mat_d().clone())
+    }
+
+    /* 座標変換 x = T z をしたモデル （A' = T^(-1) A T, B' = T^(-1) B, C' = C T, D' = D）
+       現在の状態も z = T^(-1) x に変換し、入力は引き継ぐ */
+    pub fn similarity_transform(&self, t: &DMatrix<f64>) -> Result<Self, &'static str> {
+        let n = self.get_mat_a().nrows();
+        if t.shape() != (n, n) {
+            return Err("変換行列のサイズが違います。");
+        }
+        let tinv = t.clone().try_inverse().ok_or("変換行列が正則ではありません。")?;
+
+        let mut model = SpaceStateModel::from_matrices(
+            &tinv * self.get_mat_a() * t,
+            &tinv * self.get_mat_b(),
+            self.get_mat_c() * t,
+            self.get_mat_d().clone())?;
+        model.set_x((&tinv * self.get_state()).as_slice()).unwrap();
+        model.set_u(self.get_input().as_slice()).unwrap();
+        Ok(model)
+    }
+
+    /* 可制御正準形に変換したモデルと変換行列 T （1入力の可制御なモデルのみ） */
+    pub fn to_ctrb_canonical(&self) -> Result<(Self, DMatrix<f64>), &'static str> {
+        if self.get_mat_b().ncols() != 1 {
+            return Err("正準形への変換は1入力のモデルのみ対応しています。");
+        }
+        let a = self.get_mat_a();
+        let den = poly_from_roots(a.clone().complex_eigenvalues().as_slice());
+
+        let t = self.ctrb_matrix() * hankel_from_den(&den);
+        let model = self.similarity_transform(&t).map_err(|_| "可制御でないため正準形に変換できません。")?;
+        Ok((model, t))
+    }
+
+    /* 可観測正準形に変換したモデルと変換行列 T （1出力の可観測なモデルのみ） */
+    pub fn to_obsv_canonical(&self) -> Result<(Self, DMatrix<f64>), &'static str> {
+        if self.get_mat_c().nrows() != 1 {
+            return Err("正準形への変換は1出力のモデルのみ対応しています。");
+        }
+        let a = self.get_mat_a();
+        let den = poly_from_roots(a.clone().complex_eigenvalues().as_slice());
+
+        let t = (hankel_from_den(&den) * self.obsv_matrix()).try_inverse().ok_or("可観測でないため正準形に変換できません。")?;
+        let model = self.similarity_transform(&t)?;
+        Ok((model, t))
+    }
+
+    /* モード形（対角形）に変換したモデルと変換行列 T
+       実固有値は対角に、複素固有値 σ±jω は 2x2 のブロック [σ ω; -ω σ] に並べる（対角化できるモデルのみ） */
+    pub fn to_modal(&self, tol: f64) -> Result<(Self, DMatrix<f64>), &'static str> {
+        let (t, diagonal) = real_jordan_basis(self.get_mat_a(), tol)?;
+        if !diagonal {
+            return Err("対角化できないモデルです。（ジョルダン形を使ってください）");
+        }
+        let model = self.similarity_transform(&t)?;
+        Ok((model, t))
+    }
+
+    /* 実ジョルダン形に変換したモデルと変換行列 T （tol以内の固有値を重複とみなす） */
+    pub fn to_jordan(&self, tol: f64) -> Result<(Self, DMatrix<f64>), &'static str> {
+        let (t, _) = real_jordan_basis(self.get_mat_a(), tol)?;
+        let model = self.similarity_transform(&t)?;
+        Ok((model, t))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &[f64], b: &[f64]) {
+        assert_eq!(a.len(), b.len(), "{:?} != {:?}", a, b);
+        assert!(a.iter().zip(b.iter()).all(|(x, y)| (x - y).abs() < 1e-9), "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn ss2tf_recovers_transfer_function() {
+        // G(s) = (2s^2 + s + 3) / (s^3 + 4s^2 + 5s + 2)
+        let (num, den) = ([2.0, 1.0, 3.0], [1.0, 4.0, 5.0, 2.0]);
+        for model in [SpaceStateModel::ctrb_canonical(&num, &den).unwrap(), SpaceStateModel::obsv_canonical(&num, &den).unwrap()] {
+            let tf = model.ss2tf();
+            assert_close(&tf.den, &den);
+            assert_close(&tf.num[0][0], &[0.0, 2.0, 1.0, 3.0]);
+        }
+    }
+
+    #[test]
+    fn canonical_transforms_keep_transfer_function() {
+        let mut model = SpaceStateModel::new(2, 1, 1);
+        model.set_mat_a(&[-1.0, 2.0, 0.5, -3.0]).unwrap();
+        model.set_mat_b(&[1.0, 1.0]).unwrap();
+        model.set_mat_c(&[2.0, -1.0]).unwrap();
+        let expected = model.ss2tf();
+
+        let (ctrb, _) = model.to_ctrb_canonical().unwrap();
+        let (obsv, _) = model.to_obsv_canonical().unwrap();
+        let (modal, t) = model.to_modal(1e-9).unwrap();
+        for converted in [&ctrb, &obsv, &modal] {
+            let tf = converted.ss2tf();
+            assert_close(&tf.den, &expected.den);
+            assert_close(&tf.num[0][0], &expected.num[0][0]);
+        }
+
+        // 可制御正準形の B は [0, 1]^T、モード形の A は対角
+        assert_close(ctrb.get_mat_b().as_slice(), &[0.0, 1.0]);
+        assert!(modal.get_mat_a()[(0, 1)].abs() < 1e-9 && modal.get_mat_a()[(1, 0)].abs() < 1e-9);
+        assert!((&t * modal.get_mat_a() * t.clone().try_inverse().unwrap() - model.get_mat_a()).amax() < 1e-9);
+    }
+
+    #[test]
+    fn modal_rejects_defective_matrix() {
+        let mut model = SpaceStateModel::new(2, 1, 1);
+        model.set_mat_a(&[-1.0, 1.0, 0.0, -1.0]).unwrap();
+        model.set_mat_b(&[0.0, 1.0]).unwrap();
+        model.set_mat_c(&[1.0, 0.0]).unwrap();
+        assert!(model.to_modal(1e-6).is_err());
+
+        let (jordan, _) = model.to_jordan(1e-6).unwrap();
+        let a = jordan.get_mat_a();
+        assert!((a[(0, 0)] + 1.0).abs() < 1e-9 && (a[(1, 1)] + 1.0).abs() < 1e-9 && a[(1, 0)].abs() < 1e-9);
+    }
+}
@@ -0,0 +1,274 @@
+/* むだ時間 */
+// 入力の履歴を補間して y(t) = u(t - T) を出力するむだ時間ブロックと、e^(-sT) のパデ近似
+
+use std::collections::VecDeque;
+
+extern crate nalgebra as na;
+use na::DMatrix;
+
+use super::simmodel::{*};
+
+/* むだ時間ブロック y(t) = u(t - delay)（t < delay では初期値を出力する）
+   確定した入力を (時刻, 値) の履歴として持ち、履歴の間は線形補間する
+   状態を持たず（0次元）、出力は引数の時刻tで履歴を引く
+   履歴はステップの始めと終わりの時刻に update_discrete で記録されるので、SignalGraph や CompositeModel の中でも、可変刻みのソルバでも同じように動く
+   刻み幅より短い遅れは最後に記録した入力を保持した値になる */
+#[derive(Debug, Clone)]
+pub struct TransportDelay {
+    delay: f64,                     // むだ時間
+    init_output: f64,               // 時刻 delay までの出力
+    history: VecDeque<(f64, f64)>,  // 確定した (時刻, 入力) の履歴（時刻の昇順）
+    x: DMatrix<f64>,                // 空の状態
+    t: f64,                         // 最後に履歴を記録した時刻
+    u: f64,
+}
+
+impl TransportDelay {
+    pub fn new(delay: f64, init_output: f64) -> Result<Self, &'static str> {
+        if delay < 0.0 {
+            return Err("むだ時間は0以上にしてください。");
+        }
+        Ok(Self {
+            delay: delay,
+            init_output: init_output,
+            history: VecDeque::new(),
+            x: DMatrix::zeros(0, 1),
+            t: 0.0,
+            u: 0.0,
+        })
+    }
+
+    pub fn get_delay(&self) -> f64 {
+        self.delay
+    }
+
+    pub fn set_u(&mut self, u: f64) { // 履歴には次の update_discrete で記録する
+        self.u = u;
+    }
+
+    /* 最後に記録した時刻で入力を履歴に加え、もう使わない古い履歴を捨てる */
+    fn commit(&mut self) {
+        let t = self.t;
+        while let Some((tl, _)) = self.history.back() { // 時刻が戻った（状態を設定し直した）ときは先の履歴を捨てる
+            if *tl >= t {
+                self.history.pop_back();
+            } else {
+                break;
+            }
+        }
+        self.history.push_back((t, self.u));
+
+        // t - delay より前の点は補間に1点だけ残す
+        while self.history.len() > 2 && self.history[1].0 <= t - self.delay {
+            self.history.pop_front();
+        }
+    }
+
+    /* 時刻tでの出力 u(t - delay) */
+    fn delayed(&self, t: f64) -> f64 {
+        let td = t - self.delay;
+        let (t0, _) = match self.history.front() {
+            Some(p) => *p,
+            None => return self.init_output,
+        };
+        if td < t0 {
+            return self.init_output;
+        }
+
+        // td を挟む2点を二分探索で探して線形補間する（最後の点より後は最後の値を保持する）
+        let idx = self.history.partition_point(|(ti, _)| *ti <= td);
+        if idx >= self.history.len() {
+            return self.history.back().unwrap().1;
+        }
+        let (ta, ua) = self.history[idx - 1];
+        let (tb, ub) = self.history[idx];
+        ua + (ub - ua) * (td - ta) / (tb - ta)
+    }
+}
+
+impl Model for TransportDelay {
+    fn slopefunc(&self, _t: f64, _x: &DMatrix<f64>) -> DMatrix<f64> { // 状態を持たない
+        DMatrix::zeros(0, 1)
+    }
+
+    fn get_signals_info(&self) -> Vec<String> {
+        vec!["u".to_string(), "y".to_string()]
+    }
+
+    fn set_state(&mut self, newstate: DMatrix<f64>) {
+        self.x = newstate;
+    }
+
+    fn get_state(&self) -> &DMatrix<f64> {
+        &self.x
+    }
+
+    fn get_allsignals(&self) -> Vec<f64> {
+        vec![self.u, self.outputfunc(self.t, &self.x, &self.get_input())[0]]
+    }
+
+    fn calc_nextstate(&mut self, t: f64, delta_t : f64, _solvertype: &SolverType) { // 入力は区間の間一定とみなして、始めと終わりの時刻に記録する
+        self.update_discrete(t, 0.0);
+        self.update_discrete(t, delta_t);
+    }
+
+    /* 時刻 t + delta_t に現在の入力を履歴に記録する（同じ時刻の記録があれば置き換える） */
+    fn update_discrete(&mut self, t: f64, delta_t: f64) {
+        self.t = t + delta_t;
+        self.commit();
+    }
+
+    fn get_input_dim(&self) -> usize {
+        1
+    }
+
+    fn get_input(&self) -> DMatrix<f64> {
+        DMatrix::from_element(1, 1, self.u)
+    }
+
+    fn set_input(&mut self, u: &[f64]) {
+        self.set_u(u[0]);
+    }
+
+    fn slopefunc_u(&self, t: f64, x: &DMatrix<f64>, _u: &DMatrix<f64>) -> DMatrix<f64> {
+        self.slopefunc(t, x)
+    }
+
+    fn get_output_dim(&self) -> usize {
+        1
+    }
+
+    fn outputfunc(&self, t: f64, _x: &DMatrix<f64>, u: &DMatrix<f64>) -> DMatrix<f64> {
+        let y = if self.delay == 0.0 { u[0] } else { self.delayed(t) };
+        DMatrix::from_element(1, 1, y)
+    }
+
+    fn has_feedthrough(&self) -> bool { // 遅れがあれば出力は過去の入力だけで決まる
+        self.delay == 0.0
+    }
+
+    fn get_input_names(&self) -> Vec<String> {
+        vec!["u".to_string()]
+    }
+
+    fn get_output_names(&self) -> Vec<String> {
+        vec!["y".to_string()]
+    }
+}
+
+/* e^(-sT) の order 次のパデ近似 N(s) / D(s)
+   戻り値は TransFuncModel::new にそのまま渡せる (分子, 分母) の係数（降べきの順、分母の最高次の係数は1） */
+pub fn pade(delay: f64, order: usize) -> Result<(Vec<f64>, Vec<f64>), &'static str> {
+    if delay <= 0.0 {
+        return Err("むだ時間は正の値にしてください。");
+    }
+    if order == 0 {
+        return Err("近似の次数は1以上にしてください。");
+    }
+
+    // D(s) = Σ c_k (sT)^k, c_k = (2n-k)! n! / ((2n)! k! (n-k)!)、N(s) = D(-s)
+    let n = order;
+    let mut c = vec![1.0];
+    for k in 0..n {
+        let next = c[k] * (n - k) as f64 / ((2 * n - k) * (k + 1)) as f64;
+        c.push(next);
+    }
+
+    let lead = c[n] * delay.powi(n as i32);
+    let den = (0..=n).rev().map(|k| c[k] * delay.powi(k as i32) / lead).collect::<Vec<f64>>();
+    let num = (0..=n).rev().map(|k| if k % 2 == 0 { 1.0 } else { -1.0 } * c[k] * delay.powi(k as i32) / lead).collect::<Vec<f64>>();
+    Ok((num, den))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::simgraph::{*};
+
+    fn assert_close(a: &[f64], b: &[f64]) {
+        assert_eq!(a.len(), b.len());
+        assert!(a.iter().zip(b.iter()).all(|(x, y)| (x - y).abs() < 1e-12), "{:?} {:?}", a, b);
+    }
+
+    #[test]
+    fn pade_coefficients() {
+        // 1次: (-s + 2/T) / (s + 2/T)、2次: (s^2 - 6s/T + 12/T^2) / (s^2 + 6s/T + 12/T^2)
+        let delay = 0.5;
+        let (num, den) = pade(delay, 1).unwrap();
+        assert_close(&num, &[-1.0, 4.0]);
+        assert_close(&den, &[1.0, 4.0]);
+
+        let (num, den) = pade(delay, 2).unwrap();
+        assert_close(&num, &[1.0, -12.0, 48.0]);
+        assert_close(&den, &[1.0, 12.0, 48.0]);
+
+        // 直流ゲインは1
+        let (num, den) = pade(delay, 5).unwrap();
+        assert!((num[5] / den[5] - 1.0).abs() < 1e-12);
+
+        assert!(pade(0.0, 1).is_err());
+        assert!(pade(delay, 0).is_err());
+    }
+
+    #[test]
+    fn delay_outputs_past_input() {
+        // 入力 u = t を与え続けると、時刻 delay 以降は y = t - delay
+        let (delay, delta_t) = (0.1, 0.01);
+        let mut block = TransportDelay::new(delay, -1.0).unwrap();
+        block.set_input(&[0.0]);
+        for i in 0..50 {
+            let t = i as f64 * delta_t;
+            let y = block.get_allsignals()[1];
+            if t < delay - 1e-9 {
+                assert_eq!(y, -1.0);
+            } else if t > delay + 1e-9 { // 時刻 delay ちょうどは時刻の丸め誤差でどちらにもなりうる
+                assert!((y - (t - delay)).abs() < 1e-9);
+            }
+            block.calc_nextstate(t, delta_t, &SolverType::RungeKutta);
+            block.set_input(&[t + delta_t]);
+        }
+        assert!(!block.has_feedthrough());
+    }
+
+    #[test]
+    fn output_uses_time_argument() {
+        // 状態を持たず、引数の時刻tで履歴を引く
+        let mut block = TransportDelay::new(0.1, 0.0).unwrap();
+        assert_eq!(block.get_state().nrows(), 0);
+        block.set_input(&[1.0]);
+        for i in 0..20 {
+            block.calc_nextstate(i as f64 * 0.01, 0.01, &SolverType::Euler);
+        }
+        let (x, u) = (block.get_state().clone(), block.get_input());
+        assert_eq!(block.outputfunc(0.05, &x, &u)[0], 0.0);
+        assert_eq!(block.outputfunc(0.2, &x, &u)[0], 1.0);
+    }
+
+    #[test]
+    fn delay_in_graph_with_dormand_prince() {
+        // r → むだ時間 0.2 → 1/(s+1) → y に単位ステップを入れると y = 1 - e^-(t-0.2)
+        let mut lag = SpaceStateModel::new(1, 1, 1);
+        lag.set_mat_a(&[-1.0]).unwrap();
+        lag.set_mat_b(&[1.0]).unwrap();
+        lag.set_mat_c(&[1.0]).unwrap();
+        let mut builder = SignalGraphBuilder::new();
+        builder.add_input("r").unwrap();
+        builder.add_block("delay", Box::new(TransportDelay::new(0.2, 0.0).unwrap())).unwrap();
+        builder.add_block("G", Box::new(lag)).unwrap();
+        builder.connect("r", "delay.u").unwrap();
+        builder.connect("delay.y", "G.u_0").unwrap();
+        builder.add_output("y", "G.y_0").unwrap();
+        let mut graph = builder.build().unwrap();
+
+        let solver = SolverType::dormand_prince(1e-8, 1e-10, 1e-6, 0.01).unwrap();
+        graph.set_input(&[1.0]);
+        for i in 0..100 {
+            let t = i as f64 * 0.01;
+            graph.calc_nextstate(t, 0.01, &solver);
+            let y = graph.outputfunc(t + 0.01, graph.get_state(), &graph.get_input())[0];
+            let expected = if t + 0.01 <= 0.2 { 0.0 } else { 1.0 - (-(t + 0.01 - 0.2)).exp() };
+            assert!((y - expected).abs() < 1e-6, "t = {}: {} != {}", t + 0.01, y, expected);
+        }
+        assert_eq!(graph.get_state().nrows(), 1); // むだ時間は状態を増やさない
+    }
+}
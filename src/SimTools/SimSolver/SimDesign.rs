@@ -0,0 +1,459 @@
+/* 状態フィードバック制御器の設計 */
+// 極配置・最適レギュレータ (LQR) で u = -K x のゲインKを求める
+
+extern crate nalgebra as na;
+use na::{DMatrix, Complex, ComplexField};
+
+use super::simmodel::{*};
+use super::simanalysis::{*};
+
+const KNV_MAXITER: usize = 30;  // KNV法の最大反復回数
+const KNV_TOL: f64 = 1e-6;      // KNV法の収束判定（固有ベクトルの変化量）
+const RICCATI_MAXITER: usize = 100; // リカッチ方程式の反復解法の最大反復回数
+const RICCATI_TOL: f64 = 1e-12;     // リカッチ方程式の反復解法の収束判定
+
+/* LQRの設計結果 */
+#[derive(Debug, Clone)]
+pub struct LqrResult {
+    pub k: DMatrix<f64>,            // フィードバックゲイン u = -K x
+    pub p: DMatrix<f64>,            // リカッチ方程式の解
+    pub poles: Vec<Complex<f64>>,   // 閉ループ極 (A - BK の固有値)
+}
+
+/* 根から実係数の多項式を作る（最高次の係数が1、降べきの順） */
+pub fn poly_from_roots(roots: &[Complex<f64>]) -> Vec<f64> {
+    let mut coef = vec![Complex::new(1.0, 0.0)];
+    for r in roots.iter() {
+        let mut next = vec![Complex::new(0.0, 0.0); coef.len() + 1];
+        for (i, c) in coef.iter().enumerate() {
+            next[i] += c;
+            next[i + 1] -= c * r;
+        }
+        coef = next;
+    }
+    coef.iter().map(|c| c.re).collect()
+}
+
+/* 複素数の極が共役な組になっているか確認し、各極の共役の相手の番号を返す（実数の極は自分自身） */
+fn conjugate_pairs(poles: &[Complex<f64>]) -> Result<Vec<usize>, &'static str> {
+    let mut partner = vec![usize::MAX; poles.len()];
+    for i in 0..poles.len() {
+        if partner[i] != usize::MAX {
+            continue;
+        }
+        if poles[i].im == 0.0 {
+            partner[i] = i;
+            continue;
+        }
+
+        let scale = poles[i].modulus().max(1.0);
+        let j = (i + 1..poles.len())
+            .find(|j| partner[*j] == usize::MAX && (poles[*j] - poles[i].conj()).modulus() <= 1e-9 * scale)
+            .ok_or("複素数の極は共役な組で指定してください。")?;
+        partner[i] = j;
+        partner[j] = i;
+    }
+    Ok(partner)
+}
+
+/* 行列の列空間の直交補空間の正規直交基底 （m: n x k、列フルランクを仮定） */
+fn orth_complement(m: &DMatrix<Complex<f64>>) -> DMatrix<Complex<f64>> {
+    let n = m.nrows();
+    let k = m.ncols();
+    let mut aug = DMatrix::<Complex<f64>>::zeros(n, k + n);
+    aug.slice_mut((0, 0), (n, k)).copy_from(m);
+    aug.slice_mut((0, k), (n, n)).fill_with_identity();
+    let q = aug.qr().q();
+    q.columns(k, n - k).into_owned()
+}
+
+impl SpaceStateModel {
+    /* アッカーマンの方法による極配置（1入力のみ） */
+    pub fn acker(&self, poles: &[Complex<f64>]) -> Result<DMatrix<f64>, &'static str> {
+        let a = self.get_mat_a();
+        let n = a.nrows();
+
+        if self.get_mat_b().ncols() != 1 {
+            return Err("アッカーマンの方法は1入力のモデルのみ対応しています。");
+        }
+        if poles.len() != n {
+            return Err("極の数が状態の次数と違います。");
+        }
+        conjugate_pairs(poles)?;
+        self.check_placeable()?;
+
+        // 目標の特性多項式 φ(A) = A^n + c1 A^(n-1) + ... + cn I をホーナー法で計算
+        let coef = poly_from_roots(poles);
+        let mut phi = DMatrix::<f64>::zeros(n, n);
+        for c in coef.iter() {
+            phi = &phi * a + DMatrix::<f64>::identity(n, n) * *c;
+        }
+
+        // K = [0 ... 0 1] Uc^(-1) φ(A)
+        let mut en = DMatrix::<f64>::zeros(n, 1);
+        en[n - 1] = 1.0;
+        let w = self.ctrb_matrix().transpose().lu().solve(&en).ok_or("可制御性行列が特異です。")?;
+        Ok(w.transpose() * phi)
+    }
+
+    /* 極配置 A - BK の固有値をpolesにするゲインKを返す
+       1入力はアッカーマンの方法、多入力はKautsky-Nichols-Van Dooren法 (KNV0) で固有ベクトルの条件数が小さくなるように選ぶ */
+    pub fn place(&self, poles: &[Complex<f64>]) -> Result<DMatrix<f64>, &'static str> {
+        let a = self.get_mat_a();
+        let b = self.get_mat_b();
+        let n = a.nrows();
+        let m = b.ncols();
+
+        if m == 1 {
+            return self.acker(poles);
+        }
+        if poles.len() != n {
+            return Err("極の数が状態の次数と違います。");
+        }
+        let partner = conjugate_pairs(poles)?;
+        self.check_placeable()?;
+        if matrix_rank(b, 1e-12 * b.norm()) < m {
+            return Err("B行列の列が一次独立ではありません。");
+        }
+        for p in poles.iter() {
+            if poles.iter().filter(|q| (*q - p).modulus() <= 1e-9 * p.modulus().max(1.0)).count() > m {
+                return Err("入力の数より多い重複極は配置できません。");
+            }
+        }
+
+        // B = [U0 U1] [Z; 0] と分解する
+        let ac = a.map(|e| Complex::new(e, 0.0));
+        let bc = b.map(|e| Complex::new(e, 0.0));
+        let mut aug = DMatrix::<f64>::zeros(n, m + n);
+        aug.slice_mut((0, 0), (n, m)).copy_from(b);
+        aug.slice_mut((0, m), (n, n)).fill_with_identity();
+        let qr = aug.qr();
+        let q = qr.q().map(|e| Complex::new(e, 0.0));
+        let u0 = q.columns(0, m).into_owned();
+        let u1 = q.columns(m, n - m).into_owned();
+        let z = u0.adjoint() * &bc;
+
+        // 各極の固有ベクトルが取りうる空間 S_j = ker(U1^T (A - λ_j I)) の正規直交基底
+        let eye = DMatrix::<Complex<f64>>::identity(n, n);
+        let spaces = poles.iter()
+            .map(|p| orth_complement(&(u1.adjoint() * (&ac - &eye * *p)).adjoint()))
+            .collect::<Vec<DMatrix<Complex<f64>>>>();
+
+        // 初期値は各空間の基底の和
+        let mut x = DMatrix::<Complex<f64>>::zeros(n, n);
+        for (j, s) in spaces.iter().enumerate() {
+            let v = s * DMatrix::from_element(s.ncols(), 1, Complex::new(1.0, 0.0));
+            x.set_column(j, &(&v / Complex::new(v.norm(), 0.0)).column(0));
+        }
+        for j in 0..n {
+            if partner[j] != j && poles[j].im < 0.0 {
+                let v = x.column(partner[j]).map(|e| e.conj());
+                x.set_column(j, &v);
+            }
+        }
+
+        // KNV0: 他の列と直交する方向へ各列を順に更新する（複素数の極は共役な組をまとめて更新）
+        if n > 1 {
+            for _ in 0..KNV_MAXITER {
+                let mut change: f64 = 0.0;
+                for j in 0..n {
+                    if partner[j] != j && poles[j].im < 0.0 {
+                        continue;
+                    }
+
+                    let mut others = DMatrix::<Complex<f64>>::zeros(n, n - 1);
+                    let mut c = 0;
+                    for k in (0..n).filter(|k| *k != j) {
+                        others.set_column(c, &x.column(k));
+                        c += 1;
+                    }
+                    let y = orth_complement(&others);
+                    let s = &spaces[j];
+                    let v = s * (s.adjoint() * &y);
+                    if v.norm() < 1e-12 {
+                        continue;
+                    }
+                    let v = &v / Complex::new(v.norm(), 0.0);
+
+                    // 位相の違いを除いて変化量を測る
+                    let ph = (v.adjoint() * x.column(j))[0];
+                    let ph = if ph.modulus() > 0.0 { ph / ph.modulus() } else { Complex::new(1.0, 0.0) };
+                    change = change.max((&v * ph - x.column(j)).norm());
+
+                    x.set_column(j, &v.column(0));
+                    if partner[j] != j {
+                        x.set_column(partner[j], &v.map(|e| e.conj()).column(0));
+                    }
+                }
+                if change < KNV_TOL {
+                    break;
+                }
+            }
+        }
+
+        // A - BK = X Λ X^(-1) から K = Z^(-1) U0^T (A - X Λ X^(-1))
+        let xinv = x.clone().try_inverse().ok_or("固有ベクトルが一次独立になりませんでした。")?;
+        let lambda = DMatrix::from_diagonal(&na::DVector::from_column_slice(poles));
+        let acl = &x * lambda * xinv;
+        let zinv = z.try_inverse().ok_or("B行列の列が一次独立ではありません。")?;
+        let k = zinv * u0.adjoint() * (ac - acl);
+        Ok(k.map(|e| e.re))
+    }
+
+    fn check_placeable(&self) -> Result<(), &'static str> {
+        let uc = self.ctrb_matrix();
+        let tol = uc.nrows().max(uc.ncols()) as f64 * f64::EPSILON * uc.norm();
+        if matrix_rank(&uc, tol) < self.get_mat_a().nrows() {
+            return Err("可制御でないため極配置できません。");
+        }
+        Ok(())
+    }
+}
+
+/* Q, R, N のサイズと R の正定値性を確認し、N が無ければ0行列を返す */
+fn check_weights(a: &DMatrix<f64>, b: &DMatrix<f64>, q: &DMatrix<f64>, r: &DMatrix<f64>, n: Option<&DMatrix<f64>>) -> Result<DMatrix<f64>, &'static str> {
+    let sdim = a.nrows();
+    let idim = b.ncols();
+
+    if q.shape() != (sdim, sdim) {
+        return Err("Q行列のサイズが違います。");
+    }
+    if r.shape() != (idim, idim) {
+        return Err("R行列のサイズが違います。");
+    }
+    if r.clone().cholesky().is_none() {
+        return Err("R行列が正定値ではありません。");
+    }
+
+    match n {
+        Some(n) => {
+            if n.shape() != (sdim, idim) {
+                return Err("N行列のサイズが違います。");
+            }
+            Ok(n.clone())
+        },
+        None => Ok(DMatrix::<f64>::zeros(sdim, idim)),
+    }
+}
+
+/* 連続時間代数リカッチ方程式 A^T P + P A - (P B + N) R^(-1) (B^T P + N^T) + Q = 0 の安定化解を求める
+   ハミルトン行列の符号関数をニュートン反復で求め、安定な不変部分空間から P を取り出す */
+pub fn solve_care(a: &DMatrix<f64>, b: &DMatrix<f64>, q: &DMatrix<f64>, r: &DMatrix<f64>, n: Option<&DMatrix<f64>>) -> Result<DMatrix<f64>, &'static str> {
+    let nmat = check_weights(a, b, q, r, n)?;
+    let sdim = a.nrows();
+    let rinv = r.clone().try_inverse().ok_or("R行列が正則ではありません。")?;
+
+    // 交差項Nを消去した問題に直す
+    let a2 = a - b * &rinv * nmat.transpose();
+    let q2 = q - &nmat * &rinv * nmat.transpose();
+    let g = b * &rinv * b.transpose();
+
+    let mut h = DMatrix::<f64>::zeros(2 * sdim, 2 * sdim);
+    h.slice_mut((0, 0), (sdim, sdim)).copy_from(&a2);
+    h.slice_mut((0, sdim), (sdim, sdim)).copy_from(&(-&g));
+    h.slice_mut((sdim, 0), (sdim, sdim)).copy_from(&(-&q2));
+    h.slice_mut((sdim, sdim), (sdim, sdim)).copy_from(&(-a2.transpose()));
+
+    // 行列符号関数 sign(H)（行列式でスケーリングしたニュートン反復）
+    let mut z = h;
+    let mut converged = false;
+    for _ in 0..RICCATI_MAXITER {
+        let zinv = z.clone().try_inverse().ok_or("ハミルトン行列が虚軸上に固有値を持つため解けません。")?;
+        let c = z.determinant().abs().powf(-1.0 / (2.0 * sdim as f64));
+        let c = if c.is_finite() && c > 0.0 { c } else { 1.0 };
+        let znew = (&z * c + zinv / c) / 2.0;
+        let diff = (&znew - &z).norm();
+        z = znew;
+        if diff <= RICCATI_TOL * z.norm() {
+            converged = true;
+            break;
+        }
+    }
+    if !converged {
+        return Err("リカッチ方程式の反復が収束しませんでした。");
+    }
+
+    // (sign(H) + I) [I; P] = 0 を最小二乗で解く
+    let eye = DMatrix::<f64>::identity(sdim, sdim);
+    let mut lhs = DMatrix::<f64>::zeros(2 * sdim, sdim);
+    lhs.slice_mut((0, 0), (sdim, sdim)).copy_from(&z.slice((0, sdim), (sdim, sdim)));
+    lhs.slice_mut((sdim, 0), (sdim, sdim)).copy_from(&(z.slice((sdim, sdim), (sdim, sdim)) + &eye));
+    let mut rhs = DMatrix::<f64>::zeros(2 * sdim, sdim);
+    rhs.slice_mut((0, 0), (sdim, sdim)).copy_from(&(-(z.slice((0, 0), (sdim, sdim)) + &eye)));
+    rhs.slice_mut((sdim, 0), (sdim, sdim)).copy_from(&(-z.slice((sdim, 0), (sdim, sdim))));
+
+    let p = lhs.svd(true, true).solve(&rhs, 1e-14)?;
+    let p = (&p + p.transpose()) / 2.0;
+
+    // 残差で解を確認する
+    let pbn = &p * b + &nmat;
+    let res = a.transpose() * &p + &p * a - &pbn * &rinv * pbn.transpose() + q;
+    if res.norm() > 1e-6 * (1.0 + q.norm() + (a.transpose() * &p).norm()) {
+        return Err("リカッチ方程式の安定化解が見つかりません。（可安定・可検出か確認してください）");
+    }
+    Ok(p)
+}
+
+/* 離散時間代数リカッチ方程式
+   A^T P A - P - (A^T P B + N) (R + B^T P B)^(-1) (B^T P A + N^T) + Q = 0 の安定化解を求める
+   構造保存ダブリングアルゴリズム (SDA) で解く */
+pub fn solve_dare(a: &DMatrix<f64>, b: &DMatrix<f64>, q: &DMatrix<f64>, r: &DMatrix<f64>, n: Option<&DMatrix<f64>>) -> Result<DMatrix<f64>, &'static str> {
+    let nmat = check_weights(a, b, q, r, n)?;
+    let sdim = a.nrows();
+    let rinv = r.clone().try_inverse().ok_or("R行列が正則ではありません。")?;
+    let eye = DMatrix::<f64>::identity(sdim, sdim);
+
+    // 交差項Nを消去した問題 P = Ak^T P (I + G P)^(-1) Ak + Hk に直す
+    let mut ak = a - b * &rinv * nmat.transpose();
+    let mut gk = b * &rinv * b.transpose();
+    let mut hk = q - &nmat * &rinv * nmat.transpose();
+
+    let mut converged = false;
+    for _ in 0..RICCATI_MAXITER {
+        let w = (&eye + &gk * &hk).try_inverse().ok_or("リカッチ方程式の反復で特異行列が現れました。")?;
+        let a_next = &ak * &w * &ak;
+        let g_next = &gk + &ak * &w * &gk * ak.transpose();
+        let h_next = &hk + ak.transpose() * &hk * &w * &ak;
+
+        let diff = (&h_next - &hk).norm();
+        ak = a_next;
+        gk = g_next;
+        hk = h_next;
+        if diff <= RICCATI_TOL * hk.norm().max(1.0) {
+            converged = true;
+            break;
+        }
+    }
+    if !converged {
+        return Err("リカッチ方程式の反復が収束しませんでした。");
+    }
+
+    let p = (&hk + hk.transpose()) / 2.0;
+
+    // 残差で解を確認する
+    let apb = a.transpose() * &p * b + &nmat;
+    let s = r + b.transpose() * &p * b;
+    let sinv = s.try_inverse().ok_or("R + B^T P B が正則ではありません。")?;
+    let res = a.transpose() * &p * a - &p - &apb * sinv * apb.transpose() + q;
+    if res.norm() > 1e-6 * (1.0 + q.norm() + p.norm()) {
+        return Err("リカッチ方程式の安定化解が見つかりません。（可安定・可検出か確認してください）");
+    }
+    Ok(p)
+}
+
+impl SpaceStateModel {
+    /* 連続時間の最適レギュレータ
+       評価関数 J = ∫ (x^T Q x + u^T R u + 2 x^T N u) dt を最小にする u = -K x を求める */
+    pub fn lqr(&self, q: &DMatrix<f64>, r: &DMatrix<f64>, n: Option<&DMatrix<f64>>) -> Result<LqrResult, &'static str> {
+        let a = self.get_mat_a();
+        let b = self.get_mat_b();
+        let p = solve_care(a, b, q, r, n)?;
+
+        let nmat = n.cloned().unwrap_or_else(|| DMatrix::<f64>::zeros(a.nrows(), b.ncols()));
+        let rinv = r.clone().try_inverse().ok_or("R行列が正則ではありません。")?;
+        let k = rinv * (b.transpose() * &p + nmat.transpose());
+        let poles = (a - b * &k).complex_eigenvalues().iter().cloned().collect();
+
+        Ok(LqrResult { k: k, p: p, poles: poles })
+    }
+
+    /* 離散時間の最適レギュレータ（A, B を離散時間の行列 x[k+1] = A x[k] + B u[k] とみなす）
+       評価関数 J = Σ (x^T Q x + u^T R u + 2 x^T N u) を最小にする u = -K x を求める */
+    pub fn dlqr(&self, q: &DMatrix<f64>, r: &DMatrix<f64>, n: Option<&DMatrix<f64>>) -> Result<LqrResult, &'static str> {
+        let a = self.get_mat_a();
+        let b = self.get_mat_b();
+        let p = solve_dare(a, b, q, r, n)?;
+
+        let nmat = n.cloned().unwrap_or_else(|| DMatrix::<f64>::zeros(a.nrows(), b.ncols()));
+        let s = r + b.transpose() * &p * b;
+        let sinv = s.try_inverse().ok_or("R + B^T P B が正則ではありません。")?;
+        let k = sinv * (b.transpose() * &p * a + nmat.transpose());
+        let poles = (a - b * &k).complex_eigenvalues().iter().cloned().collect();
+
+        Ok(LqrResult { k: k, p: p, poles: poles })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(a: &[f64], b: &[f64], sdim: usize, idim: usize) -> SpaceStateModel {
+        let mut model = SpaceStateModel::new(sdim, idim, 1);
+        model.set_mat_a(a).unwrap();
+        model.set_mat_b(b).unwrap();
+        model
+    }
+
+    /* 求めた固有値が指定した極と一致するか（順不同） */
+    fn assert_poles(m: &DMatrix<f64>, poles: &[Complex<f64>]) {
+        let eig = m.clone().complex_eigenvalues();
+        for p in poles.iter() {
+            let dist = eig.iter().map(|e| (e - p).modulus()).fold(f64::INFINITY, f64::min);
+            assert!(dist < 1e-6, "{} not in {:?}", p, eig);
+        }
+    }
+
+    #[test]
+    fn place_gives_requested_poles() {
+        let poles = [Complex::new(-2.0, 1.0), Complex::new(-2.0, -1.0), Complex::new(-5.0, 0.0)];
+
+        // 1入力（アッカーマンの方法）
+        let siso = model(&[0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 1.0, -2.0, 3.0], &[0.0, 0.0, 1.0], 3, 1);
+        let k = siso.place(&poles).unwrap();
+        assert_poles(&(siso.get_mat_a() - siso.get_mat_b() * k), &poles);
+
+        // 多入力（KNV法）
+        let mimo = model(&[0.0, 1.0, 0.0, 2.0, 0.0, 1.0, 0.0, -1.0, 1.0], &[1.0, 0.0, 0.0, 0.0, 0.0, 1.0], 3, 2);
+        let k = mimo.place(&poles).unwrap();
+        assert_poles(&(mimo.get_mat_a() - mimo.get_mat_b() * k), &poles);
+    }
+
+    #[test]
+    fn place_rejects_invalid_poles() {
+        let siso = model(&[0.0, 1.0, -1.0, 0.0], &[0.0, 1.0], 2, 1);
+        assert!(siso.place(&[Complex::new(-1.0, 1.0), Complex::new(-2.0, 0.0)]).is_err()); // 共役でない
+        assert!(siso.place(&[Complex::new(-1.0, 0.0)]).is_err());
+
+        let uncontrollable = model(&[-1.0, 0.0, 0.0, -2.0], &[1.0, 0.0], 2, 1);
+        assert!(uncontrollable.place(&[Complex::new(-3.0, 0.0), Complex::new(-4.0, 0.0)]).is_err());
+    }
+
+    #[test]
+    fn care_and_dare_residuals_vanish() {
+        let a = DMatrix::from_row_slice(2, 2, &[0.0, 1.0, 2.0, -1.0]);
+        let b = DMatrix::from_row_slice(2, 1, &[0.0, 1.0]);
+        let q = DMatrix::from_row_slice(2, 2, &[2.0, 0.0, 0.0, 1.0]);
+        let r = DMatrix::from_element(1, 1, 0.5);
+        let n = DMatrix::from_row_slice(2, 1, &[0.1, 0.0]);
+
+        let p = solve_care(&a, &b, &q, &r, Some(&n)).unwrap();
+        let pbn = &p * &b + &n;
+        let res = a.transpose() * &p + &p * &a - &pbn * r.clone().try_inverse().unwrap() * pbn.transpose() + &q;
+        assert!(res.amax() < 1e-8);
+
+        let ad = DMatrix::from_row_slice(2, 2, &[1.0, 0.1, 0.2, 0.9]);
+        let bd = DMatrix::from_row_slice(2, 1, &[0.0, 0.1]);
+        let p = solve_dare(&ad, &bd, &q, &r, None).unwrap();
+        let apb = ad.transpose() * &p * &bd;
+        let s = &r + bd.transpose() * &p * &bd;
+        let res = ad.transpose() * &p * &ad - &p - &apb * s.try_inverse().unwrap() * apb.transpose() + &q;
+        assert!(res.amax() < 1e-8);
+    }
+
+    #[test]
+    fn lqr_stabilizes_unstable_plant() {
+        let eye = DMatrix::<f64>::identity(2, 2);
+        let r = DMatrix::from_element(1, 1, 1.0);
+
+        // 連続時間：A の固有値 ±√9.8
+        let plant = model(&[0.0, 1.0, 9.8, 0.0], &[0.0, 1.0], 2, 1);
+        let result = plant.lqr(&eye, &r, None).unwrap();
+        assert!(result.poles.iter().all(|p| p.re < 0.0));
+
+        // 離散時間：A の固有値 1.1 と 0.9
+        let plant = model(&[1.1, 0.1, 0.0, 0.9], &[0.0, 0.1], 2, 1);
+        let result = plant.dlqr(&eye, &r, None).unwrap();
+        assert!(result.poles.iter().all(|p| p.modulus() < 1.0));
+    }
+}
@@ -0,0 +1,563 @@
+/* 離散時間モデル */
+// 連続時間モデルの離散化 (c2d) と逆変換 (d2c)、サンプル時刻にだけ状態を更新する離散時間モデル
+
+use std::f64::consts::PI;
+
+extern crate nalgebra as na;
+use na::{DMatrix, Complex, ComplexField};
+
+use super::simmodel::{*};
+use super::simdesign::{poly_from_roots};
+
+pub const TICK_EPS: f64 = 1e-9; // サンプル時刻の判定の余裕（サンプル周期に対する割合）
+const LOGM_MAXITER: usize = 60; // 行列対数の平方根・反復の最大回数
+
+/* 離散化の方法 */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiscretizeMethod {
+    Zoh,                            // 0次ホールド（入力をサンプル間で一定とする）
+    Foh,                            // 1次ホールド（入力をサンプル間で直線補間する）
+    Tustin { prewarp: Option<f64> },// 双一次変換（prewarpに角周波数[rad/s]を与えるとその周波数で応答を一致させる）
+    Matched,                        // 極・零点マッチング（1入力1出力のみ）
+}
+
+/* 離散時間の状態空間モデル x[k+1] = A x[k] + B u[k], y[k] = C x[k] + D u[k]
+   Simulator の刻み幅とは独立に、サンプル時刻 k * ts に達したときだけ状態を更新する
+   入力はサンプル時刻に取り込み、次のサンプル時刻まで保持する */
+#[derive(Debug, Clone)]
+pub struct DiscreteSpaceStateModel {
+    model: SpaceStateModel, // 行列と状態を持つ（入力は取り込んだ値）
+    ts: f64,                // サンプル周期
+    u_live: DMatrix<f64>,   // 外部から与えられている現在の入力
+    samples: usize,         // 処理したサンプル時刻の数
+}
+
+impl DiscreteSpaceStateModel {
+    pub fn from_matrices(mat_a: DMatrix<f64>, mat_b: DMatrix<f64>, mat_c: DMatrix<f64>, mat_d: DMatrix<f64>, ts: f64) -> Result<Self, &'static str> {
+        let model = SpaceStateModel::from_matrices(mat_a, mat_b, mat_c, mat_d)?;
+        DiscreteSpaceStateModel::from_model(model, ts)
+    }
+
+    /* 状態空間モデルの行列をそのまま離散時間の行列として使う */
+    pub fn from_model(model: SpaceStateModel, ts: f64) -> Result<Self, &'static str> {
+        if ts <= 0.0 {
+            return Err("サンプル周期は正の値にしてください。");
+        }
+        let u_live = model.get_input();
+        Ok(Self {
+            model: model,
+            ts: ts,
+            u_live: u_live,
+            samples: 0,
+        })
+    }
+
+    pub fn get_model(&self) -> &SpaceStateModel {
+        &self.model
+    }
+
+    pub fn get_sample_time(&self) -> f64 {
+        self.ts
+    }
+
+    pub fn set_x(&mut self, x: &[f64]) -> Result<(), &str> {
+        self.model.set_x(x)
+    }
+
+    pub fn set_u(&mut self, u: &[f64]) -> Result<(), &str> {
+        if u.len() != self.u_live.nrows() {
+            return Err("入力ベクトルの次数が違います。")
+        }
+        self.u_live.copy_from_slice(u);
+        Ok(())
+    }
+
+    /* サンプル数を0に戻す（次の calc_nextstate で時刻0のサンプルから始める） */
+    pub fn reset_clock(&mut self) {
+        self.samples = 0;
+    }
+
+    /* 連続時間のモデルに戻す */
+    pub fn d2c(&self, method: DiscretizeMethod) -> Result<SpaceStateModel, &'static str> {
+        let (ad, bd, cd, dd) = (self.model.get_mat_a(), self.model.get_mat_b(), self.model.get_mat_c(), self.model.get_mat_d());
+        let n = ad.nrows();
+        let eye = DMatrix::<f64>::identity(n, n);
+
+        match method {
+            DiscretizeMethod::Zoh | DiscretizeMethod::Foh => {
+                let a = logm(ad)? / self.ts;
+
+                // 単位行列を入力行列として離散化すると、Bd = M B となる M が得られる
+                let unit = SpaceStateModel::from_matrices(a.clone(), eye.clone(), DMatrix::zeros(0, n), DMatrix::zeros(0, n))?;
+                let (phi, gamma1, gamma2) = foh_matrices(&unit, self.ts);
+                let (mmat, dcorr) = if method == DiscretizeMethod::Zoh {
+                    (gamma1, DMatrix::<f64>::zeros(n, n))
+                } else {
+                    (&gamma1 + (&phi - &eye) * &gamma2, gamma2)
+                };
+                let b = mmat.lu().solve(bd).ok_or("入力行列を求められませんでした。")?;
+                let d = dd - cd * dcorr * &b;
+                SpaceStateModel::from_matrices(a, b, cd.clone(), d)
+            },
+            DiscretizeMethod::Tustin { prewarp } => {
+                let beta = 1.0 / tustin_alpha(self.ts, prewarp)?;
+                let adi = (ad + &eye).try_inverse().ok_or("z = -1 に極があるため双一次変換できません。")?;
+                SpaceStateModel::from_matrices(
+                    (ad - &eye) * &adi / beta,
+                    &adi * bd / beta,
+                    cd * &adi * 2.0,
+                    dd - cd * &adi * bd)
+            },
+            DiscretizeMethod::Matched => {
+                check_siso(&self.model)?;
+                let (num, den) = matched_tf(&self.model.poles(), &self.model.zeros()?, self.ts, true)?;
+                let gain = match_gain(&self.model, self.ts, true, &num, &den)?;
+                let num: Vec<f64> = num.iter().map(|b| b * gain).collect();
+                SpaceStateModel::ctrb_canonical(&num, &den)
+            },
+        }
+    }
+}
+
+impl Model for DiscreteSpaceStateModel {
+    fn slopefunc(&self, _t: f64, x: &DMatrix<f64>) -> DMatrix<f64> { // 連続的には変化しない
+        DMatrix::zeros(x.nrows(), 1)
+    }
+
+    fn get_signals_info(&self) -> Vec<String> {
+        self.model.get_signals_info()
+    }
+
+    fn set_state(&mut self, newstate: DMatrix<f64>) {
+        self.model.set_state(newstate);
+    }
+
+    fn get_state(&self) -> &DMatrix<f64> {
+        self.model.get_state()
+    }
+
+    fn calc_nextstate(&mut self, t: f64, delta_t: f64, _solvertype: &SolverType) { // 連続的に変化する状態は無い
+        self.update_discrete(t, delta_t);
+    }
+
+    /* 区間 (t, t + delta_t] に含まれるサンプル時刻ごとに x = A x + B u を計算する
+       （時刻0の入力は最初の呼び出しで取り込む。入力はステップの間一定とみなす） */
+    fn update_discrete(&mut self, t: f64, delta_t: f64) {
+        while self.samples as f64 * self.ts <= t + delta_t + TICK_EPS * self.ts {
+            if self.samples > 0 {
+                let x = self.model.get_mat_a() * self.model.get_state() + self.model.get_mat_b() * self.model.get_input();
+                self.model.set_state(x);
+            }
+            self.model.set_u(self.u_live.as_slice()).unwrap();
+            self.samples += 1;
+        }
+    }
+
+    fn get_input_dim(&self) -> usize {
+        self.model.get_input_dim()
+    }
+
+    fn get_input(&self) -> DMatrix<f64> {
+        self.u_live.clone()
+    }
+
+    fn set_input(&mut self, u: &[f64]) {
+        self.set_u(u).unwrap();
+    }
+
+    fn slopefunc_u(&self, t: f64, x: &DMatrix<f64>, _u: &DMatrix<f64>) -> DMatrix<f64> { // 状態はサンプル時刻に calc_nextstate で更新する
+        self.slopefunc(t, x)
+    }
+
+    fn get_output_dim(&self) -> usize {
+        self.model.get_output_dim()
+    }
+
+    fn outputfunc(&self, t: f64, x: &DMatrix<f64>, u: &DMatrix<f64>) -> DMatrix<f64> {
+        self.model.outputfunc(t, x, u)
+    }
+
+    fn has_feedthrough(&self) -> bool {
+        self.model.has_feedthrough()
+    }
+
+    fn get_allsignals(&self) -> Vec<f64> { // 入力と出力はサンプル時刻に取り込んだ値を保持したもの
+        self.model.get_allsignals()
+    }
+}
+
+/* z領域の伝達関数モデル（内部的には離散時間の状態空間モデルを持つ） */
+#[derive(Debug, Clone)]
+pub struct DiscreteTransFuncModel {
+    model: DiscreteSpaceStateModel,
+    num: Vec<f64>,  // 分子多項式の係数 2次の例 b2 * z^2 + b1 * z + b0
+    den: Vec<f64>,  // 分母多項式の係数 2次の例 a2 * z^2 + a1 * z + a0
+}
+
+impl DiscreteTransFuncModel {
+    pub fn new(num_coef: &[f64], den_coef: &[f64], ts: f64) -> Self {
+        let model = SpaceStateModel::from_tf(&num_coef, &den_coef).unwrap();
+        Self {
+            num : num_coef.to_vec(),
+            den : den_coef.to_vec(),
+            model : DiscreteSpaceStateModel::from_model(model, ts).unwrap(),
+        }
+    }
+
+    pub fn set_u(&mut self, u: f64) {
+        self.model.set_u(&vec![u]).unwrap();
+    }
+
+    pub fn get_model(&self) -> &DiscreteSpaceStateModel {
+        &self.model
+    }
+
+    pub fn get_num(&self) -> &[f64] {
+        &self.num
+    }
+
+    pub fn get_den(&self) -> &[f64] {
+        &self.den
+    }
+
+    pub fn get_sample_time(&self) -> f64 {
+        self.model.get_sample_time()
+    }
+
+    /* 連続時間の伝達関数に戻す */
+    pub fn d2c(&self, method: DiscretizeMethod) -> Result<TransFuncModel, &'static str> {
+        let ss = self.model.d2c(method)?;
+        ss.ss2tf().get_tf(0, 0)
+    }
+}
+
+impl Model for DiscreteTransFuncModel {
+    fn slopefunc(&self, t: f64, x: &DMatrix<f64>) -> DMatrix<f64> {
+        self.model.slopefunc(t, x)
+    }
+
+    fn get_signals_info(&self) -> Vec<String> {
+        self.model.get_signals_info()
+    }
+
+    fn set_state(&mut self, newstate: DMatrix<f64>) {
+        self.model.set_state(newstate);
+    }
+
+    fn get_state(&self) -> &DMatrix<f64> {
+        self.model.get_state()
+    }
+
+    fn calc_nextstate(&mut self, t: f64, delta_t: f64, solvertype: &SolverType) {
+        self.model.calc_nextstate(t, delta_t, solvertype);
+    }
+
+    fn update_discrete(&mut self, t: f64, delta_t: f64) {
+        self.model.update_discrete(t, delta_t);
+    }
+
+    fn get_input_dim(&self) -> usize {
+        self.model.get_input_dim()
+    }
+
+    fn get_input(&self) -> DMatrix<f64> {
+        self.model.get_input()
+    }
+
+    fn set_input(&mut self, u: &[f64]) {
+        self.model.set_input(u);
+    }
+
+    fn slopefunc_u(&self, t: f64, x: &DMatrix<f64>, u: &DMatrix<f64>) -> DMatrix<f64> {
+        self.model.slopefunc_u(t, x, u)
+    }
+
+    fn get_output_dim(&self) -> usize {
+        self.model.get_output_dim()
+    }
+
+    fn outputfunc(&self, t: f64, x: &DMatrix<f64>, u: &DMatrix<f64>) -> DMatrix<f64> {
+        self.model.outputfunc(t, x, u)
+    }
+
+    fn has_feedthrough(&self) -> bool {
+        self.model.has_feedthrough()
+    }
+
+    fn get_allsignals(&self) -> Vec<f64> {
+        self.model.get_allsignals()
+    }
+}
+
+/* 1次ホールドの離散化に使う行列 Φ, Γ1 = ∫e^(Aτ)dτ B, Γ2 = (1/T)∫e^(A(T-s)) B s ds
+   拡大行列 [[A, B, 0], [0, 0, I/T], [0, 0, 0]] の行列指数関数から取り出す */
+fn foh_matrices(model: &SpaceStateModel, ts: f64) -> (DMatrix<f64>, DMatrix<f64>, DMatrix<f64>) {
+    let a = model.get_mat_a();
+    let b = model.get_mat_b();
+    let n = a.nrows();
+    let m = b.ncols();
+
+    let mut aug = DMatrix::<f64>::zeros(n + 2 * m, n + 2 * m);
+    aug.slice_mut((0, 0), (n, n)).copy_from(&(a * ts));
+    aug.slice_mut((0, n), (n, m)).copy_from(&(b * ts));
+    aug.slice_mut((n, n + m), (m, m)).fill_with_identity();
+
+    let expm = aug.exp();
+    (expm.slice((0, 0), (n, n)).into_owned(),
+     expm.slice((0, n), (n, m)).into_owned(),
+     expm.slice((0, n + m), (n, m)).into_owned())
+}
+
+/* 双一次変換 s = α (z - 1) / (z + 1) の係数α （プリワープなしなら 2 / T） */
+fn tustin_alpha(ts: f64, prewarp: Option<f64>) -> Result<f64, &'static str> {
+    match prewarp {
+        None => Ok(2.0 / ts),
+        Some(w) => {
+            if w <= 0.0 || w * ts >= PI {
+                return Err("プリワープ周波数は 0 < ω < π / T としてください。");
+            }
+            Ok(w / (w * ts / 2.0).tan())
+        },
+    }
+}
+
+fn check_siso(model: &SpaceStateModel) -> Result<(), &'static str> {
+    if model.get_input_dim() != 1 || model.get_output_dim() != 1 {
+        return Err("極・零点マッチングは1入力1出力のモデルのみ対応しています。");
+    }
+    if model.get_mat_a().nrows() == 0 {
+        return Err("次数が0になりました。");
+    }
+    Ok(())
+}
+
+/* 極・零点を写像した伝達関数（ゲインは1のまま）
+   c2d: z = e^(sT)、無限遠の零点は1つを残して z = -1 に置く
+   d2c: s = ln(z) / T、z = -1 の零点は無限遠に戻す */
+fn matched_tf(poles: &[Complex<f64>], zeros: &[Complex<f64>], ts: f64, to_continuous: bool) -> Result<(Vec<f64>, Vec<f64>), &'static str> {
+    let map = |p: &Complex<f64>| -> Result<Complex<f64>, &'static str> {
+        if to_continuous {
+            if p.modulus() == 0.0 || (p.re < 0.0 && p.im.abs() <= 1e-12) {
+                return Err("z = 0 または負の実軸上に極・零点があるため連続時間に変換できません。");
+            }
+            Ok(p.ln() / ts)
+        } else {
+            Ok((p * ts).exp())
+        }
+    };
+
+    let pmap = poles.iter().map(map).collect::<Result<Vec<_>, _>>()?;
+    let zmap = if to_continuous {
+        zeros.iter().filter(|z| (*z + 1.0).modulus() > 1e-9).map(map).collect::<Result<Vec<_>, _>>()?
+    } else {
+        let mut zmap = zeros.iter().map(map).collect::<Result<Vec<_>, _>>()?;
+        for _ in 1..poles.len().saturating_sub(zeros.len()) {
+            zmap.push(Complex::new(-1.0, 0.0));
+        }
+        zmap
+    };
+    Ok((poly_from_roots(&zmap), poly_from_roots(&pmap)))
+}
+
+/* 写像した伝達関数のゲインを元のモデルと直流 (s = 0, z = 1) で一致させる
+   直流に極・零点があるときは ω = π / 2T で一致させる */
+fn match_gain(model: &SpaceStateModel, ts: f64, model_is_discrete: bool, num: &[f64], den: &[f64]) -> Result<f64, &'static str> {
+    let a = model.get_mat_a().map(|v| Complex::new(v, 0.0));
+    let b = model.get_mat_b().map(|v| Complex::new(v, 0.0));
+    let c = model.get_mat_c().map(|v| Complex::new(v, 0.0));
+    let n = a.nrows();
+    let eval = |coef: &[f64], s: Complex<f64>| coef.iter().fold(Complex::new(0.0, 0.0), |acc, c| acc * s + c);
+
+    // s0 と z0 = e^(s0 T) での両モデルの値の比
+    let ratio = |s0: Complex<f64>| -> Option<Complex<f64>> {
+        let z0 = (s0 * ts).exp();
+        let (p, q) = if model_is_discrete { (z0, s0) } else { (s0, z0) };
+        let x = (DMatrix::<Complex<f64>>::from_diagonal_element(n, n, p) - &a).lu().solve(&b)?;
+        let g = (&c * x)[(0, 0)] + model.get_mat_d()[(0, 0)];
+        let h = eval(num, q) / eval(den, q);
+        let k = g / h;
+        if k.re.is_finite() && k.im.is_finite() && k.modulus() > 0.0 { Some(k) } else { None }
+    };
+
+    let k = ratio(Complex::new(0.0, 0.0))
+        .or_else(|| ratio(Complex::new(0.0, PI / (2.0 * ts))))
+        .ok_or("ゲインを合わせる点に極・零点があります。")?;
+    Ok(k.modulus() * k.re.signum())
+}
+
+/* 行列の平方根（デンマン・ビーバース反復） */
+fn sqrtm(m: &DMatrix<f64>) -> Result<DMatrix<f64>, &'static str> {
+    let n = m.nrows();
+    let mut y = m.clone();
+    let mut z = DMatrix::<f64>::identity(n, n);
+    for _ in 0..LOGM_MAXITER {
+        let yinv = y.clone().try_inverse().ok_or("行列の平方根を求められませんでした。")?;
+        let zinv = z.clone().try_inverse().ok_or("行列の平方根を求められませんでした。")?;
+        let ynew = (&y + zinv) / 2.0;
+        z = (&z + yinv) / 2.0;
+        let diff = (&ynew - &y).norm();
+        y = ynew;
+        if diff <= 1e-14 * y.norm() {
+            return Ok(y);
+        }
+    }
+    Err("行列の平方根の反復が収束しませんでした。")
+}
+
+/* 行列対数（平方根を繰り返して単位行列に近づけてから級数で求める） */
+fn logm(m: &DMatrix<f64>) -> Result<DMatrix<f64>, &'static str> {
+    let n = m.nrows();
+    if m.clone().complex_eigenvalues().iter().any(|e| e.modulus() == 0.0 || (e.re < 0.0 && e.im.abs() <= 1e-12 * e.modulus())) {
+        return Err("0 または負の実数の固有値を持つため連続時間に変換できません。");
+    }
+
+    let eye = DMatrix::<f64>::identity(n, n);
+    let mut x = m.clone();
+    let mut k = 0i32;
+    while (&x - &eye).norm() > 0.25 {
+        if k as usize >= LOGM_MAXITER {
+            return Err("行列対数を求められませんでした。");
+        }
+        x = sqrtm(&x)?;
+        k += 1;
+    }
+
+    // log(X) = 2 (Z + Z^3/3 + Z^5/5 + ...),  Z = (X - I)(X + I)^(-1)
+    let z = (&x - &eye) * (&x + &eye).try_inverse().ok_or("行列対数を求められませんでした。")?;
+    let z2 = &z * &z;
+    let mut term = z.clone();
+    let mut sum = z.clone();
+    for j in 1..30 {
+        term = &term * &z2;
+        sum += &term / (2 * j + 1) as f64;
+    }
+    Ok(sum * 2.0 * 2f64.powi(k))
+}
+
+impl SpaceStateModel {
+    /* サンプル周期tsで離散化したモデル（現在の状態と入力を引き継ぐ） */
+    pub fn c2d(&self, ts: f64, method: DiscretizeMethod) -> Result<DiscreteSpaceStateModel, &'static str> {
+        if ts <= 0.0 {
+            return Err("サンプル周期は正の値にしてください。");
+        }
+        let (a, b, c, d) = (self.get_mat_a(), self.get_mat_b(), self.get_mat_c(), self.get_mat_d());
+        let n = a.nrows();
+        let eye = DMatrix::<f64>::identity(n, n);
+
+        let x = self.get_state();
+        let u = self.get_input();
+
+        // 離散化したモデルと、現在の状態に対応する離散時間の状態
+        let (mut model, xd) = match method {
+            DiscretizeMethod::Zoh => {
+                let (phi, gamma) = self.calc_discrete_matrices(ts);
+                (SpaceStateModel::from_matrices(phi, gamma, c.clone(), d.clone())?, x.clone())
+            },
+            DiscretizeMethod::Foh => { // 状態を ξ = x - Γ2 u に取り直して因果的な形にする
+                let (phi, gamma1, gamma2) = foh_matrices(self, ts);
+                let bd = &gamma1 + (&phi - &eye) * &gamma2;
+                let dd = d + c * &gamma2;
+                let xd = x - &gamma2 * &u;
+                (SpaceStateModel::from_matrices(phi, bd, c.clone(), dd)?, xd)
+            },
+            DiscretizeMethod::Tustin { prewarp } => { // 状態は ξ = (I - βA) x - βB u に対応する
+                let beta = 1.0 / tustin_alpha(ts, prewarp)?;
+                let inv = (&eye - a * beta).try_inverse().ok_or("s = 2/T に極があるため双一次変換できません。")?;
+                let xd = (&eye - a * beta) * x - b * &u * beta;
+                (SpaceStateModel::from_matrices(
+                    &inv * (&eye + a * beta),
+                    &inv * b * (2.0 * beta),
+                    c * &inv,
+                    d + c * &inv * b * beta)?, xd)
+            },
+            DiscretizeMethod::Matched => { // 正準形で実現するので状態は引き継がない
+                check_siso(self)?;
+                let (num, den) = matched_tf(&self.poles(), &self.zeros()?, ts, false)?;
+                let gain = match_gain(self, ts, false, &num, &den)?;
+                let num: Vec<f64> = num.iter().map(|b| b * gain).collect();
+                let model = SpaceStateModel::ctrb_canonical(&num, &den)?;
+                let xd = DMatrix::<f64>::zeros(model.get_mat_a().nrows(), 1);
+                (model, xd)
+            },
+        };
+
+        model.set_x(xd.as_slice()).unwrap();
+        model.set_u(u.as_slice()).unwrap();
+        DiscreteSpaceStateModel::from_model(model, ts)
+    }
+}
+
+impl TransFuncModel {
+    /* サンプル周期tsで離散化したz領域の伝達関数 */
+    pub fn c2d(&self, ts: f64, method: DiscretizeMethod) -> Result<DiscreteTransFuncModel, &'static str> {
+        let dss = self.get_model().c2d(ts, method)?;
+        let tfm = dss.get_model().ss2tf();
+        if tfm.den.len() < 2 {
+            return Err("次数が0になりました。");
+        }
+        Ok(DiscreteTransFuncModel::new(&tfm.num[0][0], &tfm.den, ts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* G(s) = (s + 3) / (s^2 + 2s + 5) */
+    fn plant() -> SpaceStateModel {
+        SpaceStateModel::from_tf(&[1.0, 3.0], &[1.0, 2.0, 5.0]).unwrap()
+    }
+
+    fn assert_same_tf(g1: &SpaceStateModel, g2: &SpaceStateModel) {
+        let (tf1, tf2) = (g1.ss2tf(), g2.ss2tf());
+        let close = |a: &[f64], b: &[f64]| a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| (x - y).abs() < 1e-8);
+        assert!(close(&tf1.den, &tf2.den) && close(&tf1.num[0][0], &tf2.num[0][0]), "{:?} != {:?}", tf1, tf2);
+    }
+
+    #[test]
+    fn c2d_and_d2c_round_trip() {
+        let model = plant();
+        let methods = [
+            DiscretizeMethod::Zoh, DiscretizeMethod::Foh, DiscretizeMethod::Tustin { prewarp: None },
+            DiscretizeMethod::Tustin { prewarp: Some(2.0) }, DiscretizeMethod::Matched,
+        ];
+        for method in methods.iter() {
+            let discrete = model.c2d(0.1, *method).unwrap();
+            assert_same_tf(&discrete.d2c(*method).unwrap(), &model);
+        }
+    }
+
+    #[test]
+    fn zoh_of_first_order_lag() {
+        // 1 / (s + 1) → (1 - e^(-T)) / (z - e^(-T))
+        let lag = SpaceStateModel::from_tf(&[1.0], &[1.0, 1.0]).unwrap();
+        let discrete = lag.c2d(0.5, DiscretizeMethod::Zoh).unwrap();
+        let (a, b, c) = (discrete.get_model().get_mat_a(), discrete.get_model().get_mat_b(), discrete.get_model().get_mat_c());
+        let p = (-0.5_f64).exp();
+        assert!((a[0] - p).abs() < 1e-12);
+        assert!((c[0] * b[0] - (1.0 - p)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn discrete_model_updates_only_at_samples() {
+        // 刻み幅0.01で進めても、状態はサンプル周期0.1ごとにだけ変わる
+        let lag = SpaceStateModel::from_tf(&[1.0], &[1.0, 1.0]).unwrap();
+        let mut discrete = lag.c2d(0.1, DiscretizeMethod::Zoh).unwrap();
+        discrete.set_u(&[1.0]).unwrap();
+
+        let mut changes = 0;
+        let mut prev = discrete.get_state()[0];
+        for i in 0..100 {
+            discrete.calc_nextstate(i as f64 * 0.01, 0.01, &SolverType::RungeKutta);
+            if discrete.get_state()[0] != prev {
+                changes += 1;
+                prev = discrete.get_state()[0];
+            }
+        }
+        assert_eq!(changes, 10);
+
+        // ZOHはステップ入力に対してサンプル時刻で厳密
+        let y = discrete.outputfunc(1.0, discrete.get_state(), &discrete.get_input())[0];
+        assert!((y - (1.0 - (-1.0_f64).exp())).abs() < 1e-9);
+    }
+}
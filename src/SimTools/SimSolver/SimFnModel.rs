@@ -0,0 +1,260 @@
+/* クロージャで定義するモデル */
+// 状態方程式 dx/dt = f(t, x, u) と出力方程式 y = g(t, x, u) をクロージャで与えるだけで Model として使える
+// 例：振り子 FnModel::new(&[0.1, 0.0], 1, |_t, x, u| DMatrix::from_column_slice(2, 1, &[x[1], -9.8 * x[0].sin() + u[0]]))
+
+extern crate nalgebra as na;
+use na::DMatrix;
+
+use super::simmodel::{*};
+
+type ModelFn = Box<dyn Fn(f64, &DMatrix<f64>, &DMatrix<f64>) -> DMatrix<f64>>;
+
+pub struct FnModel {
+    f: ModelFn,                 // 状態方程式
+    g: Option<ModelFn>,         // 出力方程式（無ければ出力を持たない）
+    output_dim: usize,
+    feedthrough: bool,          // 出力が入力に直接依存するか
+    state_names: Vec<String>,
+    input_names: Vec<String>,
+    output_names: Vec<String>,
+    t: f64,                     // 現在時刻（記録する出力の計算に使う）
+    x: DMatrix<f64>,
+    u: DMatrix<f64>,
+    step_control: StepControl,  // 可変刻みのソルバの状態
+}
+
+fn check_dim(v: &DMatrix<f64>, rows: usize) -> Result<(), &'static str> {
+    if v.shape() != (rows, 1) {
+        return Err("クロージャの戻り値の次数が違います。");
+    }
+    Ok(())
+}
+
+fn to_names(names: &[&str], dim: usize) -> Result<Vec<String>, &'static str> {
+    if names.len() != dim {
+        return Err("信号名の数が次数と違います。");
+    }
+    Ok(names.iter().map(|s| s.to_string()).collect())
+}
+
+impl FnModel {
+    /* 初期状態init_state（状態の次数はその長さ）、入力の次数input_dim、状態方程式fからモデルを作る
+       時刻0・入力0でfを1回呼んで戻り値の次数を確認する */
+    pub fn new<F>(init_state: &[f64], input_dim: usize, f: F) -> Result<Self, &'static str>
+    where F: Fn(f64, &DMatrix<f64>, &DMatrix<f64>) -> DMatrix<f64> + 'static
+    {
+        let x = DMatrix::from_column_slice(init_state.len(), 1, init_state);
+        let u = DMatrix::from_element(input_dim, 1, 0.0);
+        check_dim(&f(0.0, &x, &u), x.nrows())?;
+
+        Ok(Self {
+            f: Box::new(f),
+            g: None,
+            output_dim: 0,
+            feedthrough: false,
+            state_names: (0..x.nrows()).map(|i| format!("x_{}", i)).collect(),
+            input_names: (0..input_dim).map(|i| format!("u_{}", i)).collect(),
+            output_names: Vec::new(),
+            t: 0.0,
+            x: x,
+            u: u,
+            step_control: StepControl::default(),
+        })
+    }
+
+    /* 出力方程式 y = g(t, x, u) を設定する（出力は入力に直接依存するものとして扱う） */
+    pub fn set_output<G>(&mut self, output_dim: usize, g: G) -> Result<(), &'static str>
+    where G: Fn(f64, &DMatrix<f64>, &DMatrix<f64>) -> DMatrix<f64> + 'static
+    {
+        check_dim(&g(self.t, &self.x, &self.u), output_dim)?;
+        self.g = Some(Box::new(g));
+        self.output_dim = output_dim;
+        self.feedthrough = true;
+        self.output_names = (0..output_dim).map(|i| format!("y_{}", i)).collect();
+        Ok(())
+    }
+
+    /* 出力が入力に直接依存しない（gがuを使わない）ときはfalseにすると、フィードバック結合の代数ループの判定で使われる */
+    pub fn set_feedthrough(&mut self, feedthrough: bool) {
+        self.feedthrough = feedthrough;
+    }
+
+    pub fn set_state_names(&mut self, names: &[&str]) -> Result<(), &'static str> {
+        self.state_names = to_names(names, self.x.nrows())?;
+        Ok(())
+    }
+
+    pub fn set_input_names(&mut self, names: &[&str]) -> Result<(), &'static str> {
+        self.input_names = to_names(names, self.u.nrows())?;
+        Ok(())
+    }
+
+    pub fn set_output_names(&mut self, names: &[&str]) -> Result<(), &'static str> {
+        self.output_names = to_names(names, self.output_dim)?;
+        Ok(())
+    }
+
+    pub fn init_state(&mut self, init_state: &[f64]) -> Result<(), &str> {
+        if init_state.len() != self.x.nrows() {
+            return Err("状態ベクトルの次数が違います。");
+        }
+        self.x.copy_from_slice(init_state);
+        Ok(())
+    }
+
+    pub fn set_u(&mut self, u: &[f64]) -> Result<(), &str> {
+        if u.len() != self.u.nrows() {
+            return Err("入力ベクトルの次数が違います。");
+        }
+        self.u.copy_from_slice(u);
+        Ok(())
+    }
+}
+
+impl Model for FnModel {
+    fn slopefunc(&self, t: f64, x: &DMatrix<f64>) -> DMatrix<f64> {
+        (self.f)(t, x, &self.u)
+    }
+
+    fn get_signals_info(&self) -> Vec<String> {
+        let mut series = self.input_names.clone();
+        series.append(&mut self.state_names.clone());
+        series.append(&mut self.output_names.clone());
+        series
+    }
+
+    fn set_state(&mut self, newstate: DMatrix<f64>) {
+        self.x = newstate;
+    }
+
+    fn get_state(&self) -> &DMatrix<f64> {
+        &self.x
+    }
+
+    fn get_allsignals(&self) -> Vec<f64> {
+        let mut result = self.u.iter().map(|u| *u).collect::<Vec<f64>>();
+        result.append(&mut self.x.iter().map(|x| *x).collect::<Vec<f64>>());
+        result.append(&mut self.outputfunc(self.t, &self.x, &self.u).iter().map(|y| *y).collect::<Vec<f64>>());
+        result
+    }
+
+    fn calc_nextstate(&mut self, t: f64, delta_t : f64, solvertype: &SolverType) {
+        let newstate = solve_nextstate(self, t, delta_t, solvertype);
+        self.t = t + delta_t;
+        self.set_state(newstate);
+    }
+
+    fn get_input_dim(&self) -> usize {
+        self.u.nrows()
+    }
+
+    fn get_input(&self) -> DMatrix<f64> {
+        self.u.clone()
+    }
+
+    fn set_input(&mut self, u: &[f64]) { // 次数は呼び出し側で get_input_dim と合わせる約束なので、違えば呼び出し側の誤りとしてpanicする（確認したいときは set_u を使う）
+        self.set_u(u).expect("set_input に渡した入力ベクトルの次数が get_input_dim と違います。");
+    }
+
+    fn slopefunc_u(&self, t: f64, x: &DMatrix<f64>, u: &DMatrix<f64>) -> DMatrix<f64> {
+        (self.f)(t, x, u)
+    }
+
+    fn get_output_dim(&self) -> usize {
+        self.output_dim
+    }
+
+    fn outputfunc(&self, t: f64, x: &DMatrix<f64>, u: &DMatrix<f64>) -> DMatrix<f64> {
+        match &self.g {
+            Some(g) => g(t, x, u),
+            None => DMatrix::from_element(0, 1, 0.0),
+        }
+    }
+
+    fn has_feedthrough(&self) -> bool {
+        self.feedthrough
+    }
+
+    fn get_step_control(&self) -> StepControl {
+        self.step_control
+    }
+
+    fn set_step_control(&mut self, control: StepControl) {
+        self.step_control = control;
+    }
+
+    fn get_input_names(&self) -> Vec<String> {
+        self.input_names.clone()
+    }
+
+    fn get_output_names(&self) -> Vec<String> {
+        self.output_names.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::simblock::{*};
+
+    /* dx/dt = -x + u、y = 2x */
+    fn lag() -> FnModel {
+        let mut model = FnModel::new(&[0.0], 1, |_t, x, u| -x + u).unwrap();
+        model.set_output(1, |_t, x, _u| 2.0 * x).unwrap();
+        model
+    }
+
+    #[test]
+    fn pendulum_oscillates_at_natural_frequency() {
+        // 微小振幅では x = x0 cos(√(g/l) t)
+        let mut model = FnModel::new(&[0.01, 0.0], 1, |_t, x, u| DMatrix::from_column_slice(2, 1, &[x[1], -9.8 * x[0].sin() + u[0]])).unwrap();
+        model.set_state_names(&["theta", "omega"]).unwrap();
+        let delta_t = 0.001;
+        for i in 0..1000 {
+            model.calc_nextstate(i as f64 * delta_t, delta_t, &SolverType::RungeKutta);
+        }
+        assert!((model.get_state()[0] - 0.01 * 9.8f64.sqrt().cos()).abs() < 1e-6);
+        assert_eq!(model.get_signals_info(), vec!["u_0", "theta", "omega"]);
+    }
+
+    #[test]
+    fn input_and_output_follow_closures() {
+        let mut model = lag();
+        model.set_input(&[1.0]);
+        let delta_t = 0.01;
+        for i in 0..100 {
+            model.calc_nextstate(i as f64 * delta_t, delta_t, &SolverType::RungeKutta);
+        }
+        let expected = 1.0 - (-1.0f64).exp();
+        assert!((model.get_state()[0] - expected).abs() < 1e-9);
+        assert!((model.get_allsignals()[2] - 2.0 * expected).abs() < 1e-9);
+        assert!(model.has_feedthrough());
+    }
+
+    #[test]
+    fn dimension_errors() {
+        assert!(FnModel::new(&[0.0, 0.0], 1, |_t, x, _u| x.rows(0, 1).into_owned()).is_err());
+        let mut model = lag();
+        assert!(model.set_output(2, |_t, x, _u| x.clone()).is_err());
+        assert!(model.set_input_names(&["a", "b"]).is_err());
+        assert!(model.init_state(&[1.0, 2.0]).is_err());
+        assert!(model.set_u(&[1.0, 2.0]).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn set_input_with_wrong_dimension_panics() {
+        lag().set_input(&[1.0, 2.0]);
+    }
+
+    #[test]
+    fn feedback_uses_feedthrough_flag() {
+        // 出力が入力に依存しないと宣言すれば、定数ゲインとのフィードバックは代数ループにならない
+        let mut gain = SpaceStateModel::new(0, 1, 1);
+        gain.set_mat_d(&[1.0]).unwrap();
+        assert!(CompositeModel::feedback(lag(), gain.clone(), FeedbackType::Negative).is_err());
+        let mut model = lag();
+        model.set_feedthrough(false);
+        assert!(CompositeModel::feedback(model, gain, FeedbackType::Negative).is_ok());
+    }
+}
@@ -0,0 +1,289 @@
+/* 周波数応答 */
+// G(jω) の計算、ゲイン余裕・位相余裕、ボード線図・ナイキスト線図の描画
+
+use std::fs;
+use std::f64::consts::PI;
+
+extern crate nalgebra as na;
+use na::{DMatrix, Complex, ComplexField};
+
+use plotters::prelude::*;
+
+use super::simmodel::{*};
+use super::simanalysis::{*};
+
+/* 対数で等間隔な角周波数の列 [rad/s] */
+pub fn logspace(w_min: f64, w_max: f64, npoints: usize) -> Result<Vec<f64>, &'static str> {
+    if w_min <= 0.0 || w_max <= w_min {
+        return Err("周波数範囲は 0 < w_min < w_max としてください。");
+    }
+    if npoints < 2 {
+        return Err("周波数の点数は2以上にしてください。");
+    }
+
+    let (lmin, lmax) = (w_min.log10(), w_max.log10());
+    Ok((0..npoints).map(|i| 10f64.powf(lmin + (lmax - lmin) * i as f64 / (npoints - 1) as f64)).collect())
+}
+
+/* 1入力1出力の周波数応答 */
+#[derive(Debug, Clone)]
+pub struct FreqResponse {
+    pub omega: Vec<f64>,                // 角周波数 [rad/s]
+    pub response: Vec<Complex<f64>>,    // G(jω)
+    pub gain_db: Vec<f64>,              // ゲイン [dB]
+    pub phase_deg: Vec<f64>,            // 位相 [deg]（連続になるようにアンラップしたもの）
+}
+
+/* ゲイン余裕と位相余裕（交差しない場合はNone） */
+#[derive(Debug, Clone)]
+pub struct Margins {
+    pub gain_margin: Option<f64>,       // ゲイン余裕 [dB]
+    pub phase_crossover: Option<f64>,   // 位相交差周波数 [rad/s]（位相が-180degとなる周波数）
+    pub phase_margin: Option<f64>,      // 位相余裕 [deg]
+    pub gain_crossover: Option<f64>,    // ゲイン交差周波数 [rad/s]（ゲインが0dBとなる周波数）
+}
+
+impl FreqResponse {
+    pub fn new(omega: Vec<f64>, response: Vec<Complex<f64>>) -> Result<Self, &'static str> {
+        if omega.len() != response.len() {
+            return Err("周波数と応答の点数が違います。");
+        }
+
+        let gain_db = response.iter().map(|g| 20.0 * g.modulus().log10()).collect();
+
+        let mut phase_deg: Vec<f64> = Vec::with_capacity(response.len());
+        for g in response.iter() {
+            let mut ph = g.im.atan2(g.re) * 180.0 / PI;
+            if let Some(prev) = phase_deg.last() { // 前の点との差が±180deg以内になるように360degずらす
+                ph -= 360.0 * ((ph - prev) / 360.0).round();
+            }
+            phase_deg.push(ph);
+        }
+
+        Ok(Self {
+            omega: omega,
+            response: response,
+            gain_db: gain_db,
+            phase_deg: phase_deg,
+        })
+    }
+
+    /* ゲイン余裕・位相余裕
+       周波数の格子点の間は log(ω) について線形補間する。交差が複数あるときは余裕が最も小さいものを返す */
+    pub fn margins(&self) -> Margins {
+        let mut gm: Option<(f64, f64)> = None;
+        let mut pm: Option<(f64, f64)> = None;
+
+        for i in 0..self.omega.len().saturating_sub(1) {
+            let (w0, w1) = (self.omega[i].log10(), self.omega[i + 1].log10());
+
+            // 位相が -180 + 360k [deg] を横切る点
+            let (p0, p1) = (self.phase_deg[i], self.phase_deg[i + 1]);
+            let (f0, f1) = ((p0 + 180.0) / 360.0, (p1 + 180.0) / 360.0);
+            if f0.floor() != f1.floor() || f1 == f1.floor() {
+                let level = 360.0 * f0.max(f1).floor() - 180.0;
+                let r = if p1 != p0 { (level - p0) / (p1 - p0) } else { 0.0 };
+                let g = self.gain_db[i] + r * (self.gain_db[i + 1] - self.gain_db[i]);
+                let w = 10f64.powf(w0 + r * (w1 - w0));
+                if gm.map_or(true, |(m, _)| g.abs() < m.abs()) {
+                    gm = Some((-g, w));
+                }
+            }
+
+            // ゲインが 0dB を横切る点
+            let (g0, g1) = (self.gain_db[i], self.gain_db[i + 1]);
+            if (g0 > 0.0) != (g1 > 0.0) || g1 == 0.0 {
+                let r = if g1 != g0 { -g0 / (g1 - g0) } else { 0.0 };
+                let ph = p0 + r * (p1 - p0);
+                let w = 10f64.powf(w0 + r * (w1 - w0));
+                let m = ph + 180.0 - 360.0 * ((ph + 180.0) / 360.0).round(); // -180～180degに収める
+                if pm.map_or(true, |(n, _)| m.abs() < n.abs()) {
+                    pm = Some((m, w));
+                }
+            }
+        }
+
+        Margins {
+            gain_margin: gm.map(|(m, _)| m),
+            phase_crossover: gm.map(|(_, w)| w),
+            phase_margin: pm.map(|(m, _)| m),
+            gain_crossover: pm.map(|(_, w)| w),
+        }
+    }
+
+    /* ボード線図（上段ゲイン、下段位相）を ./dirname/name_bode.png に描く */
+    pub fn bodeplot(&self, dirname: &str, name: &str, pltsize: (u32, u32)) {
+        match fs::create_dir(dirname) {
+            Err(e) => println!("! {:?}", e.kind()),
+            Ok(_) => {},
+        }
+        let filename = format!("./{}/{}_bode.png", dirname, name);
+
+        let plt = BitMapBackend::new(&filename, pltsize).into_drawing_area();
+        plt.fill(&WHITE).unwrap();
+        let areas = plt.split_evenly((2, 1));
+
+        let font = ("sans-serif", 20);
+        let w_min = self.omega[0];
+        let w_max = self.omega[self.omega.len() - 1];
+
+        let panels = [
+            (format!("{} gain [dB]", name), &self.gain_db),
+            (format!("{} phase [deg]", name), &self.phase_deg),
+        ];
+        for (area, (caption, values)) in areas.iter().zip(panels.iter()) {
+            let (y_min, y_max) = values.iter()
+                .fold((f64::NAN, f64::NAN), |(m, n), v| (v.min(m), v.max(n)));
+            let pad = ((y_max - y_min) * 0.05).max(1.0);
+
+            let mut chart = ChartBuilder::on(area)
+              .caption(caption, font.into_font())
+              .margin(10)
+              .x_label_area_size(16)
+              .y_label_area_size(42)
+              .build_cartesian_2d(
+                (w_min..w_max).log_scale(),
+                (y_min - pad)..(y_max + pad))
+              .unwrap();
+
+            chart.configure_mesh().draw().unwrap();
+
+            let line_series = LineSeries::new(
+                self.omega.iter()
+                    .zip(values.iter())
+                    .map(|(x, y)| (*x, *y)),
+                &RED);
+
+            chart.draw_series(line_series).unwrap();
+        }
+    }
+
+    /* ナイキスト線図（ω > 0 を赤、ω < 0 を青で描き、-1 の点に印を付ける）を ./dirname/name_nyquist.png に描く */
+    pub fn nyquistplot(&self, dirname: &str, name: &str, pltsize: (u32, u32)) {
+        match fs::create_dir(dirname) {
+            Err(e) => println!("! {:?}", e.kind()),
+            Ok(_) => {},
+        }
+        let filename = format!("./{}/{}_nyquist.png", dirname, name);
+
+        let plt = BitMapBackend::new(&filename, pltsize).into_drawing_area();
+        plt.fill(&WHITE).unwrap();
+
+        let font = ("sans-serif", 20);
+
+        // -1 の点が必ず入るように範囲を決める
+        let (x_min, x_max, y_max) = self.response.iter()
+            .fold((-1.0f64, 0.0f64, 0.0f64), |(a, b, c), g| (a.min(g.re), b.max(g.re), c.max(g.im.abs())));
+        let pad = 0.05 * (x_max - x_min).max(2.0 * y_max);
+
+        let mut chart = ChartBuilder::on(&plt)
+          .caption(&format!("{} nyquist", name), font.into_font())
+          .margin(10)
+          .x_label_area_size(16)
+          .y_label_area_size(42)
+          .build_cartesian_2d(
+            (x_min - pad)..(x_max + pad),
+            (-y_max - pad)..(y_max + pad))
+          .unwrap();
+
+        chart.configure_mesh().draw().unwrap();
+
+        chart.draw_series(LineSeries::new(
+            self.response.iter().map(|g| (g.re, g.im)),
+            &RED)).unwrap();
+        chart.draw_series(LineSeries::new(
+            self.response.iter().map(|g| (g.re, -g.im)),
+            &BLUE)).unwrap();
+        chart.draw_series(std::iter::once(Cross::new((-1.0, 0.0), 6, &BLACK))).unwrap();
+    }
+}
+
+impl SpaceStateModel {
+    /* 周波数応答行列 G(jω) = C (jωI - A)^(-1) B + D */
+    pub fn eval_freq(&self, w: f64) -> Result<DMatrix<Complex<f64>>, &'static str> {
+        let to_complex = |m: &DMatrix<f64>| m.map(|v| Complex::new(v, 0.0));
+        let n = self.get_mat_a().nrows();
+
+        let jw_a = DMatrix::<Complex<f64>>::from_diagonal_element(n, n, Complex::new(0.0, w)) - to_complex(self.get_mat_a());
+        let x = jw_a.lu().solve(&to_complex(self.get_mat_b())).ok_or("虚軸上に極があるため周波数応答が無限大です。")?;
+        Ok(to_complex(self.get_mat_c()) * x + to_complex(self.get_mat_d()))
+    }
+
+    /* 各周波数での周波数応答行列 */
+    pub fn freqresp(&self, omega: &[f64]) -> Result<Vec<DMatrix<Complex<f64>>>, &'static str> {
+        omega.iter().map(|w| self.eval_freq(*w)).collect()
+    }
+
+    /* 入力inputから出力outputへの周波数応答 */
+    pub fn freqresp_channel(&self, output: usize, input: usize, omega: &[f64]) -> Result<FreqResponse, &'static str> {
+        if output >= self.get_mat_c().nrows() || input >= self.get_mat_b().ncols() {
+            return Err("入出力の番号が範囲外です。");
+        }
+        let response = omega.iter()
+            .map(|w| self.eval_freq(*w).map(|g| g[(output, input)]))
+            .collect::<Result<Vec<_>, _>>()?;
+        FreqResponse::new(omega.to_vec(), response)
+    }
+}
+
+impl TransFuncModel {
+    /* 周波数応答 G(jω) = num(jω) / den(jω) */
+    pub fn eval_freq(&self, w: f64) -> Result<Complex<f64>, &'static str> {
+        let s = Complex::new(0.0, w);
+        let den = poly_eval(self.get_den(), s);
+        if den.modulus() == 0.0 {
+            return Err("虚軸上に極があるため周波数応答が無限大です。");
+        }
+        Ok(poly_eval(self.get_num(), s) / den)
+    }
+
+    pub fn freqresp(&self, omega: &[f64]) -> Result<FreqResponse, &'static str> {
+        let response = omega.iter()
+            .map(|w| self.eval_freq(*w))
+            .collect::<Result<Vec<_>, _>>()?;
+        FreqResponse::new(omega.to_vec(), response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfer_function_and_state_space_agree() {
+        let (num, den) = ([2.0, 1.0], [1.0, 3.0, 2.0]);
+        let tf = TransFuncModel::new(&num, &den);
+        let ss = SpaceStateModel::from_tf(&num, &den).unwrap();
+        for w in [0.1, 1.0, 10.0] {
+            let s = Complex::new(0.0, w);
+            let expected = (s * 2.0 + 1.0) / (s * s + s * 3.0 + 2.0);
+            assert!((tf.eval_freq(w).unwrap() - expected).modulus() < 1e-12);
+            assert!((ss.eval_freq(w).unwrap()[(0, 0)] - expected).modulus() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn margins_of_third_order_loop() {
+        // L(s) = 1 / (s (s + 1) (s + 2))：位相交差周波数 √2、ゲイン余裕 20 log10(6)
+        let tf = TransFuncModel::new(&[1.0], &[1.0, 3.0, 2.0, 0.0]);
+        let resp = tf.freqresp(&logspace(0.01, 100.0, 2000).unwrap()).unwrap();
+        let margins = resp.margins();
+
+        assert!((margins.phase_crossover.unwrap() - 2.0_f64.sqrt()).abs() < 1e-2);
+        assert!((margins.gain_margin.unwrap() - 20.0 * 6.0_f64.log10()).abs() < 1e-2);
+
+        // ゲイン交差周波数 wc は wc^2 (wc^2 + 1) (wc^2 + 4) = 1 の解
+        let wc = margins.gain_crossover.unwrap();
+        assert!((wc * wc * (wc * wc + 1.0) * (wc * wc + 4.0) - 1.0).abs() < 1e-2);
+        let pm = 90.0 - (wc.atan() + (wc / 2.0).atan()) * 180.0 / PI;
+        assert!((margins.phase_margin.unwrap() - pm).abs() < 1e-2);
+    }
+
+    #[test]
+    fn logspace_checks_range() {
+        let w = logspace(1.0, 100.0, 3).unwrap();
+        assert!((w[1] - 10.0).abs() < 1e-12);
+        assert!(logspace(0.0, 1.0, 10).is_err());
+        assert!(logspace(1.0, 10.0, 1).is_err());
+    }
+}
@@ -0,0 +1,482 @@
+/* 信号フローグラフ */
+// 名前付きのブロックと入出力ポートを結線し、全ブロックの状態をまとめて積分する
+// ポートは "ブロック名.ポート名"、グラフの外部入力は "入力名" で指定する
+
+extern crate nalgebra as na;
+use na::DMatrix;
+
+use super::simmodel::{*};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Endpoint {
+    Input(usize),           // グラフの外部入力
+    Block(usize, usize),    // (ブロック番号, 出力ポート番号)
+}
+
+#[derive(Debug, Clone)]
+struct Wire {
+    from: Endpoint,         // 信号の出どころ
+    to: (usize, usize),     // (ブロック番号, 入力ポート番号)
+    name: String,           // 記録するときの信号名
+}
+
+/* ブロックの出力と入力をまとめたもの（calc_signalsの結果） */
+struct GraphSignals {
+    outputs: Vec<DMatrix<f64>>, // 各ブロックの出力
+    inputs: Vec<DMatrix<f64>>,  // 各ブロックの入力
+}
+
+/* 信号フローグラフの組み立て
+   ブロック・入出力を追加して結線し、buildで実行できるSignalGraphにする */
+pub struct SignalGraphBuilder {
+    blocks: Vec<(String, Box<dyn Model>)>,  // (ブロック名, モデル)
+    inputs: Vec<String>,                    // 外部入力の名前
+    outputs: Vec<(String, Endpoint)>,       // 外部出力の名前と接続元
+    wires: Vec<Wire>,
+}
+
+/* 信号フローグラフ全体を1つのモデルとしてSimulatorで実行する（SignalGraphBuilder::buildで作る）
+   各ブロックの calc_nextstate は呼ばれず、積分には slopefunc_u と outputfunc が使われる
+   積分で変化しない状態（離散時間のブロックなど）は、積分の後に各ブロックの update_discrete で進める */
+pub struct SignalGraph {
+    blocks: Vec<(String, Box<dyn Model>)>,  // (ブロック名, モデル)
+    inputs: Vec<String>,                    // 外部入力の名前
+    outputs: Vec<(String, Endpoint)>,       // 外部出力の名前と接続元
+    wires: Vec<Wire>,
+    order: Vec<usize>,                      // 出力を計算するブロックの順番
+    input_dependent: Vec<bool>,             // ブロックの出力が外部入力に直接依存するか
+    offsets: Vec<usize>,                    // 結合した状態ベクトルでの各ブロックの開始位置
+    t: f64,
+    x: DMatrix<f64>,
+    u: DMatrix<f64>,
+    step_control: StepControl,              // 可変刻みのソルバの状態
+}
+
+impl SignalGraphBuilder {
+    pub fn new() -> Self {
+        Self {
+            blocks: Vec::new(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            wires: Vec::new(),
+        }
+    }
+
+    pub fn add_block(&mut self, name: &str, block: Box<dyn Model>) -> Result<(), &'static str> {
+        if name.contains('.') {
+            return Err("ブロック名に'.'は使えません。");
+        }
+        if self.blocks.iter().any(|(n, _)| n == name) || self.inputs.iter().any(|n| n == name) {
+            return Err("同じ名前のブロックまたは入力が既にあります。");
+        }
+
+        self.blocks.push((name.to_string(), block));
+        Ok(())
+    }
+
+    pub fn add_input(&mut self, name: &str) -> Result<(), &'static str> {
+        if name.contains('.') {
+            return Err("入力名に'.'は使えません。");
+        }
+        if self.blocks.iter().any(|(n, _)| n == name) || self.inputs.iter().any(|n| n == name) {
+            return Err("同じ名前のブロックまたは入力が既にあります。");
+        }
+
+        self.inputs.push(name.to_string());
+        Ok(())
+    }
+
+    /* 外部出力を追加する fromは "ブロック名.出力ポート名" または外部入力名 */
+    pub fn add_output(&mut self, name: &str, from: &str) -> Result<(), &'static str> {
+        if self.outputs.iter().any(|(n, _)| n == name) || self.inputs.iter().any(|n| n == name) {
+            return Err("同じ名前の入力または出力が既にあります。");
+        }
+
+        let endpoint = self.find_source(from)?;
+        self.outputs.push((name.to_string(), endpoint));
+        Ok(())
+    }
+
+    /* 結線する fromは "ブロック名.出力ポート名" または外部入力名、toは "ブロック名.入力ポート名" */
+    pub fn connect(&mut self, from: &str, to: &str) -> Result<(), &'static str> {
+        let endpoint = self.find_source(from)?;
+        let dest = self.find_dest(to)?;
+
+        if self.wires.iter().any(|w| w.to == dest) {
+            return Err("この入力ポートには既に結線されています。");
+        }
+
+        self.wires.push(Wire { from: endpoint, to: dest, name: format!("{}->{}", from, to) });
+        Ok(())
+    }
+
+    /* 結線を確定し、出力の計算順序を決めたSignalGraphを返す
+       直達項を持つブロックだけを通る閉路があれば代数ループとしてエラーにする */
+    pub fn build(self) -> Result<SignalGraph, &'static str> {
+        let nblock = self.blocks.len();
+
+        // 全ての入力ポートが結線されているか確認
+        for (b, (_, block)) in self.blocks.iter().enumerate() {
+            for p in 0..block.get_input_dim() {
+                if !self.wires.iter().any(|w| w.to == (b, p)) {
+                    return Err("結線されていない入力ポートがあります。");
+                }
+            }
+        }
+
+        // 直達項を持つブロックは、入力元のブロックの出力を先に計算する必要がある（カーンのアルゴリズムで順序付け）
+        let mut indegree = vec![0; nblock];
+        let mut edges: Vec<Vec<usize>> = vec![Vec::new(); nblock];
+        for w in self.wires.iter() {
+            if let Endpoint::Block(src, _) = w.from {
+                let dst = w.to.0;
+                if self.blocks[dst].1.has_feedthrough() {
+                    edges[src].push(dst);
+                    indegree[dst] += 1;
+                }
+            }
+        }
+
+        let mut order = Vec::with_capacity(nblock);
+        let mut queue = (0..nblock).filter(|b| indegree[*b] == 0).collect::<Vec<usize>>();
+        while let Some(b) = queue.pop() {
+            order.push(b);
+            for dst in edges[b].iter() {
+                indegree[*dst] -= 1;
+                if indegree[*dst] == 0 {
+                    queue.push(*dst);
+                }
+            }
+        }
+        if order.len() != nblock {
+            return Err("直達項を持つブロックによる代数ループがあります。");
+        }
+
+        // 外部入力に直接依存する出力を調べる（グラフ全体の直達項の判定に使う）
+        let mut input_dependent = vec![false; nblock];
+        for b in order.iter() {
+            if self.blocks[*b].1.has_feedthrough() {
+                input_dependent[*b] = self.wires.iter().filter(|w| w.to.0 == *b).any(|w| match w.from {
+                    Endpoint::Input(_) => true,
+                    Endpoint::Block(src, _) => input_dependent[src],
+                });
+            }
+        }
+
+        // 状態ベクトルを結合する
+        let mut offsets = Vec::with_capacity(nblock);
+        let mut states = Vec::new();
+        for (_, block) in self.blocks.iter() {
+            offsets.push(states.len());
+            states.extend(block.get_state().iter());
+        }
+
+        let u = DMatrix::from_element(self.inputs.len(), 1, 0.0);
+        let mut graph = SignalGraph {
+            blocks: self.blocks,
+            inputs: self.inputs,
+            outputs: self.outputs,
+            wires: self.wires,
+            order: order,
+            input_dependent: input_dependent,
+            offsets: offsets,
+            t: 0.0,
+            x: DMatrix::from_column_slice(states.len(), 1, &states),
+            u: u,
+            step_control: StepControl::default(),
+        };
+        graph.update_signals();
+        Ok(graph)
+    }
+
+    fn find_source(&self, name: &str) -> Result<Endpoint, &'static str> {
+        match name.split_once('.') {
+            None => {
+                let i = self.inputs.iter().position(|n| n == name).ok_or("外部入力が見つかりません。")?;
+                Ok(Endpoint::Input(i))
+            },
+            Some((bname, pname)) => {
+                let b = self.blocks.iter().position(|(n, _)| n == bname).ok_or("ブロックが見つかりません。")?;
+                let p = self.blocks[b].1.get_output_names().iter().position(|n| n == pname).ok_or("出力ポートが見つかりません。")?;
+                Ok(Endpoint::Block(b, p))
+            },
+        }
+    }
+
+    fn find_dest(&self, name: &str) -> Result<(usize, usize), &'static str> {
+        let (bname, pname) = name.split_once('.').ok_or("入力ポートは \"ブロック名.ポート名\" で指定してください。")?;
+        let b = self.blocks.iter().position(|(n, _)| n == bname).ok_or("ブロックが見つかりません。")?;
+        let p = self.blocks[b].1.get_input_names().iter().position(|n| n == pname).ok_or("入力ポートが見つかりません。")?;
+        Ok((b, p))
+    }
+}
+
+impl SignalGraph {
+
+    fn block_state(&self, b: usize, x: &DMatrix<f64>) -> DMatrix<f64> {
+        let n = self.blocks[b].1.get_state().nrows();
+        x.rows(self.offsets[b], n).into_owned()
+    }
+
+    fn endpoint_value(&self, endpoint: &Endpoint, u: &DMatrix<f64>, outputs: &[DMatrix<f64>]) -> f64 {
+        match endpoint {
+            Endpoint::Input(i) => u[*i],
+            Endpoint::Block(b, p) => outputs[*b][*p],
+        }
+    }
+
+    fn gather_input(&self, b: usize, u: &DMatrix<f64>, outputs: &[DMatrix<f64>]) -> DMatrix<f64> {
+        let mut ub = DMatrix::from_element(self.blocks[b].1.get_input_dim(), 1, 0.0);
+        for w in self.wires.iter().filter(|w| w.to.0 == b) {
+            ub[w.to.1] = self.endpoint_value(&w.from, u, outputs);
+        }
+        ub
+    }
+
+    /* 結合状態xと外部入力uから全ブロックの出力と入力を求める */
+    fn calc_signals(&self, t: f64, x: &DMatrix<f64>, u: &DMatrix<f64>) -> GraphSignals {
+        let mut outputs = self.blocks.iter()
+            .map(|(_, block)| DMatrix::from_element(block.get_output_dim(), 1, 0.0))
+            .collect::<Vec<DMatrix<f64>>>();
+
+        for b in self.order.iter() {
+            let block = &self.blocks[*b].1;
+            let xb = self.block_state(*b, x);
+            let ub = if block.has_feedthrough() {
+                self.gather_input(*b, u, &outputs)
+            } else {
+                DMatrix::from_element(block.get_input_dim(), 1, 0.0) // 出力が入力に依存しないので仮の値でよい
+            };
+            outputs[*b] = block.outputfunc(t, &xb, &ub);
+        }
+
+        let inputs = (0..self.blocks.len())
+            .map(|b| self.gather_input(b, u, &outputs))
+            .collect::<Vec<DMatrix<f64>>>();
+
+        GraphSignals { outputs: outputs, inputs: inputs }
+    }
+
+    /* 各ブロックの状態と入力を現在の値にそろえる（記録される信号を一致させるため） */
+    fn update_signals(&mut self) {
+        let signals = self.calc_signals(self.t, &self.x, &self.u);
+        for b in 0..self.blocks.len() {
+            let xb = self.block_state(b, &self.x);
+            let block = &mut self.blocks[b].1;
+            block.set_state(xb);
+            block.set_input(signals.inputs[b].as_slice());
+        }
+    }
+}
+
+impl Model for SignalGraph {
+    fn slopefunc(&self, t: f64, x: &DMatrix<f64>) -> DMatrix<f64> {
+        self.slopefunc_u(t, x, &self.u)
+    }
+
+    fn get_signals_info(&self) -> Vec<String> {
+        let mut series = self.inputs.clone();
+        for (name, block) in self.blocks.iter() {
+            series.append(&mut block.get_signals_info().iter().map(|s| format!("{}.{}", name, s)).collect::<Vec<String>>());
+        }
+        series.append(&mut self.wires.iter().map(|w| w.name.clone()).collect::<Vec<String>>());
+        series.append(&mut self.outputs.iter().map(|(name, _)| name.clone()).collect::<Vec<String>>());
+        series
+    }
+
+    fn set_state(&mut self, newstate: DMatrix<f64>) {
+        self.x = newstate;
+        self.update_signals();
+    }
+
+    fn get_state(&self) -> &DMatrix<f64> {
+        &self.x
+    }
+
+    fn get_allsignals(&self) -> Vec<f64> {
+        let signals = self.calc_signals(self.t, &self.x, &self.u);
+        let mut result = self.u.iter().map(|u| *u).collect::<Vec<f64>>();
+        for (_, block) in self.blocks.iter() {
+            result.append(&mut block.get_allsignals());
+        }
+        result.append(&mut self.wires.iter().map(|w| self.endpoint_value(&w.from, &self.u, &signals.outputs)).collect::<Vec<f64>>());
+        result.append(&mut self.outputs.iter().map(|(_, e)| self.endpoint_value(e, &self.u, &signals.outputs)).collect::<Vec<f64>>());
+        result
+    }
+
+    fn calc_nextstate(&mut self, t: f64, delta_t : f64, solvertype: &SolverType) {
+        self.update_discrete(t, 0.0); // 時刻tちょうどのサンプル（時刻0など）はその時刻の入力で処理する
+        let newstate = solve_nextstate(self, t, delta_t, solvertype);
+        self.t = t + delta_t;
+        self.set_state(newstate);
+        self.update_discrete(t, delta_t);
+    }
+
+    fn update_discrete(&mut self, t: f64, delta_t: f64) {
+        for b in 0..self.blocks.len() {
+            let block = &mut self.blocks[b].1;
+            block.update_discrete(t, delta_t);
+            let n = block.get_state().nrows();
+            self.x.rows_mut(self.offsets[b], n).copy_from(block.get_state()); // 更新したブロックの状態を結合状態に戻す
+        }
+        self.update_signals();
+    }
+
+    fn get_input_dim(&self) -> usize {
+        self.inputs.len()
+    }
+
+    fn get_input(&self) -> DMatrix<f64> {
+        self.u.clone()
+    }
+
+    fn set_input(&mut self, u: &[f64]) {
+        self.u = DMatrix::from_column_slice(u.len(), 1, u);
+        self.update_signals();
+    }
+
+    fn slopefunc_u(&self, t: f64, x: &DMatrix<f64>, u: &DMatrix<f64>) -> DMatrix<f64> {
+        let signals = self.calc_signals(t, x, u);
+        let mut dx = DMatrix::from_element(x.nrows(), 1, 0.0);
+        for (b, (_, block)) in self.blocks.iter().enumerate() {
+            let xb = self.block_state(b, x);
+            let n = xb.nrows();
+            dx.rows_mut(self.offsets[b], n).copy_from(&block.slopefunc_u(t, &xb, &signals.inputs[b]));
+        }
+        dx
+    }
+
+    fn get_output_dim(&self) -> usize {
+        self.outputs.len()
+    }
+
+    fn outputfunc(&self, t: f64, x: &DMatrix<f64>, u: &DMatrix<f64>) -> DMatrix<f64> {
+        let signals = self.calc_signals(t, x, u);
+        let y = self.outputs.iter().map(|(_, e)| self.endpoint_value(e, u, &signals.outputs)).collect::<Vec<f64>>();
+        DMatrix::from_column_slice(y.len(), 1, &y)
+    }
+
+    fn has_feedthrough(&self) -> bool {
+        self.outputs.iter().any(|(_, e)| match e {
+            Endpoint::Input(_) => true,
+            Endpoint::Block(b, _) => self.input_dependent[*b],
+        })
+    }
+
+    fn get_step_control(&self) -> StepControl {
+        self.step_control
+    }
+
+    fn set_step_control(&mut self, control: StepControl) {
+        self.step_control = control;
+    }
+
+    fn get_input_names(&self) -> Vec<String> {
+        self.inputs.clone()
+    }
+
+    fn get_output_names(&self) -> Vec<String> {
+        self.outputs.iter().map(|(name, _)| name.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::simblock::{*};
+    use super::super::simpid::{*};
+
+    /* 1次遅れ k / (s + a) + d */
+    fn lag(k: f64, a: f64, d: f64) -> SpaceStateModel {
+        let mut model = SpaceStateModel::new(1, 1, 1);
+        model.set_mat_a(&[-a]).unwrap();
+        model.set_mat_b(&[k]).unwrap();
+        model.set_mat_c(&[1.0]).unwrap();
+        model.set_mat_d(&[d]).unwrap();
+        model
+    }
+
+    /* r → (+) → G → y、y → H → (-) の負帰還 */
+    fn feedback_graph(g: SpaceStateModel, h: SpaceStateModel) -> SignalGraphBuilder {
+        let mut sum = SpaceStateModel::new(1, 2, 1); // e = r - y_h （状態は使わない）
+        sum.set_mat_d(&[1.0, -1.0]).unwrap();
+
+        let mut graph = SignalGraphBuilder::new();
+        graph.add_input("r").unwrap();
+        graph.add_block("sum", Box::new(sum)).unwrap();
+        graph.add_block("G", Box::new(g)).unwrap();
+        graph.add_block("H", Box::new(h)).unwrap();
+        graph.connect("r", "sum.u_0").unwrap();
+        graph.connect("H.y_0", "sum.u_1").unwrap();
+        graph.connect("sum.y_0", "G.u_0").unwrap();
+        graph.connect("G.y_0", "H.u_0").unwrap();
+        graph.add_output("y", "G.y_0").unwrap();
+        graph
+    }
+
+    #[test]
+    fn graph_matches_linear_feedback() {
+        let (g, h) = (lag(1.0, 1.0, 0.0), lag(2.0, 2.0, 0.0));
+        let mut linear = g.feedback(&h, FeedbackType::Negative).unwrap();
+        let mut graph = feedback_graph(g, h).build().unwrap();
+        assert!(!graph.has_feedthrough());
+
+        linear.set_input(&[1.0]);
+        graph.set_input(&[1.0]);
+        for i in 0..300 {
+            linear.calc_nextstate(i as f64 * 0.01, 0.01, &SolverType::RungeKutta);
+            graph.calc_nextstate(i as f64 * 0.01, 0.01, &SolverType::RungeKutta);
+        }
+        let y_linear = linear.outputfunc(3.0, linear.get_state(), &linear.get_input())[0];
+        let y_graph = graph.outputfunc(3.0, graph.get_state(), &graph.get_input())[0];
+        assert!((y_linear - y_graph).abs() < 1e-9);
+    }
+
+    #[test]
+    fn graph_steps_discrete_blocks() {
+        // 離散時間のPI制御器で 1/(s+1) を制御すると、定常偏差なく目標値に追従する
+        let pid = DiscretePidController::new(PidParams::new(1.0, 2.0, 0.0, 0.0).unwrap(), 0.05).unwrap();
+        let mut graph = SignalGraphBuilder::new();
+        graph.add_input("r").unwrap();
+        graph.add_block("C", Box::new(pid)).unwrap();
+        graph.add_block("P", Box::new(lag(1.0, 1.0, 0.0))).unwrap();
+        graph.connect("r", "C.r").unwrap();
+        graph.connect("P.y_0", "C.y").unwrap();
+        graph.connect("C.u", "P.u_0").unwrap();
+        graph.add_output("y", "P.y_0").unwrap();
+        let mut graph = graph.build().unwrap();
+
+        graph.set_input(&[1.0]);
+        for i in 0..1000 {
+            graph.calc_nextstate(i as f64 * 0.01, 0.01, &SolverType::RungeKutta);
+        }
+        let y = graph.outputfunc(10.0, graph.get_state(), &graph.get_input())[0];
+        assert!((y - 1.0).abs() < 1e-3, "{}", y);
+        assert!((graph.get_state()[0] - 1.0).abs() < 1e-3); // 定常状態では積分項が操作量のすべてを受け持つ
+    }
+
+    #[test]
+    fn build_detects_algebraic_loop() {
+        // G と H の両方に直達項があると sum → G → H → sum が代数ループになる
+        assert!(feedback_graph(lag(1.0, 1.0, 0.5), lag(2.0, 2.0, 0.5)).build().is_err());
+        assert!(feedback_graph(lag(1.0, 1.0, 0.5), lag(2.0, 2.0, 0.0)).build().is_ok());
+    }
+
+    #[test]
+    fn build_rejects_unconnected_input() {
+        let mut graph = SignalGraphBuilder::new();
+        graph.add_block("G", Box::new(lag(1.0, 1.0, 0.0))).unwrap();
+        assert!(graph.build().is_err());
+    }
+
+    #[test]
+    fn builder_rejects_bad_names() {
+        let mut graph = SignalGraphBuilder::new();
+        graph.add_input("r").unwrap();
+        assert!(graph.add_input("r").is_err());
+        assert!(graph.add_block("a.b", Box::new(lag(1.0, 1.0, 0.0))).is_err());
+        graph.add_block("G", Box::new(lag(1.0, 1.0, 0.0))).unwrap();
+        assert!(graph.connect("r", "G.v").is_err());
+        assert!(graph.connect("G.y_1", "G.u_0").is_err());
+    }
+}
@@ -0,0 +1,387 @@
+/* マルチレートのハイブリッドシミュレーション */
+// 連続時間のプラントを細かい刻み（または可変刻み）で積分しながら、離散時間のコントローラをそれぞれのサンプル周期で実行する
+// コントローラの出力は次のサンプル時刻まで保持（0次ホールド）してプラントに加える
+
+use std::fs;
+use std::fs::File;
+use std::io::{Write, BufWriter};
+
+use std::collections::HashMap;
+
+extern crate nalgebra as na;
+use na::DMatrix;
+
+use super::{plot_timeseries};
+use super::simmodel::{*};
+use super::simsource::{*};
+use super::simdiscrete::{*};
+
+const EVENT_EPS: f64 = 1e-9;    // 同じ時刻とみなすイベントの間隔（刻み幅・サンプル周期に対する割合）
+const PLANT_NAME: &str = "plant"; // 結線でプラントを指す名前
+
+/* サンプル周期ごとに実行される離散時間のコントローラ */
+pub trait DiscreteController: Model {
+    fn get_sample_time(&self) -> f64;                               // サンプル周期
+    fn sample(&mut self, t: f64, u: &DMatrix<f64>) -> DMatrix<f64>; // サンプル時刻tに入力uを取り込み、次のサンプル時刻まで保持する出力を返す
+}
+
+/* 離散時間モデルは自身のサンプル数で更新するので、時刻tまでのサンプルを処理させて出力を求める
+   （HybridSimulatorからはサンプル時刻ちょうどに呼ばれるので、1回の呼び出しで1サンプルだけ進む） */
+pub fn sample_by_clock<M: Model>(model: &mut M, t: f64, u: &DMatrix<f64>) -> DMatrix<f64> {
+    model.set_input(u.as_slice());
+    model.calc_nextstate(t, 0.0, &SolverType::Euler); // ソルバは使われない
+    model.outputfunc(t, model.get_state(), &model.get_input())
+}
+
+impl DiscreteController for DiscreteSpaceStateModel {
+    fn get_sample_time(&self) -> f64 {
+        DiscreteSpaceStateModel::get_sample_time(self)
+    }
+
+    fn sample(&mut self, t: f64, u: &DMatrix<f64>) -> DMatrix<f64> {
+        sample_by_clock(self, t, u)
+    }
+}
+
+impl DiscreteController for DiscreteTransFuncModel {
+    fn get_sample_time(&self) -> f64 {
+        DiscreteTransFuncModel::get_sample_time(self)
+    }
+
+    fn sample(&mut self, t: f64, u: &DMatrix<f64>) -> DMatrix<f64> {
+        sample_by_clock(self, t, u)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Port {
+    Plant(usize),               // プラントの入力または出力ポート
+    Controller(usize, usize),   // (コントローラ番号, ポート番号)
+    Source(usize),              // 信号源（出どころとしてのみ使う）
+}
+
+/* 連続時間のプラントと複数の離散時間コントローラをまとめて実行するシミュレータ
+   delta_tごとの時刻とサンプル時刻を合わせたイベントの間でプラントを積分する（周期の比は整数でなくてよい）
+   記録はdelta_tごとの時刻に加え、サンプル時刻には更新前後の2点を同じ時刻で記録するので、離散信号が階段状になる */
+pub struct HybridSimulator<T>
+where T: Model
+{
+    simtime: f64,                                           // シミュレーション時間
+    delta_t: f64,                                           // 記録する時刻の間隔（プラントを積分する最大の刻み幅）
+    solvertype: SolverType,                                 // プラントの計算手法
+    plant: T,
+    controllers: Vec<(String, Box<dyn DiscreteController>)>,// (コントローラ名, コントローラ)
+    sources: Vec<(String, Box<dyn Source>)>,                // (信号名, 信号源)
+    wires: Vec<(Port, Port)>,                               // (出どころ, 行き先)
+    held: Vec<DMatrix<f64>>,                                // 各コントローラの保持している出力
+    seriesname: Vec<String>,                                // 記録する信号名（"time"が先頭）
+    simstorage: HashMap<String, Vec<f64>>,                  // 計測する信号名とデータ配列の組み合わせ
+}
+
+impl<T> HybridSimulator<T>
+where T: Model
+{
+    pub fn new(simtime: f64, delta_t: f64, solvertype: SolverType, plant: T) -> Self {
+        Self {
+            simtime: simtime,
+            delta_t: delta_t,
+            solvertype: solvertype,
+            plant: plant,
+            controllers: Vec::new(),
+            sources: Vec::new(),
+            wires: Vec::new(),
+            held: Vec::new(),
+            seriesname: Vec::new(),
+            simstorage: HashMap::new(),
+        }
+    }
+
+    /* コントローラを追加する（登録した順に実行するので、同じ時刻では前のコントローラの新しい出力が後のコントローラに渡る） */
+    pub fn add_controller(&mut self, name: &str, controller: Box<dyn DiscreteController>) -> Result<(), &'static str> {
+        self.check_name(name)?;
+        if controller.get_sample_time() <= 0.0 {
+            return Err("サンプル周期は正の値にしてください。");
+        }
+
+        self.controllers.push((name.to_string(), controller));
+        Ok(())
+    }
+
+    /* 時刻だけで値が決まる信号源（目標値など）を追加する */
+    pub fn add_source(&mut self, name: &str, source: Box<dyn Source>) -> Result<(), &'static str> {
+        self.check_name(name)?;
+        self.sources.push((name.to_string(), source));
+        Ok(())
+    }
+
+    /* 結線する fromは "plant.出力ポート名"・"コントローラ名.出力ポート名"・信号源の名前、
+       toは "plant.入力ポート名" または "コントローラ名.入力ポート名"
+       プラントの入力はイベントの間一定に保つ（時間変化する入力はSourcedModelでプラントに直接与える） */
+    pub fn connect(&mut self, from: &str, to: &str) -> Result<(), &'static str> {
+        let src = self.find_source(from)?;
+        let dest = self.find_dest(to)?;
+
+        if self.wires.iter().any(|(_, d)| *d == dest) {
+            return Err("この入力ポートには既に結線されています。");
+        }
+
+        self.wires.push((src, dest));
+        Ok(())
+    }
+
+    pub fn get_plant(&self) -> &T {
+        &self.plant
+    }
+
+    pub fn get_simdata(&self, signal: &str) -> Option<&Vec<f64>> { // 記録した信号の時系列（時刻は"time"）
+        self.simstorage.get(signal)
+    }
+
+    fn check_name(&self, name: &str) -> Result<(), &'static str> {
+        if name.contains('.') {
+            return Err("名前に'.'は使えません。");
+        }
+        if name == PLANT_NAME || name == "time"
+            || self.controllers.iter().any(|(n, _)| n == name) || self.sources.iter().any(|(n, _)| n == name) {
+            return Err("同じ名前のコントローラまたは信号源が既にあります。");
+        }
+        Ok(())
+    }
+
+    fn find_source(&self, name: &str) -> Result<Port, &'static str> {
+        match name.split_once('.') {
+            None => {
+                let s = self.sources.iter().position(|(n, _)| n == name).ok_or("信号源が見つかりません。")?;
+                Ok(Port::Source(s))
+            },
+            Some((bname, pname)) if bname == PLANT_NAME => {
+                let p = self.plant.get_output_names().iter().position(|n| n == pname).ok_or("出力ポートが見つかりません。")?;
+                Ok(Port::Plant(p))
+            },
+            Some((bname, pname)) => {
+                let c = self.controllers.iter().position(|(n, _)| n == bname).ok_or("コントローラが見つかりません。")?;
+                let p = self.controllers[c].1.get_output_names().iter().position(|n| n == pname).ok_or("出力ポートが見つかりません。")?;
+                Ok(Port::Controller(c, p))
+            },
+        }
+    }
+
+    fn find_dest(&self, name: &str) -> Result<Port, &'static str> {
+        let (bname, pname) = name.split_once('.').ok_or("入力ポートは \"名前.ポート名\" で指定してください。")?;
+        if bname == PLANT_NAME {
+            let p = self.plant.get_input_names().iter().position(|n| n == pname).ok_or("入力ポートが見つかりません。")?;
+            return Ok(Port::Plant(p));
+        }
+        let c = self.controllers.iter().position(|(n, _)| n == bname).ok_or("コントローラが見つかりません。")?;
+        let p = self.controllers[c].1.get_input_names().iter().position(|n| n == pname).ok_or("入力ポートが見つかりません。")?;
+        Ok(Port::Controller(c, p))
+    }
+
+    /* 結線の出どころの時刻tでの値（プラントの出力は現在の状態と入力から求める） */
+    fn port_value(&self, t: f64, port: &Port, plant_y: &DMatrix<f64>) -> f64 {
+        match port {
+            Port::Plant(p) => plant_y[*p],
+            Port::Controller(c, p) => self.held[*c][*p],
+            Port::Source(s) => self.sources[*s].1.get_value(t),
+        }
+    }
+
+    fn plant_output(&self, t: f64) -> DMatrix<f64> {
+        self.plant.outputfunc(t, self.plant.get_state(), &self.plant.get_input())
+    }
+
+    /* 時刻tにサンプル時刻を迎えたコントローラを登録順に実行する（実行したらtrue） */
+    fn sample_controllers(&mut self, t: f64, ticks: &mut [usize], eps: f64) -> bool {
+        let mut sampled = false;
+        let plant_y = self.plant_output(t);
+
+        for c in 0..self.controllers.len() {
+            let ts = self.controllers[c].1.get_sample_time();
+            let t_tick = ticks[c] as f64 * ts; // 誤差が積み重ならないように番号から求める
+            if t_tick > t + eps {
+                continue;
+            }
+
+            let mut u = DMatrix::from_element(self.controllers[c].1.get_input_dim(), 1, 0.0);
+            for (src, dest) in self.wires.iter() {
+                if let Port::Controller(d, p) = dest {
+                    if *d == c {
+                        u[*p] = self.port_value(t, src, &plant_y);
+                    }
+                }
+            }
+
+            self.held[c] = self.controllers[c].1.sample(t_tick, &u);
+            ticks[c] += 1;
+            sampled = true;
+        }
+        sampled
+    }
+
+    /* 保持しているコントローラの出力と信号源の値をプラントの入力に反映する */
+    fn update_plant_input(&mut self, t: f64) {
+        let plant_y = self.plant_output(t);
+        let mut u = self.plant.get_input();
+        for (src, dest) in self.wires.iter() {
+            if let Port::Plant(p) = dest {
+                u[*p] = self.port_value(t, src, &plant_y);
+            }
+        }
+        self.plant.set_input(u.as_slice());
+    }
+
+    fn record(&mut self, t: f64) {
+        let mut values = vec![t];
+        values.append(&mut self.plant.get_allsignals());
+        for (_, controller) in self.controllers.iter() {
+            values.append(&mut controller.get_allsignals());
+        }
+        values.append(&mut self.sources.iter().map(|(_, s)| s.get_value(t)).collect::<Vec<f64>>());
+
+        for (name, v) in self.seriesname.iter().zip(values.iter()) {
+            self.simstorage.get_mut(name).unwrap().push(*v);
+        }
+    }
+
+    pub fn run_sim(&mut self) -> Result<(), &'static str> {
+        if self.delta_t <= 0.0 || self.simtime < self.delta_t {
+            return Err("時間は 0 < delta_t <= simtime としてください。");
+        }
+        for (c, (_, controller)) in self.controllers.iter().enumerate() {
+            for p in 0..controller.get_input_dim() {
+                if !self.wires.iter().any(|(_, d)| *d == Port::Controller(c, p)) {
+                    return Err("結線されていないコントローラの入力ポートがあります。");
+                }
+            }
+        }
+
+        // 記録する信号名 プラントは "plant.信号名"、コントローラは "コントローラ名.信号名"
+        let mut seriesname = vec![String::from("time")];
+        seriesname.append(&mut self.plant.get_signals_info().iter().map(|s| format!("{}.{}", PLANT_NAME, s)).collect::<Vec<String>>());
+        for (name, controller) in self.controllers.iter() {
+            seriesname.append(&mut controller.get_signals_info().iter().map(|s| format!("{}.{}", name, s)).collect::<Vec<String>>());
+        }
+        seriesname.append(&mut self.sources.iter().map(|(name, _)| name.clone()).collect::<Vec<String>>());
+        self.simstorage = seriesname.iter().map(|name| (name.clone(), Vec::new())).collect();
+        self.seriesname = seriesname;
+        self.held = self.controllers.iter()
+            .map(|(_, controller)| DMatrix::from_element(controller.get_output_dim(), 1, 0.0))
+            .collect();
+
+        let min_period = self.controllers.iter().fold(self.delta_t, |m, (_, c)| m.min(c.get_sample_time()));
+        let eps = EVENT_EPS * min_period;
+        let nsteps = (self.simtime / self.delta_t + 0.5) as usize;
+        let t_end = nsteps as f64 * self.delta_t;
+        let mut ticks = vec![0usize; self.controllers.len()];
+
+        // 時刻0のサンプル
+        self.sample_controllers(0.0, &mut ticks, eps);
+        self.update_plant_input(0.0);
+        self.record(0.0);
+
+        let mut t = 0.0;
+        let mut step = 0;
+        loop {
+            // 次のイベント（記録時刻またはいずれかのサンプル時刻）
+            let next_log = (step + 1) as f64 * self.delta_t;
+            let next_tick = self.controllers.iter().zip(ticks.iter())
+                .map(|((_, c), k)| *k as f64 * c.get_sample_time())
+                .fold(f64::INFINITY, f64::min);
+            let t_next = next_log.min(next_tick);
+            if t_next > t_end + eps {
+                break;
+            }
+
+            if t_next > t {
+                self.plant.calc_nextstate(t, t_next - t, &self.solvertype);
+            }
+            t = t_next;
+            if (next_log - t).abs() <= eps {
+                step += 1;
+            }
+
+            let due = self.controllers.iter().zip(ticks.iter())
+                .any(|((_, c), k)| *k as f64 * c.get_sample_time() <= t + eps);
+            if due {
+                self.record(t); // 更新前の値（階段の角）
+                self.sample_controllers(t, &mut ticks, eps);
+            }
+            self.update_plant_input(t);
+            self.record(t);
+        }
+
+        Ok(())
+    }
+
+    pub fn export_sim(&self, filepath: &str) { // csv形式として吐き出す
+        let mut file = BufWriter::new(File::create(filepath).unwrap());
+        writeln!(file, "{}", self.seriesname.join(",")).unwrap();
+
+        let nrows = self.simstorage.get("time").map_or(0, |v| v.len());
+        for idx in 0..nrows {
+            let line = self.seriesname.iter().map(|name| self.simstorage.get(name).unwrap()[idx].to_string())
+                                        .collect::<Vec<String>>().join(",");
+            writeln!(file, "{}", line).unwrap();
+        }
+    }
+
+    pub fn timeplot(&self, dirname : &str, pltsize: (u32, u32)) {
+        match fs::create_dir(dirname) {
+            Err(e) => println!("! {:?}", e.kind()),
+            Ok(_) => {},
+        }
+
+        let timeaxis = self.simstorage.get("time").unwrap();
+        for signal in self.seriesname.iter().skip(1) {
+            let filename = format!("./{}/{}.png", dirname, signal);
+            plot_timeseries(&filename, signal, timeaxis, self.simstorage.get(signal).unwrap(), self.simtime, pltsize);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* プラント dx/dt = -x + u, y = x */
+    fn plant(x0: f64) -> SpaceStateModel {
+        let mut model = SpaceStateModel::new(1, 1, 1);
+        model.set_mat_a(&[-1.0]).unwrap();
+        model.set_mat_b(&[1.0]).unwrap();
+        model.set_mat_c(&[1.0]).unwrap();
+        model.init_state(&[x0]).unwrap();
+        model
+    }
+
+    /* 静的ゲイン u[k] = gain * y[k] の離散時間コントローラ */
+    fn gain(gain: f64, ts: f64) -> DiscreteSpaceStateModel {
+        DiscreteSpaceStateModel::from_matrices(
+            DMatrix::zeros(1, 1), DMatrix::zeros(1, 1), DMatrix::zeros(1, 1), DMatrix::from_element(1, 1, gain), ts).unwrap()
+    }
+
+    #[test]
+    fn sampled_feedback_matches_discrete_closed_loop() {
+        // 記録の間隔0.04とサンプル周期0.1は整数比でない
+        let (k, ts) = (2.0, 0.1);
+        let mut sim = HybridSimulator::new(1.0, 0.04, SolverType::Exact, plant(1.0));
+        sim.add_controller("K", Box::new(gain(-k, ts))).unwrap();
+        sim.connect("plant.y_0", "K.u_0").unwrap();
+        sim.connect("K.y_0", "plant.u_0").unwrap();
+        sim.run_sim().unwrap();
+
+        // x[k+1] = (e^(-T) - K (1 - e^(-T))) x[k]
+        let p = (-ts).exp();
+        let expected = (p - k * (1.0 - p)).powi(10);
+        let x = sim.get_simdata("plant.x_0").unwrap();
+        assert!((x.last().unwrap() - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn hybrid_simulator_checks_wiring() {
+        let mut sim = HybridSimulator::new(1.0, 0.01, SolverType::RungeKutta, plant(0.0));
+        sim.add_controller("K", Box::new(gain(1.0, 0.1))).unwrap();
+        assert!(sim.add_controller("K", Box::new(gain(1.0, 0.1))).is_err());
+        assert!(sim.connect("plant.y_0", "K.u_1").is_err());
+        assert!(sim.run_sim().is_err()); // K.u_0 が未結線
+    }
+}
@@ -0,0 +1,79 @@
+/* 非線形モデルの線形化 */
+// 動作点 (x0, u0) のまわりのヤコビ行列を中心差分で求めて状態空間モデルにする
+// 動作点（平衡点）は simsteady の trim で求められる
+
+extern crate nalgebra as na;
+use na::DMatrix;
+
+use super::simmodel::{*};
+
+fn check_point<M: Model + ?Sized>(model: &M, x0: &[f64], u0: &[f64]) -> Result<(), &'static str> {
+    if x0.len() != model.get_state().nrows() {
+        return Err("状態ベクトルの次数が違います。");
+    }
+    if u0.len() != model.get_input_dim() {
+        return Err("入力ベクトルの次数が違います。");
+    }
+    Ok(())
+}
+
+/* 時刻tの動作点 (x0, u0) のまわりで線形化した状態空間モデル
+   A = ∂f/∂x, B = ∂f/∂u, C = ∂g/∂x, D = ∂g/∂u （f は slopefunc_u、g は outputfunc）
+   得られるモデルの状態・入力・出力は動作点からの偏差になる */
+pub fn linearize<M: Model + ?Sized>(model: &M, t: f64, x0: &[f64], u0: &[f64]) -> Result<SpaceStateModel, &'static str> {
+    check_point(model, x0, u0)?;
+    let x0 = DMatrix::from_column_slice(x0.len(), 1, x0);
+    let u0 = DMatrix::from_column_slice(u0.len(), 1, u0);
+
+    let mat_a = central_jacobian(|x| model.slopefunc_u(t, x, &u0), &x0);
+    let mat_b = central_jacobian(|u| model.slopefunc_u(t, &x0, u), &u0);
+    let mat_c = central_jacobian(|x| model.outputfunc(t, x, &u0), &x0);
+    let mat_d = central_jacobian(|u| model.outputfunc(t, &x0, u), &u0);
+
+    if [&mat_a, &mat_b, &mat_c, &mat_d].iter().any(|m| m.iter().any(|v| !v.is_finite())) {
+        return Err("動作点でヤコビ行列が有限の値になりません。");
+    }
+
+    SpaceStateModel::from_matrices(mat_a, mat_b, mat_c, mat_d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::simfnmodel::{*};
+
+    fn assert_matrix(m: &DMatrix<f64>, expected: &[f64]) {
+        assert_eq!(m.len(), expected.len());
+        assert!(m.iter().zip(expected.iter()).all(|(a, b)| (a - b).abs() < 1e-7), "{}", m);
+    }
+
+    #[test]
+    fn linearize_pendulum() {
+        // 振り子 dθ/dt = ω, dω/dt = -g/l sinθ + u、出力 y = θ
+        let mut pendulum = FnModel::new(&[0.0, 0.0], 1, |_t, x, u| DMatrix::from_column_slice(2, 1, &[x[1], -9.8 * x[0].sin() + u[0]])).unwrap();
+        pendulum.set_output(1, |_t, x, _u| x.rows(0, 1).into_owned()).unwrap();
+
+        // 下の平衡点と、倒立の平衡点（sin の傾きの符号が変わる）
+        let lin = linearize(&pendulum, 0.0, &[0.0, 0.0], &[0.0]).unwrap();
+        assert_matrix(lin.get_mat_a(), &[0.0, -9.8, 1.0, 0.0]);
+        assert_matrix(lin.get_mat_b(), &[0.0, 1.0]);
+        assert_matrix(lin.get_mat_c(), &[1.0, 0.0]);
+        assert_matrix(lin.get_mat_d(), &[0.0]);
+
+        let lin = linearize(&pendulum, 0.0, &[std::f64::consts::PI, 0.0], &[0.0]).unwrap();
+        assert_matrix(lin.get_mat_a(), &[0.0, 9.8, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn linearize_reproduces_linear_model() {
+        let model = SpaceStateModel::from_tf(&[2.0, 1.0], &[1.0, 3.0, 2.0]).unwrap();
+        let lin = linearize(&model, 0.0, &[0.5, -1.0], &[3.0]).unwrap();
+        assert_matrix(lin.get_mat_a(), model.get_mat_a().as_slice());
+        assert_matrix(lin.get_mat_b(), model.get_mat_b().as_slice());
+        assert_matrix(lin.get_mat_c(), model.get_mat_c().as_slice());
+        assert_matrix(lin.get_mat_d(), model.get_mat_d().as_slice());
+
+        assert!(linearize(&model, 0.0, &[0.0], &[0.0]).is_err());
+        assert!(linearize(&model, 0.0, &[0.0, 0.0], &[]).is_err());
+    }
+}
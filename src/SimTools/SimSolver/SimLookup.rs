@@ -0,0 +1,404 @@
+/* ルックアップテーブル */
+// 1次元・2次元のテーブルの補間（線形・最近傍・3次スプライン）と範囲外の扱い（端の値で止める・線形外挿）
+// テーブル自体は slopefunc の中から eval で引け、ブロックとしてループに入れることもできる
+
+use std::cmp::Ordering;
+use std::fs;
+
+extern crate nalgebra as na;
+use na::DMatrix;
+
+use super::simmodel::{*};
+use super::simnonlinear::{*};
+
+/* 補間の方法 */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    Linear,         // 線形補間
+    Nearest,        // 最も近い点の値
+    CubicSpline,    // 自然3次スプライン（両端の2階微分が0）
+}
+
+/* 範囲外の扱い */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Extrapolation {
+    Clamp,  // 端の値で止める
+    Linear, // 端の傾きで延長する
+}
+
+fn check_breakpoints(x: &[f64]) -> Result<(), &'static str> {
+    if x.len() < 2 {
+        return Err("ブレークポイントは2点以上にしてください。");
+    }
+    if x.windows(2).any(|w| w[0].partial_cmp(&w[1]) != Some(Ordering::Less)) { // NaNも弾く
+        return Err("ブレークポイントは狭義単調増加にしてください。");
+    }
+    Ok(())
+}
+
+/* 自然3次スプラインの各点の2階微分（三重対角方程式をトーマス法で解く） */
+fn spline_second_derivs(x: &[f64], y: &[f64]) -> Vec<f64> {
+    let n = x.len();
+    let mut m = vec![0.0; n];
+    if n < 3 {
+        return m;
+    }
+
+    let mut diag = vec![0.0; n];
+    let mut rhs = vec![0.0; n];
+    let h = x.windows(2).map(|w| w[1] - w[0]).collect::<Vec<f64>>();
+    for i in 1..n - 1 {
+        diag[i] = 2.0 * (h[i - 1] + h[i]);
+        rhs[i] = 6.0 * ((y[i + 1] - y[i]) / h[i] - (y[i] - y[i - 1]) / h[i - 1]);
+    }
+
+    // 前進消去（副対角は h[i-1], h[i]）
+    for i in 2..n - 1 {
+        let w = h[i - 1] / diag[i - 1];
+        diag[i] -= w * h[i - 1];
+        rhs[i] -= w * rhs[i - 1];
+    }
+    for i in (1..n - 1).rev() {
+        m[i] = (rhs[i] - h[i] * m[i + 1]) / diag[i]; // m[n-1] = 0
+    }
+    m
+}
+
+/* 区間の番号 i （x[i] <= v < x[i+1]、範囲外は端の区間） */
+fn find_segment(x: &[f64], v: f64) -> usize {
+    x.partition_point(|xi| *xi <= v).max(1).min(x.len() - 1) - 1
+}
+
+/* 1次元の補間（m はスプラインのときだけ使う2階微分） */
+fn interp1(x: &[f64], y: &[f64], m: &[f64], interp: Interpolation, extrap: Extrapolation, v: f64) -> f64 {
+    let n = x.len();
+    let (x0, xn) = (x[0], x[n - 1]);
+
+    if v < x0 || v > xn {
+        let (edge, xe, ye) = if v < x0 { (0, x0, y[0]) } else { (n - 2, xn, y[n - 1]) };
+        return match extrap {
+            Extrapolation::Clamp => ye,
+            Extrapolation::Linear => {
+                let h = x[edge + 1] - x[edge];
+                let mut slope = (y[edge + 1] - y[edge]) / h;
+                if interp == Interpolation::CubicSpline { // 端でのスプラインの傾き
+                    slope += if v < x0 { -h * (2.0 * m[0] + m[1]) / 6.0 } else { h * (m[n - 2] + 2.0 * m[n - 1]) / 6.0 };
+                }
+                ye + slope * (v - xe)
+            },
+        };
+    }
+
+    let i = find_segment(x, v);
+    let h = x[i + 1] - x[i];
+    let r = (v - x[i]) / h;
+    match interp {
+        Interpolation::Linear => y[i] + r * (y[i + 1] - y[i]),
+        Interpolation::Nearest => if r < 0.5 { y[i] } else { y[i + 1] },
+        Interpolation::CubicSpline => {
+            let (a, b) = (1.0 - r, r);
+            a * y[i] + b * y[i + 1] + ((a * a * a - a) * m[i] + (b * b * b - b) * m[i + 1]) * h * h / 6.0
+        },
+    }
+}
+
+/* CSVファイルを行ごとのセルに分けて読む（空行は飛ばす） */
+fn read_csv(filepath: &str) -> Result<Vec<Vec<String>>, &'static str> {
+    let text = fs::read_to_string(filepath).map_err(|_| "CSVファイルを読み込めません。")?;
+    Ok(text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.split(',').map(|s| s.trim().to_string()).collect())
+        .collect())
+}
+
+fn parse_row(cells: &[String]) -> Result<Vec<f64>, &'static str> {
+    cells.iter().map(|s| s.parse::<f64>().map_err(|_| "CSVファイルに数値でない値があります。")).collect()
+}
+
+/* 1次元のルックアップテーブル y = f(x) */
+#[derive(Debug, Clone)]
+pub struct LookupTable1D {
+    x: Vec<f64>,    // ブレークポイント（昇順）
+    y: Vec<f64>,    // 各ブレークポイントでの値
+    m: Vec<f64>,    // スプラインの2階微分
+    interp: Interpolation,
+    extrap: Extrapolation,
+}
+
+impl LookupTable1D {
+    pub fn new(x: &[f64], y: &[f64], interp: Interpolation, extrap: Extrapolation) -> Result<Self, &'static str> {
+        check_breakpoints(x)?;
+        if x.len() != y.len() {
+            return Err("ブレークポイントと値の点数が違います。");
+        }
+        Ok(Self {
+            x: x.to_vec(),
+            y: y.to_vec(),
+            m: spline_second_derivs(x, y),
+            interp: interp,
+            extrap: extrap,
+        })
+    }
+
+    /* "x,y" の2列のCSVファイルから読み込む（1行目は見出しでもよい） */
+    pub fn from_csv(filepath: &str, interp: Interpolation, extrap: Extrapolation) -> Result<Self, &'static str> {
+        let rows = read_csv(filepath)?;
+        let mut x = Vec::with_capacity(rows.len());
+        let mut y = Vec::with_capacity(rows.len());
+        for (i, row) in rows.iter().enumerate() {
+            if row.len() < 2 {
+                return Err("CSVファイルの列数が足りません。");
+            }
+            match parse_row(&row[0..2]) {
+                Ok(v) => {
+                    x.push(v[0]);
+                    y.push(v[1]);
+                },
+                Err(_) if i == 0 => {}, // 見出し
+                Err(e) => return Err(e),
+            }
+        }
+        LookupTable1D::new(&x, &y, interp, extrap)
+    }
+
+    pub fn eval(&self, x: f64) -> f64 {
+        interp1(&self.x, &self.y, &self.m, self.interp, self.extrap, x)
+    }
+}
+
+impl Nonlinearity for LookupTable1D { // NonlinearBlock に入れると1入力1出力のブロックになる
+    fn eval(&self, _t: f64, u: f64) -> f64 {
+        LookupTable1D::eval(self, u)
+    }
+}
+
+/* 2次元のルックアップテーブル z = f(x, y)（z[(i, j)] が (x[i], y[j]) での値）
+   スプラインはy方向に補間してからx方向に補間する */
+#[derive(Debug, Clone)]
+pub struct LookupTable2D {
+    x: Vec<f64>,
+    y: Vec<f64>,
+    z: DMatrix<f64>,
+    m: Vec<Vec<f64>>,   // 各行（xを固定したy方向）のスプラインの2階微分
+    interp: Interpolation,
+    extrap: Extrapolation,
+}
+
+impl LookupTable2D {
+    pub fn new(x: &[f64], y: &[f64], z: DMatrix<f64>, interp: Interpolation, extrap: Extrapolation) -> Result<Self, &'static str> {
+        check_breakpoints(x)?;
+        check_breakpoints(y)?;
+        if z.shape() != (x.len(), y.len()) {
+            return Err("テーブルのサイズがブレークポイントの点数と違います。");
+        }
+        let m = (0..x.len())
+            .map(|i| spline_second_derivs(y, z.row(i).transpose().as_slice()))
+            .collect();
+        Ok(Self {
+            x: x.to_vec(),
+            y: y.to_vec(),
+            z: z,
+            m: m,
+            interp: interp,
+            extrap: extrap,
+        })
+    }
+
+    /* 1行目にyのブレークポイント、各行の1列目にxのブレークポイントを並べたCSVファイルから読み込む（左上のセルは無視する） */
+    pub fn from_csv(filepath: &str, interp: Interpolation, extrap: Extrapolation) -> Result<Self, &'static str> {
+        let rows = read_csv(filepath)?;
+        if rows.len() < 3 || rows[0].len() < 3 {
+            return Err("CSVファイルの行数または列数が足りません。");
+        }
+        let y = parse_row(&rows[0][1..])?;
+
+        let mut x = Vec::with_capacity(rows.len() - 1);
+        let mut z = Vec::with_capacity((rows.len() - 1) * y.len());
+        for row in rows[1..].iter() {
+            if row.len() != y.len() + 1 {
+                return Err("CSVファイルの列数がそろっていません。");
+            }
+            let v = parse_row(row)?;
+            x.push(v[0]);
+            z.extend_from_slice(&v[1..]);
+        }
+        LookupTable2D::new(&x, &y, DMatrix::from_row_slice(x.len(), y.len(), &z), interp, extrap)
+    }
+
+    pub fn eval(&self, x: f64, y: f64) -> f64 {
+        // y方向に各行を補間し、その値をx方向に補間する
+        let column = (0..self.x.len())
+            .map(|i| interp1(&self.y, self.z.row(i).transpose().as_slice(), &self.m[i], self.interp, self.extrap, y))
+            .collect::<Vec<f64>>();
+        let m = if self.interp == Interpolation::CubicSpline { spline_second_derivs(&self.x, &column) } else { vec![0.0; self.x.len()] };
+        interp1(&self.x, &column, &m, self.interp, self.extrap, x)
+    }
+}
+
+/* 2次元のルックアップテーブルのブロック（入力 [u_0, u_1] = [x, y]、出力 y = f(x, y)） */
+#[derive(Debug, Clone)]
+pub struct LookupBlock2D {
+    table: LookupTable2D,
+    x: DMatrix<f64>,    // 空の状態ベクトル
+    u: DMatrix<f64>,
+}
+
+impl LookupBlock2D {
+    pub fn new(table: LookupTable2D) -> Self {
+        Self {
+            table: table,
+            x: DMatrix::from_element(0, 1, 0.0),
+            u: DMatrix::from_element(2, 1, 0.0),
+        }
+    }
+
+    pub fn get_table(&self) -> &LookupTable2D {
+        &self.table
+    }
+}
+
+impl Model for LookupBlock2D {
+    fn slopefunc(&self, _t: f64, x: &DMatrix<f64>) -> DMatrix<f64> {
+        DMatrix::zeros(x.nrows(), 1)
+    }
+
+    fn get_signals_info(&self) -> Vec<String> {
+        vec!["u_0".to_string(), "u_1".to_string(), "y".to_string()]
+    }
+
+    fn set_state(&mut self, newstate: DMatrix<f64>) {
+        self.x = newstate;
+    }
+
+    fn get_state(&self) -> &DMatrix<f64> {
+        &self.x
+    }
+
+    fn get_allsignals(&self) -> Vec<f64> {
+        vec![self.u[0], self.u[1], self.table.eval(self.u[0], self.u[1])]
+    }
+
+    fn get_input_dim(&self) -> usize {
+        2
+    }
+
+    fn get_input(&self) -> DMatrix<f64> {
+        self.u.clone()
+    }
+
+    fn set_input(&mut self, u: &[f64]) {
+        self.u.copy_from_slice(u);
+    }
+
+    fn slopefunc_u(&self, t: f64, x: &DMatrix<f64>, _u: &DMatrix<f64>) -> DMatrix<f64> { // 状態を持たない
+        self.slopefunc(t, x)
+    }
+
+    fn get_output_dim(&self) -> usize {
+        1
+    }
+
+    fn outputfunc(&self, _t: f64, _x: &DMatrix<f64>, u: &DMatrix<f64>) -> DMatrix<f64> {
+        DMatrix::from_element(1, 1, self.table.eval(u[0], u[1]))
+    }
+
+    fn has_feedthrough(&self) -> bool { // 出力は現在の入力だけで決まる
+        true
+    }
+
+    fn get_output_names(&self) -> Vec<String> {
+        vec!["y".to_string()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn write_temp(name: &str, text: &str) -> String {
+        let path = env::temp_dir().join(name);
+        fs::write(&path, text).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn interpolation_and_extrapolation_1d() {
+        let x = [0.0, 1.0, 2.0, 4.0];
+        let y = [0.0, 2.0, 1.0, 5.0];
+        let linear = LookupTable1D::new(&x, &y, Interpolation::Linear, Extrapolation::Clamp).unwrap();
+        assert_eq!(linear.eval(0.5), 1.0);
+        assert_eq!(linear.eval(3.0), 3.0);
+        assert_eq!(linear.eval(-1.0), 0.0);
+        assert_eq!(linear.eval(10.0), 5.0);
+
+        let linear = LookupTable1D::new(&x, &y, Interpolation::Linear, Extrapolation::Linear).unwrap();
+        assert_eq!(linear.eval(-1.0), -2.0);
+        assert_eq!(linear.eval(5.0), 7.0);
+
+        let nearest = LookupTable1D::new(&x, &y, Interpolation::Nearest, Extrapolation::Clamp).unwrap();
+        assert_eq!(nearest.eval(0.4), 0.0);
+        assert_eq!(nearest.eval(1.6), 1.0);
+
+        // スプラインはブレークポイントを通り、直線のデータでは直線になる
+        let spline = LookupTable1D::new(&x, &y, Interpolation::CubicSpline, Extrapolation::Clamp).unwrap();
+        assert!(x.iter().zip(y.iter()).all(|(xi, yi)| (spline.eval(*xi) - yi).abs() < 1e-12));
+        let line = LookupTable1D::new(&x, &[1.0, 3.0, 5.0, 9.0], Interpolation::CubicSpline, Extrapolation::Linear).unwrap();
+        assert!([-1.0, 0.3, 1.5, 3.7, 6.0].iter().all(|v| (line.eval(*v) - (1.0 + 2.0 * v)).abs() < 1e-12));
+    }
+
+    #[test]
+    fn table_rejects_bad_breakpoints() {
+        assert!(LookupTable1D::new(&[0.0], &[1.0], Interpolation::Linear, Extrapolation::Clamp).is_err());
+        assert!(LookupTable1D::new(&[0.0, 0.0], &[1.0, 2.0], Interpolation::Linear, Extrapolation::Clamp).is_err());
+        assert!(LookupTable1D::new(&[0.0, 1.0], &[1.0], Interpolation::Linear, Extrapolation::Clamp).is_err());
+        assert!(LookupTable2D::new(&[0.0, 1.0], &[0.0, 1.0], DMatrix::zeros(2, 3), Interpolation::Linear, Extrapolation::Clamp).is_err());
+    }
+
+    #[test]
+    fn interpolation_2d_is_exact_for_plane() {
+        // z = x + 2y は双線形補間でもスプラインでも厳密に再現される
+        let x = [0.0, 1.0, 3.0];
+        let y = [0.0, 2.0, 3.0, 5.0];
+        let z = DMatrix::from_fn(3, 4, |i, j| x[i] + 2.0 * y[j]);
+        for interp in [Interpolation::Linear, Interpolation::CubicSpline] {
+            let table = LookupTable2D::new(&x, &y, z.clone(), interp, Extrapolation::Linear).unwrap();
+            assert!((table.eval(0.5, 2.5) - 5.5).abs() < 1e-12);
+            assert!((table.eval(2.0, 4.0) - 10.0).abs() < 1e-12);
+            assert!((table.eval(-1.0, 6.0) - 11.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn tables_from_csv() {
+        let path = write_temp("desim_lookup_1d.csv", "x,y\n0,1\n1,3\n\n2,2\n");
+        let table = LookupTable1D::from_csv(&path, Interpolation::Linear, Extrapolation::Clamp).unwrap();
+        assert_eq!(table.eval(0.5), 2.0);
+        assert_eq!(table.eval(1.5), 2.5);
+
+        let path = write_temp("desim_lookup_2d.csv", ",0,1\n0,0,1\n2,2,3\n");
+        let table = LookupTable2D::from_csv(&path, Interpolation::Linear, Extrapolation::Clamp).unwrap();
+        assert_eq!(table.eval(1.0, 0.5), 1.5);
+
+        let path = write_temp("desim_lookup_bad.csv", "x,y\n0,1\n1,a\n");
+        assert!(LookupTable1D::from_csv(&path, Interpolation::Linear, Extrapolation::Clamp).is_err());
+        assert!(LookupTable1D::from_csv("no_such_file.csv", Interpolation::Linear, Extrapolation::Clamp).is_err());
+    }
+
+    #[test]
+    fn lookup_blocks_pass_input_through() {
+        let table = LookupTable1D::new(&[0.0, 1.0], &[0.0, 10.0], Interpolation::Linear, Extrapolation::Clamp).unwrap();
+        let mut block = NonlinearBlock::new(table);
+        block.set_input(&[0.25]);
+        assert_eq!(block.outputfunc(0.0, block.get_state(), &block.get_input())[0], 2.5);
+        assert!(block.has_feedthrough());
+
+        let z = DMatrix::from_row_slice(2, 2, &[0.0, 1.0, 2.0, 3.0]);
+        let table = LookupTable2D::new(&[0.0, 1.0], &[0.0, 1.0], z, Interpolation::Linear, Extrapolation::Clamp).unwrap();
+        let mut block = LookupBlock2D::new(table);
+        block.set_input(&[0.5, 0.5]);
+        assert_eq!(block.get_allsignals(), vec![0.5, 0.5, 1.5]);
+        assert!(block.has_feedthrough());
+    }
+}
@@ -7,39 +7,469 @@ use std::fmt;
 extern crate nalgebra as na;
 use na::{U2, U3, Dynamic, ArrayStorage, VecStorage, Matrix, OMatrix, DMatrix};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum SolverType {
     Euler,
     RungeKutta,
+    DormandPrince(DormandPrinceParams), // 可変刻みの埋め込み型ルンゲクッタ法 (RK45)  SolverType::dormand_prince で作る
+    BackwardEuler,      // 後退オイラー法（陰解法）
+    Trapezoidal,        // 台形則（陰解法）
+    TrBdf2,             // TR-BDF2法（台形則で内点を求めてからBDF2で進める、履歴不要の1ステップ2次陰解法）
+    Exact,              // 行列指数関数による厳密離散化（線形モデルのみ、入力はステップ内で一定とみなす。それ以外のモデルは4次のルンゲクッタ法で進める）
+    Custom(ButcherTableau), // 任意のブッチャー表による陽的ルンゲクッタ法
+}
+
+impl SolverType {
+    /* Dormand-Prince法のソルバ（0 < min_step <= max_step、許容誤差は0以上で少なくとも一方は正） */
+    pub fn dormand_prince(rtol: f64, atol: f64, min_step: f64, max_step: f64) -> Result<Self, &'static str> {
+        if !(min_step > 0.0) || min_step > max_step {
+            return Err("刻み幅は 0 < min_step <= max_step としてください。");
+        }
+        if !(rtol >= 0.0) || !(atol >= 0.0) || rtol + atol == 0.0 {
+            return Err("許容誤差は0以上とし、少なくとも一方を正にしてください。");
+        }
+        Ok(SolverType::DormandPrince(DormandPrinceParams {
+            rtol: rtol,
+            atol: atol,
+            min_step: min_step,
+            max_step: max_step,
+        }))
+    }
+}
+
+/* Dormand-Prince法の設定（SolverType::dormand_prince で検査してから作る）
+   刻み幅の引き継ぎなど計算中の状態はモデルごとの StepControl に持つので、同じ SolverType を複数のモデルで使ってよい */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DormandPrinceParams {
+    rtol: f64,      // 相対許容誤差
+    atol: f64,      // 絶対許容誤差
+    min_step: f64,  // 最小刻み幅（正の値）
+    max_step: f64,  // 最大刻み幅
+}
+
+/* 可変刻みのソルバの、モデルごとの状態 */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct StepControl {
+    pub last_step: f64,                 // 前の呼び出しの終わりの刻み幅（次の呼び出しの最初の刻み幅に使う、0は未設定）
+    pub failure: Option<&'static str>,  // 前の呼び出しで計算を打ち切った理由（打ち切ったときは最後の有限の状態で止める）
+}
+
+/* 陽的ルンゲクッタ法のブッチャー表
+   c | a
+   --+---
+     | b  */
+#[derive(Debug, Clone)]
+pub struct ButcherTableau {
+    a: Vec<Vec<f64>>,   // 各段の係数（狭義下三角部分のみ使う）
+    b: Vec<f64>,        // 重み
+    c: Vec<f64>,        // 各段の時刻の係数
+}
+
+impl ButcherTableau {
+    pub fn new(a: &[&[f64]], b: &[f64], c: &[f64]) -> Result<Self, &'static str> {
+        let stages = b.len();
+
+        if stages < 1 {
+            return Err("段数が0になりました。");
+        }
+        if a.len() != stages || c.len() != stages {
+            return Err("ブッチャー表のサイズが違います。");
+        }
+        for (i, row) in a.iter().enumerate() {
+            if row.len() != stages {
+                return Err("ブッチャー表のサイズが違います。");
+            }
+            if row[i..].iter().any(|e| *e != 0.0) {
+                return Err("陽解法ではないブッチャー表です。（aが狭義下三角行列ではありません）");
+            }
+        }
+
+        Ok(Self {
+            a: a.iter().map(|row| row.to_vec()).collect(),
+            b: b.to_vec(),
+            c: c.to_vec(),
+        })
+    }
+
+    pub fn euler() -> Self { // オイラー法
+        Self::new(&[&[0.0]], &[1.0], &[0.0]).unwrap()
+    }
+
+    pub fn heun() -> Self { // ホイン法（2次）
+        Self::new(&[&[0.0, 0.0], &[1.0, 0.0]], &[0.5, 0.5], &[0.0, 1.0]).unwrap()
+    }
+
+    pub fn midpoint() -> Self { // 中点法（2次）
+        Self::new(&[&[0.0, 0.0], &[0.5, 0.0]], &[0.0, 1.0], &[0.0, 0.5]).unwrap()
+    }
+
+    pub fn ralston() -> Self { // ラルストン法（2次）
+        Self::new(&[&[0.0, 0.0], &[2.0 / 3.0, 0.0]], &[0.25, 0.75], &[0.0, 2.0 / 3.0]).unwrap()
+    }
+
+    pub fn rk3() -> Self { // クッタの3次公式
+        Self::new(
+            &[&[0.0, 0.0, 0.0],
+              &[0.5, 0.0, 0.0],
+              &[-1.0, 2.0, 0.0]],
+            &[1.0 / 6.0, 2.0 / 3.0, 1.0 / 6.0],
+            &[0.0, 0.5, 1.0]).unwrap()
+    }
+
+    pub fn rk4() -> Self { // 古典的ルンゲクッタ法（4次）
+        Self::new(
+            &[&[0.0, 0.0, 0.0, 0.0],
+              &[0.5, 0.0, 0.0, 0.0],
+              &[0.0, 0.5, 0.0, 0.0],
+              &[0.0, 0.0, 1.0, 0.0]],
+            &[1.0 / 6.0, 1.0 / 3.0, 1.0 / 3.0, 1.0 / 6.0],
+            &[0.0, 0.5, 0.5, 1.0]).unwrap()
+    }
+
+    pub fn rk38() -> Self { // 3/8公式（4次）
+        Self::new(
+            &[&[0.0, 0.0, 0.0, 0.0],
+              &[1.0 / 3.0, 0.0, 0.0, 0.0],
+              &[-1.0 / 3.0, 1.0, 0.0, 0.0],
+              &[1.0, -1.0, 1.0, 0.0]],
+            &[1.0 / 8.0, 3.0 / 8.0, 3.0 / 8.0, 1.0 / 8.0],
+            &[0.0, 1.0 / 3.0, 2.0 / 3.0, 1.0]).unwrap()
+    }
+
+    pub fn stages(&self) -> usize {
+        self.b.len()
+    }
 }
 
 pub trait Model {
-    fn slopefunc(&self, x: &DMatrix<f64>) -> DMatrix<f64>;
+    fn slopefunc(&self, t: f64, x: &DMatrix<f64>) -> DMatrix<f64>; // 時刻tにおける状態xの微分
     fn get_signals_info(&self) -> Vec<String>;            // モデルの状態の情報　（各要素の名前と次元数）
     fn set_state(&mut self, newstate: DMatrix<f64>);    // 状態ベクトルをセットする　get_stateで &mut Dmatixを返すようにすれば、setはいらないかも！確認
     fn get_state(&self) -> &DMatrix<f64>;               // 状態ベクトルを取得する
     fn get_allsignals(&self) -> Vec<f64>;               // Simulatorに渡して、データストレージに格納してもらうためのインターフェース
 
-    fn calc_nextstate(&mut self, delta_t : f64, solvertype: &SolverType) { // オイラー法またはルンゲクッタ法による次の状態の計算
-        match solvertype {
-            SolverType::Euler => {
-                let state = self.get_state();
-                let newstate = state + self.slopefunc(state) * delta_t;
-                self.set_state(newstate);
-            },
-            SolverType::RungeKutta => {
-                let state = self.get_state();
-                let d1 = self.slopefunc(state) * delta_t;
-                let d2 = self.slopefunc(&(state + &d1 / 2.0)) * delta_t;
-                let d3 = self.slopefunc(&(state + &d2 / 2.0)) * delta_t;
-                let d4 = self.slopefunc(&(state + &d3)) * delta_t;
-                let newstate = state + (d1 + 2.0 * d2 + 2.0 * d3 + d4) / 6.0;
-                self.set_state(newstate);
+    /* 入力ポートを持つモデル用のインターフェース（信号源の接続などで使う） */
+    fn get_input_dim(&self) -> usize { 0 }              // 入力の次数（入力を持たないモデルは0）
+    fn get_input(&self) -> DMatrix<f64> {               // 現在の入力ベクトル
+        DMatrix::from_element(self.get_input_dim(), 1, 0.0)
+    }
+    fn set_input(&mut self, _u: &[f64]) {              // 入力ベクトルをセットする（長さはget_input_dimと同じにする。次数の確認は呼び出し側で行う）
+        debug_assert!(self.get_input_dim() == 0, "入力を持つモデルは set_input をオーバーライドしてください。");
+    }
+    fn slopefunc_u(&self, t: f64, x: &DMatrix<f64>, _u: &DMatrix<f64>) -> DMatrix<f64> { // 入力uを明示的に与えたときの微分
+        debug_assert!(self.get_input_dim() == 0, "入力を持つモデルは slopefunc_u をオーバーライドしてください。"); // 既定の実装では入力が黙って捨てられるため
+        self.slopefunc(t, x)
+    }
+
+    /* 出力ポートを持つモデル用のインターフェース（ブロック線図の結合などで使う） */
+    fn get_output_dim(&self) -> usize { 0 }             // 出力の次数（出力を持たないモデルは0）
+    fn outputfunc(&self, _t: f64, _x: &DMatrix<f64>, _u: &DMatrix<f64>) -> DMatrix<f64> { // 状態xと入力uに対する出力
+        DMatrix::from_element(self.get_output_dim(), 1, 0.0)
+    }
+    fn has_feedthrough(&self) -> bool {                 // 入力が出力に直接現れるか（代数ループの判定に使う）
+        self.get_input_dim() > 0 && self.get_output_dim() > 0 // 出力の式が分からないので安全側に倒す。出力が入力に依存しないモデルはfalseを返すようにオーバーライドする
+    }
+    fn get_input_names(&self) -> Vec<String> {          // 入力ポートの名前（信号フローグラフの結線で使う）
+        (0..self.get_input_dim()).map(|i| format!("u_{}", i)).collect()
+    }
+    fn get_output_names(&self) -> Vec<String> {         // 出力ポートの名前
+        (0..self.get_output_dim()).map(|i| format!("y_{}", i)).collect()
+    }
+
+    /* 陰解法の各段で y - c * f(t, y) = rhs を解く
+       一般のモデルでは数値ヤコビアンを使った減衰付きニュートン法で解く（線形モデルは閉形式で解けるのでオーバーライドする）
+       解けないときはErrを返し、呼び出し側で刻み幅を分割して解き直す */
+    fn solve_implicit(&self, t: f64, rhs: &DMatrix<f64>, c: f64) -> Result<DMatrix<f64>, &'static str> {
+        let n = rhs.nrows();
+        let mut y = rhs.clone();
+        let mut g = &y - self.slopefunc(t, &y) * c - rhs;
+
+        for _ in 0..NEWTON_MAXITER {
+            let jac = DMatrix::<f64>::identity(n, n) - numerical_jacobian(self, t, &y) * c;
+            let dy = jac.lu().solve(&g).ok_or("陰解法のヤコビ行列が特異です。")?;
+            if dy.norm() <= NEWTON_TOL * (1.0 + y.norm()) {
+                return Ok(y - dy);
+            }
+
+            // 残差が減るまで更新量を半分にする
+            let mut damping = 1.0;
+            loop {
+                let y_new = &y - &dy * damping;
+                let g_new = &y_new - self.slopefunc(t, &y_new) * c - rhs;
+                if g_new.norm() < g.norm() {
+                    y = y_new;
+                    g = g_new;
+                    break;
+                }
+                damping /= 2.0;
+                if damping < NEWTON_MIN_DAMPING {
+                    return Err("陰解法のニュートン反復で残差が減りません。");
+                }
             }
         }
-        
+
+        Err("陰解法のニュートン反復が収束しませんでした。")
+    }
+
+    fn calc_nextstate(&mut self, t: f64, delta_t : f64, solvertype: &SolverType) { // 時刻tから指定したソルバで次の状態を計算する
+        let newstate = solve_nextstate(self, t, delta_t, solvertype);
+        self.set_state(newstate);
+        self.update_discrete(t, delta_t);
+    }
+
+    /* 積分では変化しない状態（サンプル時刻に更新する離散時間の状態など）を、区間 (t, t + delta_t] のサンプル時刻まで進める
+       CompositeModel や SignalGraph は全体を積分した後に各ブロックについて呼ぶ（状態と入力は時刻 t + delta_t の値になっている） */
+    fn update_discrete(&mut self, _t: f64, _delta_t: f64) {}
+
+    /* 可変刻みのソルバの状態を持つためのインターフェース
+       保持しないモデルでは毎回 delta_t から刻み始め、打ち切りの理由も残らない */
+    fn get_step_control(&self) -> StepControl {
+        StepControl::default()
+    }
+    fn set_step_control(&mut self, _control: StepControl) {}
+
+}
+
+/* 時刻tの現在の状態からdelta_tだけ進めた状態を指定したソルバで計算する
+   calc_nextstateをオーバーライドしたモデルから標準の計算方法を呼び出すときにも使う
+   可変刻みのソルバは計算の後にモデルの StepControl を更新する */
+pub fn solve_nextstate<M: Model + ?Sized>(model: &mut M, t: f64, delta_t: f64, solvertype: &SolverType) -> DMatrix<f64> {
+    let state = model.get_state();
+
+    match solvertype {
+        SolverType::Euler => {
+            explicit_rk(model, &ButcherTableau::euler(), t, delta_t)
+        },
+        SolverType::RungeKutta => {
+            explicit_rk(model, &ButcherTableau::rk4(), t, delta_t)
+        },
+        SolverType::Custom(tableau) => {
+            explicit_rk(model, tableau, t, delta_t)
+        },
+        SolverType::DormandPrince(params) => {
+            let (newstate, control) = dormand_prince(&*model, t, delta_t, params, model.get_step_control().last_step);
+            model.set_step_control(control);
+            newstate
+        },
+        SolverType::BackwardEuler => {
+            implicit_step(model, ImplicitMethod::BackwardEuler, state, t, delta_t, 0)
+        },
+        SolverType::Trapezoidal => {
+            implicit_step(model, ImplicitMethod::Trapezoidal, state, t, delta_t, 0)
+        },
+        SolverType::TrBdf2 => {
+            implicit_step(model, ImplicitMethod::TrBdf2, state, t, delta_t, 0)
+        },
+        SolverType::Exact => { // 厳密離散化はSpaceStateModelがcalc_nextstateで扱う。それ以外のモデルは4次のルンゲクッタ法で代用する
+            explicit_rk(model, &ButcherTableau::rk4(), t, delta_t)
+        },
+    }
+}
+
+/* ブッチャー表に従って陽的ルンゲクッタ法でdelta_tだけ進めた状態を返す */
+pub fn explicit_rk<M: Model + ?Sized>(model: &M, tableau: &ButcherTableau, t: f64, delta_t: f64) -> DMatrix<f64> {
+    explicit_rk_from(model, tableau, model.get_state(), t, delta_t)
+}
+
+/* 状態stateから陽的ルンゲクッタ法でdelta_tだけ進めた状態を返す */
+fn explicit_rk_from<M: Model + ?Sized>(model: &M, tableau: &ButcherTableau, state: &DMatrix<f64>, t: f64, delta_t: f64) -> DMatrix<f64> {
+    let mut k: Vec<DMatrix<f64>> = Vec::with_capacity(tableau.stages());
+
+    for i in 0..tableau.stages() {
+        let mut xs = state.clone();
+        for j in 0..i {
+            if tableau.a[i][j] != 0.0 {
+                xs += &k[j] * (tableau.a[i][j] * delta_t);
+            }
+        }
+        k.push(model.slopefunc(t + tableau.c[i] * delta_t, &xs));
+    }
+
+    let mut newstate = state.clone();
+    for (ki, bi) in k.iter().zip(tableau.b.iter()) {
+        if *bi != 0.0 {
+            newstate += ki * (bi * delta_t);
+        }
+    }
+
+    newstate
+}
+
+const NEWTON_MAXITER: usize = 50;  // ニュートン法の最大反復回数
+const NEWTON_TOL: f64 = 1e-10;     // ニュートン法の収束判定（更新量の相対ノルム）
+const NEWTON_MIN_DAMPING: f64 = 1e-6; // ニュートン法の更新量を縮める下限
+const IMPLICIT_MAX_SPLIT: usize = 10; // 陰解法が解けないときに刻み幅を半分にする最大回数
+
+/* 陰解法の種類 */
+#[derive(Debug, Clone, Copy)]
+enum ImplicitMethod {
+    BackwardEuler,
+    Trapezoidal,
+    TrBdf2,
+}
+
+/* 状態x0から時刻tの1ステップを陰解法で進める
+   ニュートン法が解けなければ刻み幅を半分に分けて解き直し、IMPLICIT_MAX_SPLIT回分けても解けなければ
+   その区間だけ4次のルンゲクッタ法で進める（シミュレーションを止めない） */
+fn implicit_step<M: Model + ?Sized>(model: &M, method: ImplicitMethod, x0: &DMatrix<f64>, t: f64, delta_t: f64, depth: usize) -> DMatrix<f64> {
+    let result = match method {
+        ImplicitMethod::BackwardEuler => {
+            model.solve_implicit(t + delta_t, x0, delta_t)
+        },
+        ImplicitMethod::Trapezoidal => {
+            let rhs = x0 + model.slopefunc(t, x0) * (delta_t / 2.0);
+            model.solve_implicit(t + delta_t, &rhs, delta_t / 2.0)
+        },
+        ImplicitMethod::TrBdf2 => {
+            tr_bdf2(model, x0, t, delta_t)
+        },
+    };
+
+    match result {
+        Ok(x) => x,
+        Err(_) if depth < IMPLICIT_MAX_SPLIT => {
+            let h = delta_t / 2.0;
+            let xm = implicit_step(model, method, x0, t, h, depth + 1);
+            implicit_step(model, method, &xm, t + h, h, depth + 1)
+        },
+        Err(_) => explicit_rk_from(model, &ButcherTableau::rk4(), x0, t, delta_t),
+    }
+}
+
+/* slopefuncのヤコビ行列を前進差分で求める */
+pub fn numerical_jacobian<M: Model + ?Sized>(model: &M, t: f64, x: &DMatrix<f64>) -> DMatrix<f64> {
+    let n = x.nrows();
+    let f0 = model.slopefunc(t, x);
+    let mut jac = DMatrix::<f64>::zeros(f0.nrows(), n);
+
+    for j in 0..n {
+        let h = f64::EPSILON.sqrt() * x[j].abs().max(1.0);
+        let mut xp = x.clone();
+        xp[j] += h;
+        let df = (model.slopefunc(t, &xp) - &f0) / h;
+        jac.set_column(j, &df.column(0));
+    }
+
+    jac
+}
+
+/* 関数fのヤコビ行列を中心差分で求める
+   刻み幅は丸め誤差と打ち切り誤差が釣り合う ε^(1/3) を値の大きさに合わせて使う */
+pub fn central_jacobian<F>(f: F, v: &DMatrix<f64>) -> DMatrix<f64>
+where F: Fn(&DMatrix<f64>) -> DMatrix<f64>
+{
+    let n = v.nrows();
+    let mut jac = DMatrix::<f64>::zeros(f(v).nrows(), n);
+
+    for j in 0..n {
+        let h = f64::EPSILON.cbrt() * v[j].abs().max(1.0);
+        let mut vp = v.clone();
+        let mut vm = v.clone();
+        vp[j] += h;
+        vm[j] -= h;
+        let df = (f(&vp) - f(&vm)) / (vp[j] - vm[j]); // 実際に表現できた刻み幅で割る
+        jac.set_column(j, &df.column(0));
+    }
+
+    jac
+}
+
+/* TR-BDF2法で状態x0からdelta_tだけ進めた結果を返す
+   前半 γΔt を台形則、後半をBDF2で進めるので、前ステップの履歴を持たずに2次精度・L安定になる */
+fn tr_bdf2<M: Model + ?Sized>(model: &M, x0: &DMatrix<f64>, t: f64, delta_t: f64) -> Result<DMatrix<f64>, &'static str> {
+    let gamma = 2.0 - 2.0_f64.sqrt();
+
+    // 台形則で t + γΔt の内点を求める
+    let rhs = x0 + model.slopefunc(t, x0) * (gamma * delta_t / 2.0);
+    let xg = model.solve_implicit(t + gamma * delta_t, &rhs, gamma * delta_t / 2.0)?;
+
+    // BDF2で t + Δt まで進める
+    let k = gamma * (2.0 - gamma);
+    let rhs = xg / k - x0 * ((1.0 - gamma).powi(2) / k);
+    model.solve_implicit(t + delta_t, &rhs, (1.0 - gamma) / (2.0 - gamma) * delta_t)
+}
+
+/* Dormand-Prince法の係数 */
+const DP_C: [f64; 7] = [0.0, 1.0 / 5.0, 3.0 / 10.0, 4.0 / 5.0, 8.0 / 9.0, 1.0, 1.0];
+const DP_A: [[f64; 6]; 7] = [
+    [0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    [1.0 / 5.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    [3.0 / 40.0, 9.0 / 40.0, 0.0, 0.0, 0.0, 0.0],
+    [44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0, 0.0, 0.0, 0.0],
+    [19372.0 / 6561.0, -25360.0 / 2187.0, 64448.0 / 6561.0, -212.0 / 729.0, 0.0, 0.0],
+    [9017.0 / 3168.0, -355.0 / 33.0, 46732.0 / 5247.0, 49.0 / 176.0, -5103.0 / 18656.0, 0.0],
+    [35.0 / 384.0, 0.0, 500.0 / 1113.0, 125.0 / 192.0, -2187.0 / 6784.0, 11.0 / 84.0],
+];
+const DP_B5: [f64; 7] = [35.0 / 384.0, 0.0, 500.0 / 1113.0, 125.0 / 192.0, -2187.0 / 6784.0, 11.0 / 84.0, 0.0]; // 5次解
+const DP_B4: [f64; 7] = [5179.0 / 57600.0, 0.0, 7571.0 / 16695.0, 393.0 / 640.0, -92097.0 / 339200.0, 187.0 / 2100.0, 1.0 / 40.0]; // 4次解（誤差推定用）
+
+/* Dormand-Prince法で時刻tからdelta_tだけ状態を進める
+   内部では誤差に応じて刻み幅を調整し、最後の刻みはdelta_tにちょうど到達するように切り詰める
+   （Simulatorの出力時刻と計算結果が一致する）
+   最後の刻み幅を StepControl で返して次の呼び出しに引き継ぐ（last_step が0なら delta_t から始める）
+   誤差が有限の値にならないときはステップを棄却して刻み幅を縮め、最小刻み幅でも駄目なら計算を打ち切って最後の有限の状態と理由を返す */
+fn dormand_prince<M: Model + ?Sized>(model: &M, t0: f64, delta_t: f64, params: &DormandPrinceParams, last_step: f64) -> (DMatrix<f64>, StepControl) {
+    let DormandPrinceParams { rtol, atol, min_step, max_step } = *params;
+    let mut x = model.get_state().clone();
+    let mut t = 0.0;
+    let mut h = if last_step > 0.0 { last_step } else { delta_t }.max(min_step).min(max_step);
+    let mut k1 = model.slopefunc(t0, &x);
+
+    while t < delta_t {
+        let remain = delta_t - t;
+        let last = h >= remain;
+        let hs = if last { remain } else { h }; // このステップの刻み幅
+
+        // 各段の傾きを計算する（k[0]は前のステップの最終段を使い回す FSAL）
+        let mut k = vec![k1.clone()];
+        for s in 1..7 {
+            let mut xs = x.clone();
+            for j in 0..s {
+                if DP_A[s][j] != 0.0 {
+                    xs += &k[j] * (DP_A[s][j] * hs);
+                }
+            }
+            k.push(model.slopefunc(t0 + t + DP_C[s] * hs, &xs));
+        }
+
+        let mut x5 = x.clone();
+        let mut x4 = x.clone();
+        for s in 0..7 {
+            x5 += &k[s] * (DP_B5[s] * hs);
+            x4 += &k[s] * (DP_B4[s] * hs);
+        }
+
+        // 誤差のRMSノルム（1以下なら許容）
+        let n = x.len().max(1) as f64;
+        let err = (x5.iter().zip(x4.iter()).zip(x.iter())
+            .map(|((a, b), x0)| {
+                let sc = atol + rtol * a.abs().max(x0.abs());
+                ((a - b) / sc).powi(2)
+            })
+            .sum::<f64>() / n).sqrt();
+
+        if !err.is_finite() {
+            if hs <= min_step {
+                return (x, StepControl { last_step: h, failure: Some("最小刻み幅でも誤差が有限の値にならないため、計算を打ち切りました。") });
+            }
+            h = (hs * 0.2).max(min_step);
+            continue;
+        }
+
+        let accepted = err <= 1.0 || hs <= min_step; // 最小刻み幅に達した場合は誤差が大きくても進める
+        if accepted {
+            t = if last { delta_t } else { t + hs };
+            x = x5;
+            k1 = k.pop().unwrap();
+        }
+
+        // 次の刻み幅（安全係数0.9、変化率は0.2～5倍に制限）
+        let factor = if err == 0.0 { 5.0 } else { (0.9 * err.powf(-0.2)).max(0.2).min(5.0) };
+        let h_new = (hs * factor).max(min_step).min(max_step);
+        h = if last && accepted { h.max(h_new) } else { h_new }; // 切り詰めた最後の刻みで次の刻み幅を縮めない
     }
 
+    (x, StepControl { last_step: h, failure: None })
 }
 
 #[derive(Debug, Clone)]
@@ -53,6 +483,8 @@ pub struct SpaceStateModel {
     output_dim: usize,      // 出力次数
     x: DMatrix<f64>,        // 状態ベクトル
     u: DMatrix<f64>,        // 入力ベクトル
+    discrete_cache: Option<(f64, DMatrix<f64>, DMatrix<f64>)>, // 厳密離散化の (Δt, Φ, Γ) のキャッシュ
+    step_control: StepControl, // 可変刻みのソルバの状態
 }
 
 impl SpaceStateModel {
@@ -67,7 +499,36 @@ impl SpaceStateModel {
             state_dim: sdim,
             input_dim: idim,
             output_dim: odim,
+            discrete_cache: None,
+            step_control: StepControl::default(),
+        }
+    }
+
+    /* 行列A, B, C, Dから状態空間モデルを生成する */
+    pub fn from_matrices(mat_a: DMatrix<f64>, mat_b: DMatrix<f64>, mat_c: DMatrix<f64>, mat_d: DMatrix<f64>) -> Result<Self, &'static str> {
+        let sdim = mat_a.nrows();
+        let idim = mat_b.ncols();
+        let odim = mat_c.nrows();
+
+        if mat_a.ncols() != sdim {
+            return Err("A行列のサイズが違います。");
+        }
+        if mat_b.nrows() != sdim {
+            return Err("B行列のサイズが違います。");
         }
+        if mat_c.ncols() != sdim {
+            return Err("C行列のサイズが違います。");
+        }
+        if mat_d.nrows() != odim || mat_d.ncols() != idim {
+            return Err("D行列のサイズが違います。");
+        }
+
+        let mut model = SpaceStateModel::new(sdim, idim, odim);
+        model.mat_a = mat_a;
+        model.mat_b = mat_b;
+        model.mat_c = mat_c;
+        model.mat_d = mat_d;
+        Ok(model)
     }
 
     /* 伝達関数から状態空間モデルを生成する */
@@ -159,6 +620,7 @@ impl SpaceStateModel {
         for (i, elem) in mat_a.iter().enumerate() {
             self.mat_a[(i / self.state_dim, i % self.state_dim)] = *elem;
         }
+        self.discrete_cache = None;
 
         Ok(())
     }
@@ -172,6 +634,7 @@ impl SpaceStateModel {
         for (i, elem) in mat_b.iter().enumerate() {
             self.mat_b[(i / self.input_dim, i % self.input_dim)] = *elem;
         }
+        self.discrete_cache = None;
 
         Ok(())
     }
@@ -224,14 +687,45 @@ impl SpaceStateModel {
         Ok(())
     }
 
+    pub fn get_mat_a(&self) -> &DMatrix<f64> {
+        &self.mat_a
+    }
+
+    pub fn get_mat_b(&self) -> &DMatrix<f64> {
+        &self.mat_b
+    }
+
+    pub fn get_mat_c(&self) -> &DMatrix<f64> {
+        &self.mat_c
+    }
+
+    pub fn get_mat_d(&self) -> &DMatrix<f64> {
+        &self.mat_d
+    }
+
     pub fn get_observation(&self) -> DMatrix<f64> {
         &self.mat_c * &self.x + &self.mat_d * &self.u
     }
 
+    /* 入力をΔtの間一定としたときの離散時間の行列 Φ = e^(AΔt), Γ = ∫e^(Aτ)dτ B を求める
+       拡大行列 [[A, B], [0, 0]] の行列指数関数から Φ と Γ を同時に取り出す */
+    pub fn calc_discrete_matrices(&self, delta_t: f64) -> (DMatrix<f64>, DMatrix<f64>) {
+        let n = self.state_dim;
+        let m = self.input_dim;
+        let mut aug = DMatrix::<f64>::zeros(n + m, n + m);
+        aug.slice_mut((0, 0), (n, n)).copy_from(&(&self.mat_a * delta_t));
+        aug.slice_mut((0, n), (n, m)).copy_from(&(&self.mat_b * delta_t));
+
+        let expm = aug.exp();
+        let phi = expm.slice((0, 0), (n, n)).into_owned();
+        let gamma = expm.slice((0, n), (n, m)).into_owned();
+        (phi, gamma)
+    }
+
 }
 
 impl Model for SpaceStateModel {
-    fn slopefunc(&self, x: &DMatrix<f64>) -> DMatrix<f64> {
+    fn slopefunc(&self, _t: f64, x: &DMatrix<f64>) -> DMatrix<f64> {
         &self.mat_a * x + &self.mat_b * &self.u
     }
 
@@ -263,6 +757,70 @@ impl Model for SpaceStateModel {
         &self.x
     }
 
+    fn calc_nextstate(&mut self, t: f64, delta_t : f64, solvertype: &SolverType) {
+        match solvertype {
+            SolverType::Exact => {
+                // Δtが変わったときだけ Φ, Γ を計算し直す
+                let cached = match &self.discrete_cache {
+                    Some((dt, _, _)) => *dt == delta_t,
+                    None => false,
+                };
+                if !cached {
+                    let (phi, gamma) = self.calc_discrete_matrices(delta_t);
+                    self.discrete_cache = Some((delta_t, phi, gamma));
+                }
+
+                let (_, phi, gamma) = self.discrete_cache.as_ref().unwrap();
+                self.x = phi * &self.x + gamma * &self.u;
+            },
+            _ => {
+                let newstate = solve_nextstate(self, t, delta_t, solvertype);
+                self.set_state(newstate);
+            },
+        }
+    }
+
+    fn get_input_dim(&self) -> usize {
+        self.input_dim
+    }
+
+    fn get_input(&self) -> DMatrix<f64> {
+        self.u.clone()
+    }
+
+    fn set_input(&mut self, u: &[f64]) {
+        self.set_u(u).unwrap();
+    }
+
+    fn slopefunc_u(&self, _t: f64, x: &DMatrix<f64>, u: &DMatrix<f64>) -> DMatrix<f64> {
+        &self.mat_a * x + &self.mat_b * u
+    }
+
+    fn get_output_dim(&self) -> usize {
+        self.output_dim
+    }
+
+    fn outputfunc(&self, _t: f64, x: &DMatrix<f64>, u: &DMatrix<f64>) -> DMatrix<f64> {
+        &self.mat_c * x + &self.mat_d * u
+    }
+
+    fn has_feedthrough(&self) -> bool {
+        self.mat_d.iter().any(|d| *d != 0.0)
+    }
+
+    fn get_step_control(&self) -> StepControl {
+        self.step_control
+    }
+
+    fn set_step_control(&mut self, control: StepControl) {
+        self.step_control = control;
+    }
+
+    fn solve_implicit(&self, _t: f64, rhs: &DMatrix<f64>, c: f64) -> Result<DMatrix<f64>, &'static str> { // 線形なので (I - cA) y = rhs + cBu を直接解く
+        let lhs = DMatrix::<f64>::identity(self.state_dim, self.state_dim) - &self.mat_a * c;
+        lhs.lu().solve(&(rhs + &self.mat_b * &self.u * c)).ok_or("陰解法の係数行列が特異です。")
+    }
+
     fn get_allsignals(&self) -> Vec<f64> {
         let mut u = self.u.iter().map(|u| *u).collect::<Vec<f64>>();
         let mut x = self.x.iter().map(|x| *x).collect::<Vec<f64>>();
@@ -339,11 +897,23 @@ impl TransFuncModel {
     pub fn set_u(&mut self, u: f64) {
         self.model.set_u(&vec![u]);
     }
+
+    pub fn get_model(&self) -> &SpaceStateModel { // 内部の状態空間モデル
+        &self.model
+    }
+
+    pub fn get_num(&self) -> &[f64] { // 分子多項式の係数
+        &self.num
+    }
+
+    pub fn get_den(&self) -> &[f64] { // 分母多項式の係数
+        &self.den
+    }
 }
 
 impl Model for TransFuncModel {
-    fn slopefunc(&self, x: &DMatrix<f64>) -> DMatrix<f64> {
-        self.model.slopefunc(x)
+    fn slopefunc(&self, t: f64, x: &DMatrix<f64>) -> DMatrix<f64> {
+        self.model.slopefunc(t, x)
     }
 
     fn get_signals_info(&self) -> Vec<String> {
@@ -358,7 +928,351 @@ impl Model for TransFuncModel {
         &self.model.get_state()
     }
 
+    fn calc_nextstate(&mut self, t: f64, delta_t : f64, solvertype: &SolverType) {
+        self.model.calc_nextstate(t, delta_t, solvertype);
+    }
+
+    fn solve_implicit(&self, t: f64, rhs: &DMatrix<f64>, c: f64) -> Result<DMatrix<f64>, &'static str> {
+        self.model.solve_implicit(t, rhs, c)
+    }
+
+    fn get_input_dim(&self) -> usize {
+        self.model.get_input_dim()
+    }
+
+    fn get_input(&self) -> DMatrix<f64> {
+        self.model.get_input()
+    }
+
+    fn set_input(&mut self, u: &[f64]) {
+        self.model.set_input(u);
+    }
+
+    fn slopefunc_u(&self, t: f64, x: &DMatrix<f64>, u: &DMatrix<f64>) -> DMatrix<f64> {
+        self.model.slopefunc_u(t, x, u)
+    }
+
+    fn get_output_dim(&self) -> usize {
+        self.model.get_output_dim()
+    }
+
+    fn outputfunc(&self, t: f64, x: &DMatrix<f64>, u: &DMatrix<f64>) -> DMatrix<f64> {
+        self.model.outputfunc(t, x, u)
+    }
+
+    fn has_feedthrough(&self) -> bool {
+        self.model.has_feedthrough()
+    }
+
+    fn get_step_control(&self) -> StepControl {
+        self.model.get_step_control()
+    }
+
+    fn set_step_control(&mut self, control: StepControl) {
+        self.model.set_step_control(control);
+    }
+
     fn get_allsignals(&self) -> Vec<f64> {
         self.model.get_allsignals()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Simulator;
+
+    /* dx/dt = -k x （解析解 x0 e^(-kt)、x が limit 以上になると傾きは NaN） */
+    struct Decay {
+        k: f64,
+        limit: f64,
+        x: DMatrix<f64>,
+        step_control: StepControl,
+    }
+
+    impl Decay {
+        fn new(k: f64, x0: f64) -> Self {
+            Self { k: k, limit: f64::INFINITY, x: DMatrix::from_element(1, 1, x0), step_control: StepControl::default() }
+        }
+    }
+
+    impl Model for Decay {
+        fn slopefunc(&self, _t: f64, x: &DMatrix<f64>) -> DMatrix<f64> {
+            x.map(|v| if v < self.limit { -self.k * v } else { f64::NAN })
+        }
+
+        fn get_step_control(&self) -> StepControl {
+            self.step_control
+        }
+
+        fn set_step_control(&mut self, control: StepControl) {
+            self.step_control = control;
+        }
+
+        fn get_signals_info(&self) -> Vec<String> {
+            vec!["x".to_string()]
+        }
+
+        fn set_state(&mut self, newstate: DMatrix<f64>) {
+            self.x = newstate;
+        }
+
+        fn get_state(&self) -> &DMatrix<f64> {
+            &self.x
+        }
+
+        fn get_allsignals(&self) -> Vec<f64> {
+            vec![self.x[0]]
+        }
+    }
+
+    /* dx/dt = -1e3 atan(1e3 x) （原点付近で非常に硬く、減衰の無いニュートン法は発散する） */
+    struct StiffAtan {
+        x: DMatrix<f64>,
+    }
+
+    impl Model for StiffAtan {
+        fn slopefunc(&self, _t: f64, x: &DMatrix<f64>) -> DMatrix<f64> {
+            x.map(|v| -1e3 * (1e3 * v).atan())
+        }
+
+        fn get_signals_info(&self) -> Vec<String> {
+            vec!["x".to_string()]
+        }
+
+        fn set_state(&mut self, newstate: DMatrix<f64>) {
+            self.x = newstate;
+        }
+
+        fn get_state(&self) -> &DMatrix<f64> {
+            &self.x
+        }
+
+        fn get_allsignals(&self) -> Vec<f64> {
+            vec![self.x[0]]
+        }
+    }
+
+    /* dx/dt = cos t （解析解 sin t、各段に正しい時刻が渡されないと精度が落ちる） */
+    struct Cosine {
+        x: DMatrix<f64>,
+    }
+
+    impl Model for Cosine {
+        fn slopefunc(&self, t: f64, x: &DMatrix<f64>) -> DMatrix<f64> {
+            DMatrix::from_element(x.nrows(), 1, t.cos())
+        }
+
+        fn get_signals_info(&self) -> Vec<String> {
+            vec!["x".to_string()]
+        }
+
+        fn set_state(&mut self, newstate: DMatrix<f64>) {
+            self.x = newstate;
+        }
+
+        fn get_state(&self) -> &DMatrix<f64> {
+            &self.x
+        }
+
+        fn get_allsignals(&self) -> Vec<f64> {
+            vec![self.x[0]]
+        }
+    }
+
+    /* 入力の次数だけ宣言して slopefunc_u・set_input をオーバーライドし忘れたモデル */
+    struct ForgottenInput {
+        x: DMatrix<f64>,
+    }
+
+    impl Model for ForgottenInput {
+        fn slopefunc(&self, _t: f64, x: &DMatrix<f64>) -> DMatrix<f64> {
+            -x
+        }
+
+        fn get_signals_info(&self) -> Vec<String> {
+            vec!["x".to_string()]
+        }
+
+        fn set_state(&mut self, newstate: DMatrix<f64>) {
+            self.x = newstate;
+        }
+
+        fn get_state(&self) -> &DMatrix<f64> {
+            &self.x
+        }
+
+        fn get_allsignals(&self) -> Vec<f64> {
+            vec![self.x[0]]
+        }
+
+        fn get_input_dim(&self) -> usize {
+            1
+        }
+    }
+
+    fn run(model: &mut dyn Model, solvertype: &SolverType, delta_t: f64, steps: usize) {
+        for i in 0..steps {
+            model.calc_nextstate(i as f64 * delta_t, delta_t, solvertype);
+        }
+    }
+
+    #[test]
+    fn dormand_prince_matches_exponential_and_keeps_step() {
+        let solver = SolverType::dormand_prince(1e-6, 1e-9, 1e-6, 1.0).unwrap();
+        let mut model = Decay::new(1.0, 1.0);
+        run(&mut model, &solver, 0.01, 100);
+        assert!((model.x[0] - (-1.0_f64).exp()).abs() < 1e-6);
+
+        // 刻み幅はモデルに残り、出力の間隔を超えて大きくなる
+        assert!(model.step_control.last_step > 0.01);
+        assert!(model.step_control.failure.is_none());
+    }
+
+    #[test]
+    fn dormand_prince_keeps_step_per_model() {
+        // 同じ SolverType を使い回しても、刻み幅はモデルごとに別に引き継がれる
+        let solver = SolverType::dormand_prince(1e-6, 1e-9, 1e-6, 1.0).unwrap();
+        let mut slow = Decay::new(1.0, 1.0);
+        let mut fast = Decay::new(1e3, 1.0);
+        run(&mut slow, &solver, 0.01, 10);
+        run(&mut fast, &solver.clone(), 0.01, 10);
+        assert!(slow.step_control.last_step > 10.0 * fast.step_control.last_step);
+
+        fn assert_sync<T: Sync>(_: &T) {}
+        assert_sync(&solver);
+    }
+
+    #[test]
+    fn dormand_prince_rejects_invalid_settings() {
+        assert!(SolverType::dormand_prince(1e-6, 1e-9, 0.0, 1.0).is_err());
+        assert!(SolverType::dormand_prince(1e-6, 1e-9, 1.0, 0.1).is_err());
+        assert!(SolverType::dormand_prince(0.0, 0.0, 1e-6, 1.0).is_err());
+    }
+
+    #[test]
+    fn dormand_prince_stops_on_non_finite_slope() {
+        // x >= 2 で傾きが NaN になるモデルは、x = 2 の手前の有限の状態で止まって理由を残す
+        let solver = SolverType::dormand_prince(1e-6, 1e-9, 1e-6, 1.0).unwrap();
+        let mut model = Decay::new(-1.0, 1.0);
+        model.limit = 2.0;
+        run(&mut model, &solver, 1.0, 1);
+        assert!(model.x[0].is_finite());
+        assert!(model.x[0] > 1.9 && model.x[0] < 2.0);
+        assert!(model.step_control.failure.is_some());
+
+        // 続けて呼んでも状態は有限のまま
+        run(&mut model, &solver, 1.0, 1);
+        assert!(model.x[0].is_finite());
+
+        // Simulatorは最初に打ち切ったステップを返す
+        let mut model = Decay::new(-1.0, 1.0);
+        model.limit = 2.0;
+        let mut sim = Simulator::new(2.0, 0.5, solver, model);
+        sim.run_sim();
+        assert_eq!(sim.get_solver_failure().map(|(t, _)| t), Some(0.5));
+        assert!(sim.get_simdata("x").unwrap().iter().all(|x| x.is_finite()));
+    }
+
+    #[test]
+    #[should_panic]
+    fn default_slopefunc_u_rejects_models_with_input() {
+        let model = ForgottenInput { x: DMatrix::from_element(1, 1, 1.0) };
+        model.slopefunc_u(0.0, &model.x, &DMatrix::from_element(1, 1, 1.0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn default_set_input_rejects_models_with_input() {
+        let mut model = ForgottenInput { x: DMatrix::from_element(1, 1, 1.0) };
+        model.set_input(&[1.0]);
+    }
+
+    #[test]
+    fn implicit_methods_match_exponential_decay() {
+        let expected = (-1.0_f64).exp();
+        for (solvertype, tol) in [(SolverType::BackwardEuler, 1e-2), (SolverType::Trapezoidal, 1e-5), (SolverType::TrBdf2, 1e-5)] {
+            let mut model = Decay::new(1.0, 1.0);
+            run(&mut model, &solvertype, 0.01, 100);
+            assert!((model.x[0] - expected).abs() < tol, "{:?}: {}", solvertype, model.x[0]);
+        }
+
+        // 後退オイラー法は1ステップで x1 = x0 / (1 + kΔt)
+        let mut model = Decay::new(2.0, 1.0);
+        run(&mut model, &SolverType::BackwardEuler, 0.5, 1);
+        assert!((model.x[0] - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn implicit_methods_survive_stiff_nonlinear_model() {
+        // 台形則はL安定でないので原点のまわりで振動するが、発散やパニックはしない
+        for (solvertype, bound) in [(SolverType::BackwardEuler, 1e-3), (SolverType::Trapezoidal, 10.0), (SolverType::TrBdf2, 1e-3)] {
+            let mut model = StiffAtan { x: DMatrix::from_element(1, 1, 10.0) };
+            run(&mut model, &solvertype, 1.0, 5);
+            assert!(model.x[0].is_finite() && model.x[0].abs() < bound, "{:?}: {}", solvertype, model.x[0]);
+        }
+    }
+
+    #[test]
+    fn exact_matches_runge_kutta_on_linear_model() {
+        let mut exact = SpaceStateModel::new(2, 1, 1);
+        exact.init_state(&[1.0, 0.0]).unwrap();
+        exact.set_mat_a(&[0.0, 1.0, -4.0, -0.4]).unwrap();
+        exact.set_mat_b(&[0.0, 1.0]).unwrap();
+        exact.set_mat_c(&[1.0, 0.0]).unwrap();
+        exact.set_u(&[0.5]).unwrap();
+        let mut rk = exact.clone();
+
+        run(&mut exact, &SolverType::Exact, 0.01, 500);
+        run(&mut rk, &SolverType::RungeKutta, 0.01, 500);
+        assert!((exact.get_state() - rk.get_state()).amax() < 1e-8);
+    }
+
+    #[test]
+    fn exact_falls_back_to_runge_kutta_on_other_models() {
+        let mut exact = Decay::new(1.0, 1.0);
+        let mut rk = Decay::new(1.0, 1.0);
+        run(&mut exact, &SolverType::Exact, 0.1, 10);
+        run(&mut rk, &SolverType::RungeKutta, 0.1, 10);
+        assert_eq!(exact.x[0], rk.x[0]);
+    }
+
+    #[test]
+    fn butcher_tableaus_have_expected_order() {
+        // 刻み幅を半分にしたときの誤差の比が 2^次数 になる
+        let error = |tableau: &ButcherTableau, delta_t: f64| {
+            let mut model = Decay::new(1.0, 1.0);
+            let steps = (1.0 / delta_t).round() as usize;
+            run(&mut model, &SolverType::Custom(tableau.clone()), delta_t, steps);
+            (model.x[0] - (-1.0_f64).exp()).abs()
+        };
+        let tableaus = [
+            (ButcherTableau::euler(), 1.0), (ButcherTableau::heun(), 2.0), (ButcherTableau::midpoint(), 2.0),
+            (ButcherTableau::ralston(), 2.0), (ButcherTableau::rk3(), 3.0), (ButcherTableau::rk4(), 4.0),
+            (ButcherTableau::rk38(), 4.0),
+        ];
+        for (tableau, order) in tableaus.iter() {
+            let ratio = error(tableau, 0.02) / error(tableau, 0.01);
+            assert!((ratio.log2() - order).abs() < 0.1, "{:?}: {}", tableau, ratio.log2());
+        }
+    }
+
+    #[test]
+    fn butcher_tableau_rejects_implicit_or_mismatched_table() {
+        assert!(ButcherTableau::new(&[&[0.5]], &[1.0], &[0.5]).is_err());
+        assert!(ButcherTableau::new(&[&[0.0, 0.0]], &[0.5, 0.5], &[0.0, 1.0]).is_err());
+        assert!(ButcherTableau::new(&[], &[], &[]).is_err());
+    }
+
+    #[test]
+    fn solvers_pass_stage_time_to_slopefunc() {
+        let solvers = [
+            (SolverType::RungeKutta, 1e-9), (SolverType::Custom(ButcherTableau::heun()), 1e-4),
+            (SolverType::Trapezoidal, 1e-4), (SolverType::TrBdf2, 1e-4),
+        ];
+        for (solvertype, tol) in solvers.iter() {
+            let mut model = Cosine { x: DMatrix::zeros(1, 1) };
+            run(&mut model, solvertype, 0.01, 100);
+            assert!((model.x[0] - 1.0_f64.sin()).abs() < *tol, "{:?}: {}", solvertype, model.x[0]);
+        }
+    }
+}
@@ -0,0 +1,411 @@
+/* 非線形要素 */
+// 飽和・不感帯・バックラッシ・レートリミッタ・ヒステリシス付きリレー・量子化器
+// 1入力1出力で、入力ポートは "u"、出力ポートは "y" として記録・結線する
+
+use std::fmt;
+
+extern crate nalgebra as na;
+use na::DMatrix;
+
+use super::simmodel::{*};
+
+/* 入力の値だけで出力が決まる非線形要素（リレー・バックラッシ・レートリミッタのように前の出力を覚えているものを含む）
+   メモリは入力が確定したとき（set_input・calc_nextstate）に更新するので、ステップの途中の段では前のステップの値が使われる
+   時刻tはモデルに渡されたシミュレーション時刻で、メモリを更新した時刻からの経過時間が要る要素（レートリミッタ）が使う */
+pub trait Nonlinearity: fmt::Debug {
+    fn eval(&self, t: f64, u: f64) -> f64;      // 現在のメモリと時刻t・入力uに対する出力
+    fn commit(&mut self, _t: f64, _u: f64) {}   // 時刻tに入力uが確定したときにメモリを更新する（メモリを持たない要素は何もしない）
+    fn has_feedthrough(&self) -> bool {         // 出力が現在の入力に直接依存するか（確定したメモリだけで出力が決まる要素はfalse）
+        true
+    }
+}
+
+/* 飽和 */
+#[derive(Debug, Clone)]
+pub struct Saturation {
+    lower: f64, // 下限
+    upper: f64, // 上限
+}
+
+impl Saturation {
+    pub fn new(lower: f64, upper: f64) -> Result<Self, &'static str> {
+        if lower > upper {
+            return Err("下限が上限より大きくなっています。");
+        }
+        Ok(Self { lower: lower, upper: upper })
+    }
+}
+
+impl Nonlinearity for Saturation {
+    fn eval(&self, _t: f64, u: f64) -> f64 {
+        u.max(self.lower).min(self.upper)
+    }
+}
+
+/* 不感帯（lower～upperの間は0、外側では境界からの差を出力する） */
+#[derive(Debug, Clone)]
+pub struct DeadZone {
+    lower: f64,
+    upper: f64,
+}
+
+impl DeadZone {
+    pub fn new(lower: f64, upper: f64) -> Result<Self, &'static str> {
+        if lower > upper {
+            return Err("下限が上限より大きくなっています。");
+        }
+        Ok(Self { lower: lower, upper: upper })
+    }
+}
+
+impl Nonlinearity for DeadZone {
+    fn eval(&self, _t: f64, u: f64) -> f64 {
+        if u > self.upper {
+            u - self.upper
+        } else if u < self.lower {
+            u - self.lower
+        } else {
+            0.0
+        }
+    }
+}
+
+/* バックラッシ（幅widthの遊び。入力が遊びの端に当たっている間だけ出力が動く） */
+#[derive(Debug, Clone)]
+pub struct Backlash {
+    width: f64,     // 遊びの幅
+    output: f64,    // 前回確定した出力
+}
+
+impl Backlash {
+    pub fn new(width: f64, init_output: f64) -> Result<Self, &'static str> {
+        if width < 0.0 {
+            return Err("遊びの幅は0以上にしてください。");
+        }
+        Ok(Self { width: width, output: init_output })
+    }
+}
+
+impl Nonlinearity for Backlash {
+    fn eval(&self, _t: f64, u: f64) -> f64 {
+        self.output.max(u - self.width / 2.0).min(u + self.width / 2.0)
+    }
+
+    fn commit(&mut self, t: f64, u: f64) {
+        self.output = self.eval(t, u);
+    }
+}
+
+/* ヒステリシス付きリレー（入力がon_threshold以上でon_value、off_threshold以下でoff_value、その間は前の出力を保つ）
+   切り替えは入力が確定したときに行うので、切り替わる時刻は刻み幅の分だけ遅れることがある */
+#[derive(Debug, Clone)]
+pub struct Relay {
+    on_threshold: f64,
+    off_threshold: f64,
+    on_value: f64,
+    off_value: f64,
+    on: bool,           // 現在onか
+}
+
+impl Relay {
+    pub fn new(on_threshold: f64, off_threshold: f64, on_value: f64, off_value: f64) -> Result<Self, &'static str> {
+        if off_threshold > on_threshold {
+            return Err("offになる閾値がonになる閾値より大きくなっています。");
+        }
+        Ok(Self {
+            on_threshold: on_threshold,
+            off_threshold: off_threshold,
+            on_value: on_value,
+            off_value: off_value,
+            on: false,
+        })
+    }
+
+    pub fn set_on(&mut self, on: bool) { // 初期状態（既定はoff）
+        self.on = on;
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    fn next_on(&self, u: f64) -> bool {
+        if u >= self.on_threshold {
+            true
+        } else if u <= self.off_threshold {
+            false
+        } else {
+            self.on
+        }
+    }
+}
+
+impl Nonlinearity for Relay {
+    fn eval(&self, _t: f64, _u: f64) -> f64 { // 段ごとに切り替えるとチャタリングするので、確定した状態だけで出力を決める
+        if self.on { self.on_value } else { self.off_value }
+    }
+
+    fn commit(&mut self, _t: f64, u: f64) {
+        self.on = self.next_on(u);
+    }
+
+    fn has_feedthrough(&self) -> bool { // 出力は確定した状態だけで決まるので、ループの中に置いても代数ループにならない
+        false
+    }
+}
+
+/* レートリミッタ（出力の変化率を rise 以下、-fall 以上に制限する）
+   前回メモリを更新した時刻からの経過時間dtの間に y += clamp(u - y, -fall dt, rise dt) だけ動く */
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    rise: f64,      // 上昇方向の変化率の上限
+    fall: f64,      // 下降方向の変化率の上限（正の値）
+    output: f64,    // 前回確定した出力
+    t_last: f64,    // 前回確定した時刻
+}
+
+impl RateLimiter {
+    pub fn new(rise: f64, fall: f64, init_output: f64) -> Result<Self, &'static str> {
+        if rise <= 0.0 || fall <= 0.0 {
+            return Err("変化率の上限は正の値にしてください。");
+        }
+        Ok(Self { rise: rise, fall: fall, output: init_output, t_last: 0.0 })
+    }
+}
+
+impl Nonlinearity for RateLimiter {
+    fn eval(&self, t: f64, u: f64) -> f64 {
+        let dt = (t - self.t_last).max(0.0);
+        self.output + (u - self.output).max(-self.fall * dt).min(self.rise * dt)
+    }
+
+    fn commit(&mut self, t: f64, u: f64) {
+        self.output = self.eval(t, u);
+        self.t_last = t;
+    }
+}
+
+/* 量子化器（interval刻みの最も近い値に丸める） */
+#[derive(Debug, Clone)]
+pub struct Quantizer {
+    interval: f64,
+}
+
+impl Quantizer {
+    pub fn new(interval: f64) -> Result<Self, &'static str> {
+        if interval <= 0.0 {
+            return Err("量子化の間隔は正の値にしてください。");
+        }
+        Ok(Self { interval: interval })
+    }
+}
+
+impl Nonlinearity for Quantizer {
+    fn eval(&self, _t: f64, u: f64) -> f64 {
+        self.interval * (u / self.interval).round()
+    }
+}
+
+/* 非線形要素をモデルとして使うためのブロック
+   状態を持たず（0次元）、要素には出力を計算する時刻をそのまま渡す
+   メモリはステップの終わりの時刻 t + delta_t に update_discrete で確定する */
+#[derive(Debug, Clone)]
+pub struct NonlinearBlock<N>
+where N: Nonlinearity
+{
+    element: N,
+    x: DMatrix<f64>,    // 空の状態
+    t: f64,             // 最後にメモリを確定した時刻
+    u: f64,
+}
+
+impl<N> NonlinearBlock<N>
+where N: Nonlinearity
+{
+    pub fn new(element: N) -> Self {
+        Self {
+            element: element,
+            x: DMatrix::zeros(0, 1),
+            t: 0.0,
+            u: 0.0,
+        }
+    }
+
+    pub fn get_element(&self) -> &N {
+        &self.element
+    }
+
+    pub fn set_u(&mut self, u: f64) {
+        self.u = u;
+        self.element.commit(self.t, u);
+    }
+}
+
+impl<N> Model for NonlinearBlock<N>
+where N: Nonlinearity
+{
+    fn slopefunc(&self, _t: f64, _x: &DMatrix<f64>) -> DMatrix<f64> { // 状態を持たない
+        DMatrix::zeros(0, 1)
+    }
+
+    fn get_signals_info(&self) -> Vec<String> {
+        vec!["u".to_string(), "y".to_string()]
+    }
+
+    fn set_state(&mut self, newstate: DMatrix<f64>) {
+        self.x = newstate;
+    }
+
+    fn get_state(&self) -> &DMatrix<f64> {
+        &self.x
+    }
+
+    fn get_allsignals(&self) -> Vec<f64> {
+        vec![self.u, self.element.eval(self.t, self.u)]
+    }
+
+    fn update_discrete(&mut self, t: f64, delta_t: f64) { // ステップの終わりの時刻に現在の入力でメモリを確定する
+        self.t = t + delta_t;
+        self.element.commit(self.t, self.u);
+    }
+
+    fn get_input_dim(&self) -> usize {
+        1
+    }
+
+    fn get_input(&self) -> DMatrix<f64> {
+        DMatrix::from_element(1, 1, self.u)
+    }
+
+    fn set_input(&mut self, u: &[f64]) {
+        self.set_u(u[0]);
+    }
+
+    fn slopefunc_u(&self, t: f64, x: &DMatrix<f64>, _u: &DMatrix<f64>) -> DMatrix<f64> { // 状態を持たない
+        self.slopefunc(t, x)
+    }
+
+    fn get_output_dim(&self) -> usize {
+        1
+    }
+
+    fn outputfunc(&self, t: f64, _x: &DMatrix<f64>, u: &DMatrix<f64>) -> DMatrix<f64> {
+        DMatrix::from_element(1, 1, self.element.eval(t, u[0]))
+    }
+
+    fn has_feedthrough(&self) -> bool {
+        self.element.has_feedthrough()
+    }
+
+    fn get_input_names(&self) -> Vec<String> {
+        vec!["u".to_string()]
+    }
+
+    fn get_output_names(&self) -> Vec<String> {
+        vec!["y".to_string()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::simgraph::{*};
+    use super::super::simlinearize::linearize;
+
+    fn output<N: Nonlinearity>(block: &NonlinearBlock<N>) -> f64 {
+        block.outputfunc(0.0, block.get_state(), &block.get_input())[0]
+    }
+
+    #[test]
+    fn memoryless_elements() {
+        let sat = Saturation::new(-1.0, 2.0).unwrap();
+        assert_eq!((sat.eval(0.0, -3.0), sat.eval(0.0, 0.5), sat.eval(0.0, 3.0)), (-1.0, 0.5, 2.0));
+        let dz = DeadZone::new(-1.0, 1.0).unwrap();
+        assert_eq!((dz.eval(0.0, -3.0), dz.eval(0.0, 0.5), dz.eval(0.0, 3.0)), (-2.0, 0.0, 2.0));
+        let q = Quantizer::new(0.5).unwrap();
+        assert_eq!((q.eval(0.0, 0.7), q.eval(0.0, -0.8)), (0.5, -1.0));
+        assert!(Saturation::new(1.0, -1.0).is_err());
+    }
+
+    #[test]
+    fn backlash_and_relay_remember_output() {
+        let mut backlash = NonlinearBlock::new(Backlash::new(1.0, 0.0).unwrap());
+        backlash.set_input(&[2.0]);
+        assert_eq!(output(&backlash), 1.5);
+        backlash.set_input(&[1.5]); // 遊びの中では動かない
+        assert_eq!(output(&backlash), 1.5);
+        backlash.set_input(&[0.0]);
+        assert_eq!(output(&backlash), 0.5);
+
+        let mut relay = NonlinearBlock::new(Relay::new(1.0, -1.0, 5.0, -5.0).unwrap());
+        for (u, y) in [(0.0, -5.0), (1.0, 5.0), (0.0, 5.0), (-1.0, -5.0), (0.5, -5.0)] {
+            relay.set_input(&[u]);
+            assert_eq!(output(&relay), y);
+        }
+    }
+
+    #[test]
+    fn rate_limiter_moves_at_limited_rate() {
+        // 変化率100/sなら0.5までは5msで達する
+        let mut limiter = NonlinearBlock::new(RateLimiter::new(100.0, 100.0, 0.0).unwrap());
+        limiter.set_input(&[0.5]);
+        let mut y = Vec::new();
+        for i in 0..6 {
+            limiter.calc_nextstate(i as f64 * 1e-3, 1e-3, &SolverType::RungeKutta);
+            y.push(output(&limiter));
+        }
+        let expected = [0.1, 0.2, 0.3, 0.4, 0.5, 0.5];
+        assert!(y.iter().zip(expected.iter()).all(|(a, b)| (a - b).abs() < 1e-12), "{:?}", y);
+
+        limiter.set_input(&[-1.0]);
+        limiter.calc_nextstate(6e-3, 2e-3, &SolverType::RungeKutta);
+        assert!((output(&limiter) - 0.3).abs() < 1e-12);
+    }
+
+    #[test]
+    fn rate_limiter_in_signal_graph() {
+        let mut builder = SignalGraphBuilder::new();
+        builder.add_input("r").unwrap();
+        builder.add_block("rl", Box::new(NonlinearBlock::new(RateLimiter::new(1.0, 1.0, 0.0).unwrap()))).unwrap();
+        builder.connect("r", "rl.u").unwrap();
+        builder.add_output("y", "rl.y").unwrap();
+        let mut graph = builder.build().unwrap();
+
+        graph.set_input(&[1.0]);
+        for i in 0..30 {
+            graph.calc_nextstate(i as f64 * 0.01, 0.01, &SolverType::RungeKutta);
+        }
+        let y = graph.outputfunc(0.3, graph.get_state(), &graph.get_input())[0];
+        assert!((y - 0.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn memoryless_block_has_no_state() {
+        // 状態を持たないので、動作点のまわりの線形化は直達項だけになる
+        let block = NonlinearBlock::new(Saturation::new(-1.0, 1.0).unwrap());
+        assert_eq!(block.get_state().nrows(), 0);
+        let inside = linearize(&block, 0.0, &[], &[0.5]).unwrap();
+        let outside = linearize(&block, 0.0, &[], &[3.0]).unwrap();
+        assert!((inside.get_mat_d()[(0, 0)] - 1.0).abs() < 1e-6);
+        assert!(outside.get_mat_d()[(0, 0)].abs() < 1e-6);
+    }
+
+    #[test]
+    fn relay_in_feedback_loop_is_not_algebraic() {
+        // e = r - y, y = relay(e) のループ
+        let loop_with = |block: Box<dyn Model>| {
+            let mut sum = SpaceStateModel::new(1, 2, 1);
+            sum.set_mat_d(&[1.0, -1.0]).unwrap();
+            let mut builder = SignalGraphBuilder::new();
+            builder.add_input("r").unwrap();
+            builder.add_block("sum", Box::new(sum)).unwrap();
+            builder.add_block("nl", block).unwrap();
+            builder.connect("r", "sum.u_0").unwrap();
+            builder.connect("nl.y", "sum.u_1").unwrap();
+            builder.connect("sum.y_0", "nl.u").unwrap();
+            builder.build()
+        };
+        assert!(loop_with(Box::new(NonlinearBlock::new(Relay::new(0.1, -0.1, 1.0, -1.0).unwrap()))).is_ok());
+        assert!(loop_with(Box::new(NonlinearBlock::new(Saturation::new(-1.0, 1.0).unwrap()))).is_err());
+    }
+}
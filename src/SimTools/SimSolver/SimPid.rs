@@ -0,0 +1,629 @@
+/* PID制御器 */
+// 並列形・理想形のゲイン、1次フィルタ付きの微分、目標値の重み、出力の飽和とアンチワインドアップ、手動・自動の切り替え
+// 入力は [目標値 r, 観測値 y]、出力は操作量 u
+
+extern crate nalgebra as na;
+use na::DMatrix;
+
+use super::simmodel::{*};
+use super::simdiscrete::{TICK_EPS};
+use super::simhybrid::{*};
+
+/* アンチワインドアップの方式 */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AntiWindup {
+    None,
+    BackCalculation { tt: f64 },    // 飽和した量を時定数ttで積分器に戻す
+    Clamping,                       // 飽和している向きへの積分を止める
+}
+
+/* 運転モード */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PidMode {
+    Auto,   // PID則で操作量を計算する
+    Manual, // 手動で与えた操作量をそのまま出力する
+}
+
+/* PIDのパラメータ（並列形 u = Kp (b r - y) + Ki ∫(r - y) + Kd s / (Tf s + 1) (c r - y)） */
+#[derive(Debug, Clone)]
+pub struct PidParams {
+    kp: f64,                // 比例ゲイン
+    ki: f64,                // 積分ゲイン
+    kd: f64,                // 微分ゲイン
+    tf: f64,                // 微分フィルタの時定数
+    b: f64,                 // 比例項の目標値の重み
+    c: f64,                 // 微分項の目標値の重み
+    u_min: f64,             // 操作量の下限
+    u_max: f64,             // 操作量の上限
+    antiwindup: AntiWindup,
+}
+
+impl PidParams {
+    /* 並列形 Kp + Ki / s + Kd s / (Tf s + 1) */
+    pub fn new(kp: f64, ki: f64, kd: f64, tf: f64) -> Result<Self, &'static str> {
+        if tf < 0.0 {
+            return Err("微分フィルタの時定数は0以上にしてください。");
+        }
+        Ok(Self {
+            kp: kp,
+            ki: ki,
+            kd: kd,
+            tf: tf,
+            b: 1.0,
+            c: 1.0,
+            u_min: f64::NEG_INFINITY,
+            u_max: f64::INFINITY,
+            antiwindup: AntiWindup::None,
+        })
+    }
+
+    /* 理想形 K (1 + 1 / (Ti s) + Td s / (Td / N s + 1))
+       積分を使わないときは ti に f64::INFINITY、微分のフィルタを使わないときは n に f64::INFINITY を与える */
+    pub fn from_ideal(k: f64, ti: f64, td: f64, n: f64) -> Result<Self, &'static str> {
+        if ti <= 0.0 || td < 0.0 || n <= 0.0 {
+            return Err("積分時間・フィルタ係数は正、微分時間は0以上にしてください。");
+        }
+        PidParams::new(k, k / ti, k * td, td / n)
+    }
+
+    pub fn set_setpoint_weights(&mut self, b: f64, c: f64) {
+        self.b = b;
+        self.c = c;
+    }
+
+    pub fn set_output_limits(&mut self, u_min: f64, u_max: f64) -> Result<(), &'static str> {
+        if u_min > u_max {
+            return Err("操作量の下限が上限より大きくなっています。");
+        }
+        self.u_min = u_min;
+        self.u_max = u_max;
+        Ok(())
+    }
+
+    pub fn set_antiwindup(&mut self, antiwindup: AntiWindup) -> Result<(), &'static str> {
+        if let AntiWindup::BackCalculation { tt } = antiwindup {
+            if tt <= 0.0 {
+                return Err("バックカリキュレーションの時定数は正の値にしてください。");
+            }
+        }
+        self.antiwindup = antiwindup;
+        Ok(())
+    }
+
+    pub fn get_gains(&self) -> (f64, f64, f64, f64) { // (Kp, Ki, Kd, Tf)
+        (self.kp, self.ki, self.kd, self.tf)
+    }
+
+    fn saturate(&self, v: f64) -> f64 {
+        v.max(self.u_min).min(self.u_max)
+    }
+
+    /* 積分器の入力（アンチワインドアップを含む） vは飽和前、uは飽和後の操作量 */
+    fn integrator_input(&self, e: f64, v: f64, u: f64) -> f64 {
+        match self.antiwindup {
+            AntiWindup::None => self.ki * e,
+            AntiWindup::BackCalculation { tt } => self.ki * e + (u - v) / tt,
+            AntiWindup::Clamping => {
+                let pushing = (v > self.u_max && self.ki * e > 0.0) || (v < self.u_min && self.ki * e < 0.0);
+                if pushing { 0.0 } else { self.ki * e }
+            },
+        }
+    }
+}
+
+/* 各項の値（記録用） */
+struct PidTerms {
+    e: f64, // 偏差 r - y
+    p: f64, // 比例項
+    i: f64, // 積分項
+    d: f64, // 微分項
+    v: f64, // 飽和前の操作量
+    u: f64, // 出力する操作量
+}
+
+impl PidTerms {
+    fn to_vec(&self, r: f64, y: f64) -> Vec<f64> {
+        vec![r, y, self.e, self.p, self.i, self.d, self.u]
+    }
+}
+
+fn pid_signals_info() -> Vec<String> {
+    vec!["r", "y", "e", "p", "i", "d", "u"]
+        .iter().map(|x| x.to_string()).collect::<Vec<String>>()
+}
+
+/* 連続時間のPID制御器
+   状態は [積分項, 微分フィルタの状態]、手動モードの間は積分項を止め、自動に戻すときに出力が連続になるよう積分項を合わせる */
+#[derive(Debug, Clone)]
+pub struct PidController {
+    params: PidParams,
+    x: DMatrix<f64>,    // [積分項, 微分フィルタの状態]
+    u: DMatrix<f64>,    // [r, y]
+    mode: PidMode,
+    u_manual: f64,      // 手動モードの操作量
+}
+
+impl PidController {
+    pub fn new(params: PidParams) -> Result<Self, &'static str> {
+        PidController::check_params(&params)?;
+        Ok(Self {
+            params: params,
+            x: DMatrix::from_element(2, 1, 0.0),
+            u: DMatrix::from_element(2, 1, 0.0),
+            mode: PidMode::Auto,
+            u_manual: 0.0,
+        })
+    }
+
+    fn check_params(params: &PidParams) -> Result<(), &'static str> {
+        if params.kd != 0.0 && params.tf == 0.0 {
+            return Err("連続時間のPIDで微分を使うときはフィルタの時定数を正にしてください。");
+        }
+        Ok(())
+    }
+
+    pub fn get_params(&self) -> &PidParams {
+        &self.params
+    }
+
+    /* パラメータを変更する（積分項は値をそのまま引き継ぐので、ゲインを変えても操作量は跳ばない） */
+    pub fn set_params(&mut self, params: PidParams) -> Result<(), &'static str> {
+        PidController::check_params(&params)?;
+        self.params = params;
+        Ok(())
+    }
+
+    /* 積分項と微分フィルタを初期化する（微分フィルタは現在の入力に合わせるので、時刻0で微分項が跳ばない） */
+    pub fn init_state(&mut self, integral: f64) {
+        let ed = self.params.c * self.u[0] - self.u[1];
+        self.x = DMatrix::from_column_slice(2, 1, &[integral, ed]);
+    }
+
+    pub fn set_r(&mut self, r: f64) {
+        self.u[0] = r;
+    }
+
+    pub fn set_y(&mut self, y: f64) {
+        self.u[1] = y;
+    }
+
+    pub fn get_mode(&self) -> PidMode {
+        self.mode
+    }
+
+    /* 運転モードを切り替える（どちらの向きでも操作量が連続になるようにする） */
+    pub fn set_mode(&mut self, mode: PidMode) {
+        if mode == self.mode {
+            return;
+        }
+        match mode {
+            PidMode::Manual => {
+                self.u_manual = self.terms(&self.x, &self.u).u;
+            },
+            PidMode::Auto => {
+                let terms = self.terms(&self.x, &self.u);
+                self.x[0] = self.u_manual - terms.p - terms.d;
+            },
+        }
+        self.mode = mode;
+    }
+
+    /* 手動モードの操作量（自動モードの間に与えた値は手動に切り替えたときに上書きされる） */
+    pub fn set_manual_output(&mut self, u: f64) {
+        self.u_manual = u;
+    }
+
+    fn terms(&self, x: &DMatrix<f64>, u: &DMatrix<f64>) -> PidTerms {
+        let pr = &self.params;
+        let (r, y) = (u[0], u[1]);
+        let e = r - y;
+        let p = pr.kp * (pr.b * r - y);
+        let i = x[0];
+        let d = if pr.kd != 0.0 { pr.kd * (pr.c * r - y - x[1]) / pr.tf } else { 0.0 };
+        let v = p + i + d;
+        let out = match self.mode {
+            PidMode::Auto => pr.saturate(v),
+            PidMode::Manual => pr.saturate(self.u_manual),
+        };
+        PidTerms { e: e, p: p, i: i, d: d, v: v, u: out }
+    }
+}
+
+impl Model for PidController {
+    fn slopefunc(&self, t: f64, x: &DMatrix<f64>) -> DMatrix<f64> {
+        self.slopefunc_u(t, x, &self.u)
+    }
+
+    fn get_signals_info(&self) -> Vec<String> {
+        pid_signals_info()
+    }
+
+    fn set_state(&mut self, newstate: DMatrix<f64>) {
+        self.x = newstate;
+    }
+
+    fn get_state(&self) -> &DMatrix<f64> {
+        &self.x
+    }
+
+    fn get_allsignals(&self) -> Vec<f64> {
+        self.terms(&self.x, &self.u).to_vec(self.u[0], self.u[1])
+    }
+
+    fn get_input_dim(&self) -> usize {
+        2
+    }
+
+    fn get_input(&self) -> DMatrix<f64> {
+        self.u.clone()
+    }
+
+    fn set_input(&mut self, u: &[f64]) {
+        self.u.copy_from_slice(u);
+    }
+
+    fn slopefunc_u(&self, _t: f64, x: &DMatrix<f64>, u: &DMatrix<f64>) -> DMatrix<f64> {
+        let pr = &self.params;
+        let terms = self.terms(x, u);
+        let di = match self.mode {
+            PidMode::Auto => pr.integrator_input(terms.e, terms.v, terms.u),
+            PidMode::Manual => 0.0,
+        };
+        let dz = if pr.tf > 0.0 { (pr.c * u[0] - u[1] - x[1]) / pr.tf } else { 0.0 };
+        DMatrix::from_column_slice(2, 1, &[di, dz])
+    }
+
+    fn get_output_dim(&self) -> usize {
+        1
+    }
+
+    fn outputfunc(&self, _t: f64, x: &DMatrix<f64>, u: &DMatrix<f64>) -> DMatrix<f64> {
+        DMatrix::from_element(1, 1, self.terms(x, u).u)
+    }
+
+    fn has_feedthrough(&self) -> bool {
+        self.params.kp != 0.0 || self.params.kd != 0.0
+    }
+
+    fn get_input_names(&self) -> Vec<String> {
+        vec!["r".to_string(), "y".to_string()]
+    }
+
+    fn get_output_names(&self) -> Vec<String> {
+        vec!["u".to_string()]
+    }
+}
+
+/* 離散時間のPID制御器（サンプル周期tsごとに実行する）
+   積分は前進差分、微分はフィルタ付きの後退差分 d[k] = Tf / (Tf + ts) d[k-1] + Kd / (Tf + ts) (ed[k] - ed[k-1]) で計算する
+   DiscreteSpaceStateModel と同じく、入力はサンプル時刻に取り込んで次のサンプル時刻まで保持する */
+#[derive(Debug, Clone)]
+pub struct DiscretePidController {
+    params: PidParams,
+    ts: f64,                // サンプル周期
+    x: DMatrix<f64>,        // [積分項, 前回の微分項, 前回の微分項の偏差 c r - y]
+    u_live: DMatrix<f64>,   // 外部から与えられている現在の入力 [r, y]
+    u_held: DMatrix<f64>,   // サンプル時刻に取り込んだ入力
+    samples: usize,         // 処理したサンプル時刻の数
+    mode: PidMode,
+    u_manual: f64,
+}
+
+impl DiscretePidController {
+    pub fn new(params: PidParams, ts: f64) -> Result<Self, &'static str> {
+        if ts <= 0.0 {
+            return Err("サンプル周期は正の値にしてください。");
+        }
+        DiscretePidController::check_params(&params, ts)?;
+        Ok(Self {
+            params: params,
+            ts: ts,
+            x: DMatrix::from_element(3, 1, 0.0),
+            u_live: DMatrix::from_element(2, 1, 0.0),
+            u_held: DMatrix::from_element(2, 1, 0.0),
+            samples: 0,
+            mode: PidMode::Auto,
+            u_manual: 0.0,
+        })
+    }
+
+    /* 積分器は前進差分なので、バックカリキュレーションの時定数がサンプル周期より短いと飽和量を戻しすぎて振動する */
+    fn check_params(params: &PidParams, ts: f64) -> Result<(), &'static str> {
+        if let AntiWindup::BackCalculation { tt } = params.antiwindup {
+            if tt < ts {
+                return Err("離散時間のPIDではバックカリキュレーションの時定数をサンプル周期以上にしてください。");
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_params(&self) -> &PidParams {
+        &self.params
+    }
+
+    /* パラメータを変更する（積分項は値をそのまま引き継ぐので、ゲインを変えても操作量は跳ばない） */
+    pub fn set_params(&mut self, params: PidParams) -> Result<(), &'static str> {
+        DiscretePidController::check_params(&params, self.ts)?;
+        self.params = params;
+        Ok(())
+    }
+
+    pub fn get_sample_time(&self) -> f64 {
+        self.ts
+    }
+
+    pub fn set_r(&mut self, r: f64) {
+        self.u_live[0] = r;
+    }
+
+    pub fn set_y(&mut self, y: f64) {
+        self.u_live[1] = y;
+    }
+
+    /* サンプル数を0に戻す（次のサンプルで微分の前回値を取り直す） */
+    pub fn reset_clock(&mut self) {
+        self.samples = 0;
+    }
+
+    pub fn get_mode(&self) -> PidMode {
+        self.mode
+    }
+
+    /* 運転モードを切り替える（どちらの向きでも操作量が連続になるようにする） */
+    pub fn set_mode(&mut self, mode: PidMode) {
+        if mode == self.mode {
+            return;
+        }
+        match mode {
+            PidMode::Manual => {
+                self.u_manual = self.terms(&self.x, &self.u_held).u;
+            },
+            PidMode::Auto => {
+                let terms = self.terms(&self.x, &self.u_held);
+                self.x[0] = self.u_manual - terms.p - terms.d;
+            },
+        }
+        self.mode = mode;
+    }
+
+    pub fn set_manual_output(&mut self, u: f64) {
+        self.u_manual = u;
+    }
+
+    fn terms(&self, x: &DMatrix<f64>, u: &DMatrix<f64>) -> PidTerms {
+        let pr = &self.params;
+        let (r, y) = (u[0], u[1]);
+        let e = r - y;
+        let p = pr.kp * (pr.b * r - y);
+        let i = x[0];
+        let d = (pr.tf * x[1] + pr.kd * (pr.c * r - y - x[2])) / (pr.tf + self.ts);
+        let v = p + i + d;
+        let out = match self.mode {
+            PidMode::Auto => pr.saturate(v),
+            PidMode::Manual => pr.saturate(self.u_manual),
+        };
+        PidTerms { e: e, p: p, i: i, d: d, v: v, u: out }
+    }
+
+    /* 状態を1サンプル進める（xとuはそのサンプル時刻の値） */
+    fn update(&self, x: &DMatrix<f64>, u: &DMatrix<f64>) -> DMatrix<f64> {
+        let terms = self.terms(x, u);
+        let di = match self.mode {
+            PidMode::Auto => self.params.integrator_input(terms.e, terms.v, terms.u),
+            PidMode::Manual => 0.0,
+        };
+        DMatrix::from_column_slice(3, 1, &[x[0] + di * self.ts, terms.d, self.params.c * u[0] - u[1]])
+    }
+}
+
+impl Model for DiscretePidController {
+    fn slopefunc(&self, _t: f64, x: &DMatrix<f64>) -> DMatrix<f64> { // 連続的には変化しない
+        DMatrix::zeros(x.nrows(), 1)
+    }
+
+    fn get_signals_info(&self) -> Vec<String> {
+        pid_signals_info()
+    }
+
+    fn set_state(&mut self, newstate: DMatrix<f64>) {
+        self.x = newstate;
+    }
+
+    fn get_state(&self) -> &DMatrix<f64> {
+        &self.x
+    }
+
+    fn get_allsignals(&self) -> Vec<f64> { // 入力と出力はサンプル時刻に取り込んだ値を保持したもの
+        self.terms(&self.x, &self.u_held).to_vec(self.u_held[0], self.u_held[1])
+    }
+
+    fn calc_nextstate(&mut self, t: f64, delta_t: f64, _solvertype: &SolverType) { // 連続的に変化する状態は無い
+        self.update_discrete(t, delta_t);
+    }
+
+    /* 区間 (t, t + delta_t] に含まれるサンプル時刻ごとに状態を進め、入力を取り込む
+       最初のサンプルでは微分の前回値を現在の入力に合わせる（時刻0で微分項が跳ばない） */
+    fn update_discrete(&mut self, t: f64, delta_t: f64) {
+        while self.samples as f64 * self.ts <= t + delta_t + TICK_EPS * self.ts {
+            if self.samples > 0 {
+                self.x = self.update(&self.x, &self.u_held);
+            }
+            self.u_held = self.u_live.clone();
+            if self.samples == 0 {
+                self.x[1] = 0.0;
+                self.x[2] = self.params.c * self.u_held[0] - self.u_held[1];
+            }
+            self.samples += 1;
+        }
+    }
+
+    fn get_input_dim(&self) -> usize {
+        2
+    }
+
+    fn get_input(&self) -> DMatrix<f64> {
+        self.u_live.clone()
+    }
+
+    fn set_input(&mut self, u: &[f64]) {
+        self.u_live.copy_from_slice(u);
+    }
+
+    fn slopefunc_u(&self, t: f64, x: &DMatrix<f64>, _u: &DMatrix<f64>) -> DMatrix<f64> { // 状態はサンプル時刻に calc_nextstate で更新する
+        self.slopefunc(t, x)
+    }
+
+    fn get_output_dim(&self) -> usize {
+        1
+    }
+
+    /* 操作量はサンプル時刻に取り込んだ入力から計算し、次のサンプルまで保持する（現在の入力は使わない） */
+    fn outputfunc(&self, _t: f64, x: &DMatrix<f64>, _u: &DMatrix<f64>) -> DMatrix<f64> {
+        DMatrix::from_element(1, 1, self.terms(x, &self.u_held).u)
+    }
+
+    fn has_feedthrough(&self) -> bool { // 出力は保持した入力だけで決まる
+        false
+    }
+
+    fn get_input_names(&self) -> Vec<String> {
+        vec!["r".to_string(), "y".to_string()]
+    }
+
+    fn get_output_names(&self) -> Vec<String> {
+        vec!["u".to_string()]
+    }
+}
+
+impl DiscreteController for DiscretePidController {
+    fn get_sample_time(&self) -> f64 {
+        self.ts
+    }
+
+    fn sample(&mut self, t: f64, u: &DMatrix<f64>) -> DMatrix<f64> {
+        sample_by_clock(self, t, u)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output<M: Model>(pid: &M) -> f64 {
+        pid.outputfunc(0.0, pid.get_state(), &pid.get_input())[0]
+    }
+
+    fn run<M: Model>(pid: &mut M, delta_t: f64, steps: usize) {
+        for i in 0..steps {
+            pid.calc_nextstate(i as f64 * delta_t, delta_t, &SolverType::RungeKutta);
+        }
+    }
+
+    #[test]
+    fn set_params_validates_like_new() {
+        let mut pid = PidController::new(PidParams::new(1.0, 1.0, 0.0, 0.0).unwrap()).unwrap();
+        assert!(pid.set_params(PidParams::new(1.0, 1.0, 1.0, 0.0).unwrap()).is_err());
+        assert!(PidController::new(PidParams::new(1.0, 1.0, 1.0, 0.0).unwrap()).is_err());
+
+        let mut params = PidParams::new(1.0, 1.0, 0.0, 0.0).unwrap();
+        let mut dpid = DiscretePidController::new(params.clone(), 0.1).unwrap();
+        params.set_antiwindup(AntiWindup::BackCalculation { tt: 0.05 }).unwrap();
+        assert!(dpid.set_params(params.clone()).is_err());
+        assert!(DiscretePidController::new(params, 0.1).is_err());
+    }
+
+    #[test]
+    fn continuous_pi_integrates_error() {
+        // e = 1 一定なら u = Kp + Ki t
+        let mut pid = PidController::new(PidParams::new(2.0, 3.0, 0.0, 0.0).unwrap()).unwrap();
+        pid.set_input(&[1.0, 0.0]);
+        run(&mut pid, 0.01, 100);
+        assert!((output(&pid) - 5.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn clamping_stops_integration_while_saturated() {
+        let mut params = PidParams::new(0.0, 1.0, 0.0, 0.0).unwrap();
+        params.set_output_limits(-1.0, 1.0).unwrap();
+        params.set_antiwindup(AntiWindup::Clamping).unwrap();
+        let mut pid = PidController::new(params).unwrap();
+        pid.set_input(&[1.0, 0.0]);
+        run(&mut pid, 0.01, 300);
+        assert!((pid.get_state()[0] - 1.0).abs() < 1e-2);
+        assert_eq!(output(&pid), 1.0);
+    }
+
+    #[test]
+    fn mode_switch_is_bumpless() {
+        let mut pid = PidController::new(PidParams::new(2.0, 1.0, 0.0, 0.0).unwrap()).unwrap();
+        pid.set_input(&[1.0, 0.2]);
+        run(&mut pid, 0.01, 50);
+
+        let before = output(&pid);
+        pid.set_mode(PidMode::Manual);
+        assert_eq!(output(&pid), before);
+        pid.set_manual_output(3.0);
+        pid.set_mode(PidMode::Auto);
+        assert!((output(&pid) - 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn discrete_pid_holds_output_between_samples() {
+        // 積分は前進差分なので、k 回目のサンプルでの積分項は Ki ts (k - 1) e
+        let mut pid = DiscretePidController::new(PidParams::new(1.0, 2.0, 0.0, 0.0).unwrap(), 0.1).unwrap();
+        pid.set_input(&[1.0, 0.0]);
+        run(&mut pid, 0.01, 100);
+        let u = pid.outputfunc(1.0, pid.get_state(), &DMatrix::from_column_slice(2, 1, &[1.0, 0.0]))[0];
+        assert!((u - (1.0 + 2.0 * 0.1 * 10.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn discrete_pid_holds_proportional_term_between_samples() {
+        // サンプルの間に y が変わっても、次のサンプル時刻までは前に取り込んだ y で計算した操作量を出す
+        let mut pid = DiscretePidController::new(PidParams::new(1.0, 0.0, 0.0, 0.0).unwrap(), 0.1).unwrap();
+        pid.set_input(&[1.0, 0.0]);
+        pid.calc_nextstate(0.0, 0.01, &SolverType::RungeKutta);
+        pid.set_input(&[1.0, 0.5]);
+        assert_eq!(output(&pid), 1.0);
+        assert_eq!(pid.outputfunc(0.01, pid.get_state(), &pid.get_input())[0], 1.0);
+
+        for i in 1..10 {
+            pid.calc_nextstate(i as f64 * 0.01, 0.01, &SolverType::RungeKutta);
+        }
+        assert!((output(&pid) - 0.5).abs() < 1e-12);
+        assert!(!pid.has_feedthrough());
+    }
+
+    #[test]
+    fn ideal_form_converts_to_parallel_gains() {
+        let (kp, ki, kd, tf) = PidParams::from_ideal(2.0, 0.5, 0.1, 10.0).unwrap().get_gains();
+        assert!((kp - 2.0).abs() < 1e-12 && (ki - 4.0).abs() < 1e-12);
+        assert!((kd - 0.2).abs() < 1e-12 && (tf - 0.01).abs() < 1e-12);
+
+        let (_, ki, _, tf) = PidParams::from_ideal(2.0, f64::INFINITY, 0.1, f64::INFINITY).unwrap().get_gains();
+        assert_eq!((ki, tf), (0.0, 0.0));
+        assert!(PidParams::from_ideal(2.0, 0.0, 0.1, 10.0).is_err());
+        assert!(PidParams::from_ideal(2.0, 0.5, -0.1, 10.0).is_err());
+    }
+
+    #[test]
+    fn back_calculation_limits_integral_while_saturated() {
+        // 飽和中は dI/dt = Ki e + (u - v) / Tt なので、積分項は u_max + Ki e Tt で止まる
+        let mut params = PidParams::new(0.0, 1.0, 0.0, 0.0).unwrap();
+        params.set_output_limits(-1.0, 1.0).unwrap();
+        assert!(params.set_antiwindup(AntiWindup::BackCalculation { tt: 0.0 }).is_err());
+        params.set_antiwindup(AntiWindup::BackCalculation { tt: 0.5 }).unwrap();
+        let mut pid = PidController::new(params).unwrap();
+        pid.set_input(&[1.0, 0.0]);
+        run(&mut pid, 0.01, 1000);
+        assert!((pid.get_state()[0] - 1.5).abs() < 1e-6);
+        assert_eq!(output(&pid), 1.0);
+
+        // 偏差の符号が変わると、積分項が小さく抑えられているのですぐに飽和から抜ける
+        pid.set_input(&[-1.0, 0.0]);
+        for i in 0..100 {
+            pid.calc_nextstate(10.0 + i as f64 * 0.01, 0.01, &SolverType::RungeKutta);
+        }
+        assert!(output(&pid) < 1.0);
+    }
+}
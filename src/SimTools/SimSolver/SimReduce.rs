@@ -0,0 +1,237 @@
+/* モデルの低次元化 */
+// 最小実現（非可制御・非可観測な状態の除去）と平衡化による低次元化（打ち切り・残留化）
+
+extern crate nalgebra as na;
+use na::DMatrix;
+
+use super::simmodel::{*};
+
+/* 平衡化による低次元化の方法 */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReductionMethod {
+    Truncation,     // 平衡打ち切り（高周波の特性を保つ）
+    Residualization, // 平衡残留化（直流ゲインを保つ）
+}
+
+/* 平衡化による低次元化の結果 */
+#[derive(Debug, Clone)]
+pub struct BalredResult {
+    pub model: SpaceStateModel,     // 低次元化したモデル
+    pub hsv: Vec<f64>,              // 元のモデルのハンケル特異値（降順）
+    pub error_bound: f64,           // 周波数応答の誤差の上界 2 Σ(除いた状態のハンケル特異値)
+}
+
+/* 列空間の正規直交基底（特異値がtolより大きい左特異ベクトル） */
+fn orth(m: &DMatrix<f64>, tol: f64) -> DMatrix<f64> {
+    if m.nrows() == 0 || m.ncols() == 0 {
+        return DMatrix::<f64>::zeros(m.nrows(), 0);
+    }
+    let svd = m.clone().svd(true, false);
+    let rank = svd.singular_values.iter().filter(|s| **s > tol).count();
+    svd.u.unwrap().columns(0, rank).into_owned()
+}
+
+fn append_columns(m1: &DMatrix<f64>, m2: &DMatrix<f64>) -> DMatrix<f64> {
+    let mut m = DMatrix::<f64>::zeros(m1.nrows(), m1.ncols() + m2.ncols());
+    m.columns_mut(0, m1.ncols()).copy_from(m1);
+    m.columns_mut(m1.ncols(), m2.ncols()).copy_from(m2);
+    m
+}
+
+/* 可制御部分空間 span[B, AB, A^2B, ...] の正規直交基底
+   可制御性行列をそのまま作ると高次で桁落ちするため、ブロックごとに直交化しながら広げる */
+fn ctrb_basis(a: &DMatrix<f64>, b: &DMatrix<f64>, tol: f64) -> DMatrix<f64> {
+    let n = a.nrows();
+    let mut basis = orth(b, tol);
+    let mut block = basis.clone();
+
+    while basis.ncols() < n && block.ncols() > 0 {
+        let mut w = a * &block;
+        for _ in 0..2 { // 丸め誤差を抑えるため2回直交化する
+            w = &w - &basis * (basis.transpose() * &w);
+        }
+        block = orth(&w, tol);
+        basis = append_columns(&basis, &block);
+    }
+    basis
+}
+
+/* ハンケル特異値のうち0とみなさないものの数（0の状態は入出力に現れない） */
+fn count_nonzero_hsv(hsv: &[f64]) -> usize {
+    let smax = hsv.first().cloned().unwrap_or(0.0);
+    hsv.iter().filter(|s| **s > 1e-12 * smax.max(f64::MIN_POSITIVE)).count()
+}
+
+/* 対称半正定値行列 W = L L^T の因子 L （負の固有値は丸め誤差とみなして0にする） */
+fn psd_factor(w: &DMatrix<f64>) -> DMatrix<f64> {
+    let eig = w.clone().symmetric_eigen();
+    let sqrt_diag = DMatrix::from_diagonal(&eig.eigenvalues.map(|v| v.max(0.0).sqrt()));
+    eig.eigenvectors * sqrt_diag
+}
+
+impl SpaceStateModel {
+    /* 左右の射影 z = TL x, x ≈ TR z で次元を下げたモデル（現在の状態も射影する） */
+    fn project(&self, tl: &DMatrix<f64>, tr: &DMatrix<f64>) -> Self {
+        let mut model = SpaceStateModel::from_matrices(
+            tl * self.get_mat_a() * tr,
+            tl * self.get_mat_b(),
+            self.get_mat_c() * tr,
+            self.get_mat_d().clone()).unwrap();
+        model.set_x((tl * self.get_state()).as_slice()).unwrap();
+        model.set_u(self.get_input().as_slice()).unwrap();
+        model
+    }
+
+    /* 最小実現（非可制御な状態と非可観測な状態を取り除いたモデル）
+       tolは部分空間のランクを判定する閾値で、行列のノルムに対する相対値 */
+    pub fn minreal(&self, tol: f64) -> Self {
+        let (a, b, c) = (self.get_mat_a(), self.get_mat_b(), self.get_mat_c());
+        let scale = a.norm().max(b.norm()).max(c.norm()).max(1.0);
+
+        // 可制御部分空間に制限する
+        let vc = ctrb_basis(a, b, tol * scale);
+        let ctrb = self.project(&vc.transpose(), &vc);
+
+        // 双対系の可制御部分空間 = 可観測部分空間に制限する
+        let vo = ctrb_basis(&ctrb.get_mat_a().transpose(), &ctrb.get_mat_c().transpose(), tol * scale);
+        ctrb.project(&vo.transpose(), &vo)
+    }
+
+    /* ハンケル特異値（降順、安定なモデルのみ） */
+    pub fn hsvd(&self) -> Result<Vec<f64>, &'static str> {
+        let lc = psd_factor(&self.ctrb_gramian()?);
+        let lo = psd_factor(&self.obsv_gramian()?);
+        Ok((lo.transpose() * lc).singular_values().iter().cloned().collect())
+    }
+
+    /* 指定した次数orderまで平衡化で低次元化する（安定なモデルのみ） */
+    pub fn balred(&self, order: usize, method: ReductionMethod) -> Result<BalredResult, &'static str> {
+        // 平衡化の変換（平方根法） x = TR z, z = TL x
+        let lc = psd_factor(&self.ctrb_gramian()?);
+        let lo = psd_factor(&self.obsv_gramian()?);
+        let svd = (lo.transpose() * &lc).svd(true, true);
+        let hsv: Vec<f64> = svd.singular_values.iter().cloned().collect();
+
+        // ハンケル特異値が0の状態は平衡化の対象から外す
+        let nonzero = count_nonzero_hsv(&hsv);
+        if order > nonzero {
+            return Err("指定した次数が最小実現の次数を超えています。");
+        }
+
+        let sigma_inv_sqrt = DMatrix::from_diagonal(&svd.singular_values.rows(0, nonzero).map(|s| 1.0 / s.sqrt()));
+        let tr = &lc * svd.v_t.unwrap().rows(0, nonzero).transpose() * &sigma_inv_sqrt;
+        let tl = &sigma_inv_sqrt * svd.u.unwrap().columns(0, nonzero).transpose() * lo.transpose();
+        let balanced = self.project(&tl, &tr);
+
+        let model = match method {
+            ReductionMethod::Truncation => {
+                let keep = DMatrix::<f64>::identity(nonzero, order);
+                balanced.project(&keep.transpose(), &keep)
+            },
+            ReductionMethod::Residualization => {
+                let r = order;
+                let d = nonzero - order;
+                let (a, b, c) = (balanced.get_mat_a(), balanced.get_mat_b(), balanced.get_mat_c());
+                let a22_lu = a.slice((r, r), (d, d)).into_owned().lu();
+                let a22_a21 = a22_lu.solve(&a.slice((r, 0), (d, r)).into_owned())
+                    .ok_or("除去する部分のA行列が正則でないため残留化できません。")?;
+                let a22_b2 = a22_lu.solve(&b.rows(r, d).into_owned())
+                    .ok_or("除去する部分のA行列が正則でないため残留化できません。")?;
+                let a12 = a.slice((0, r), (r, d));
+                let c2 = c.columns(r, d);
+
+                let mut model = SpaceStateModel::from_matrices(
+                    a.slice((0, 0), (r, r)) - a12 * &a22_a21,
+                    b.rows(0, r) - a12 * &a22_b2,
+                    c.columns(0, r) - c2 * &a22_a21,
+                    balanced.get_mat_d() - c2 * &a22_b2)?;
+                model.set_x(balanced.get_state().rows(0, r).into_owned().as_slice()).unwrap();
+                model.set_u(self.get_input().as_slice()).unwrap();
+                model
+            },
+        };
+
+        let error_bound = 2.0 * hsv[order..].iter().sum::<f64>();
+        Ok(BalredResult { model: model, hsv: hsv, error_bound: error_bound })
+    }
+
+    /* 周波数応答の誤差の上界がmax_error以下となる最小の次数まで平衡化で低次元化する */
+    pub fn balred_bound(&self, max_error: f64, method: ReductionMethod) -> Result<BalredResult, &'static str> {
+        if max_error < 0.0 {
+            return Err("誤差の上界は0以上にしてください。");
+        }
+        let hsv = self.hsvd()?;
+        let order = (0..=hsv.len())
+            .find(|r| 2.0 * hsv[*r..].iter().sum::<f64>() <= max_error)
+            .unwrap()
+            .min(count_nonzero_hsv(&hsv));
+        self.balred(order, method)
+    }
+}
+
+impl TransFuncModel {
+    /* 極と零点の相殺を取り除いた伝達関数 */
+    pub fn minreal(&self, tol: f64) -> Result<TransFuncModel, &'static str> {
+        let ss = self.get_model().minreal(tol);
+        let n = ss.get_mat_a().nrows();
+        if n == 0 {
+            return Err("次数が0になりました。");
+        }
+
+        ss.ss2tf().get_tf(0, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use na::ComplexField;
+
+    use super::*;
+    use super::super::simfreq::{logspace};
+
+    /* G(s) = (s^2 + 5s + 40) / ((s + 1)(s + 2)(s + 10)(s + 20)) */
+    fn fourth_order() -> SpaceStateModel {
+        SpaceStateModel::from_tf(&[1.0, 5.0, 40.0], &[1.0, 33.0, 252.0, 620.0, 400.0]).unwrap()
+    }
+
+    /* 周波数応答の差の最大値（1入力1出力） */
+    fn max_freq_error(g1: &SpaceStateModel, g2: &SpaceStateModel) -> f64 {
+        logspace(1e-3, 1e3, 500).unwrap().iter()
+            .map(|w| (g1.eval_freq(*w).unwrap()[(0, 0)] - g2.eval_freq(*w).unwrap()[(0, 0)]).modulus())
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn balred_error_stays_within_bound() {
+        let model = fourth_order();
+        for method in [ReductionMethod::Truncation, ReductionMethod::Residualization] {
+            let result = model.balred(2, method).unwrap();
+            assert_eq!(result.model.get_mat_a().nrows(), 2);
+            assert!((result.error_bound - 2.0 * (result.hsv[2] + result.hsv[3])).abs() < 1e-15);
+            assert!(max_freq_error(&model, &result.model) <= result.error_bound * (1.0 + 1e-9));
+        }
+
+        // 残留化は直流ゲインを保つ
+        let result = model.balred(2, ReductionMethod::Residualization).unwrap();
+        assert!((result.model.dcgain().unwrap() - model.dcgain().unwrap()).amax() < 1e-9);
+    }
+
+    #[test]
+    fn balred_bound_picks_smallest_order() {
+        let model = fourth_order();
+        let hsv = model.hsvd().unwrap();
+        let max_error = 2.0 * (hsv[2] + hsv[3]) * 1.01;
+        let result = model.balred_bound(max_error, ReductionMethod::Truncation).unwrap();
+        assert_eq!(result.model.get_mat_a().nrows(), 2);
+        assert!(result.error_bound <= max_error);
+    }
+
+    #[test]
+    fn minreal_removes_cancelled_pole() {
+        // (s + 1) / ((s + 1)(s + 2)) = 1 / (s + 2)
+        let tf = TransFuncModel::new(&[1.0, 1.0], &[1.0, 3.0, 2.0]);
+        let reduced = tf.minreal(1e-9).unwrap();
+        assert_eq!(reduced.get_model().get_mat_a().nrows(), 1);
+        assert!((reduced.get_model().get_mat_a()[0] + 2.0).abs() < 1e-9);
+    }
+}
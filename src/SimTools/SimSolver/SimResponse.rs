@@ -0,0 +1,227 @@
+/* ステップ応答・インパルス応答 */
+// 応答を計算するヘルパーと、立ち上がり時間・オーバーシュートなどの時間領域の性能指標
+// 入力は set_input で与えるので、get_input_dim・set_input・slopefunc_u を実装したモデルが対象
+// （calc_nextstate の中で入力を決めているモデルは入力を持たないモデルとして扱われ、Errになる）
+
+extern crate nalgebra as na;
+use na::DMatrix;
+
+use super::{Simulator};
+use super::simmodel::{*};
+
+pub const RESPONSE_POINTS: usize = 1000;    // 刻み幅を指定しない場合の分割数
+pub const SETTLING_BAND: f64 = 0.02;        // 整定時間の既定の許容幅（最終値の変化量に対する割合）
+
+fn check_response_args<T: Model>(model: &T, input: usize, t_end: f64, delta_t: f64) -> Result<(), &'static str> {
+    if model.get_input_dim() == 0 {
+        return Err("入力を持たないモデルです。get_input_dim・set_input・slopefunc_u を実装してください。");
+    }
+    if input >= model.get_input_dim() {
+        return Err("入力の番号が範囲外です。");
+    }
+    if t_end <= 0.0 || delta_t <= 0.0 || delta_t > t_end {
+        return Err("時間は 0 < delta_t <= t_end としてください。");
+    }
+    Ok(())
+}
+
+/* 入力0に単位ステップを加えたときの応答（刻み幅 t_end / RESPONSE_POINTS、4次のルンゲクッタ法） */
+pub fn step<T: Model>(model: T, t_end: f64) -> Result<Simulator<T>, &'static str> {
+    step_with(model, 0, 1.0, t_end, t_end / RESPONSE_POINTS as f64, SolverType::RungeKutta)
+}
+
+/* 入力inputに大きさamplitudeのステップを加えたときの応答（他の入力は0、状態はモデルの現在の値から始める） */
+pub fn step_with<T: Model>(mut model: T, input: usize, amplitude: f64, t_end: f64, delta_t: f64, solvertype: SolverType)
+    -> Result<Simulator<T>, &'static str> {
+    check_response_args(&model, input, t_end, delta_t)?;
+
+    let mut u = vec![0.0; model.get_input_dim()];
+    u[input] = amplitude;
+    model.set_input(&u);
+
+    let mut sim = Simulator::new(t_end, delta_t, solvertype, model);
+    sim.run_sim();
+    Ok(sim)
+}
+
+/* 入力0に単位インパルスを加えたときの応答 */
+pub fn impulse<T: Model>(model: T, t_end: f64) -> Result<Simulator<T>, &'static str> {
+    impulse_with(model, 0, 1.0, t_end, t_end / RESPONSE_POINTS as f64, SolverType::RungeKutta)
+}
+
+/* 入力inputに面積amplitudeのインパルスを加えたときの応答
+   インパルスは時刻0での状態の跳び x(0+) = x(0) + f(0, x, u) - f(0, x, 0) として与える
+   （入力について線形なモデルでは厳密、直達項によるy(0)のインパルスは無視する） */
+pub fn impulse_with<T: Model>(mut model: T, input: usize, amplitude: f64, t_end: f64, delta_t: f64, solvertype: SolverType)
+    -> Result<Simulator<T>, &'static str> {
+    check_response_args(&model, input, t_end, delta_t)?;
+
+    let x = model.get_state().clone();
+    let zero = DMatrix::<f64>::zeros(model.get_input_dim(), 1);
+    let mut u = zero.clone();
+    u[input] = amplitude;
+    let jump = model.slopefunc_u(0.0, &x, &u) - model.slopefunc_u(0.0, &x, &zero);
+    model.set_state(x + jump);
+    model.set_input(zero.as_slice());
+
+    let mut sim = Simulator::new(t_end, delta_t, solvertype, model);
+    sim.run_sim();
+    Ok(sim)
+}
+
+/* ステップ応答の性能指標（達しなかった指標はNaN） */
+#[derive(Debug, Clone)]
+pub struct StepInfo {
+    pub rise_time: f64,             // 立ち上がり時間（変化量の10%から90%に達するまで）
+    pub peak_time: f64,             // 行き過ぎ時間（最大値をとる時刻）
+    pub peak: f64,                  // 最大値
+    pub overshoot: f64,             // オーバーシュート [%]（最終値の変化量に対する割合）
+    pub undershoot: f64,            // アンダーシュート [%]（初期値から逆向きに振れた量の割合）
+    pub settling_time: f64,         // 整定時間（最終値から許容幅に収まったままになる時刻）
+    pub final_value: f64,           // 最終値（記録の最後の値）
+    pub steady_state_error: f64,    // 定常偏差（目標値 - 最終値）
+}
+
+/* 時系列から性能指標を求める
+   yrefは目標値、bandは整定とみなす許容幅（最終値の変化量に対する割合、例えば0.02で±2%） */
+pub fn stepinfo(time: &[f64], y: &[f64], yref: f64, band: f64) -> Result<StepInfo, &'static str> {
+    if time.len() != y.len() || time.len() < 2 {
+        return Err("時刻と信号の点数が違うか、点数が足りません。");
+    }
+    if band <= 0.0 {
+        return Err("許容幅は正の値にしてください。");
+    }
+
+    let y0 = y[0];
+    let yfinal = y[y.len() - 1];
+    let dy = yfinal - y0;
+    if dy == 0.0 {
+        return Err("応答が変化していないため指標を計算できません。");
+    }
+    let e: Vec<f64> = y.iter().map(|v| (v - y0) / dy).collect(); // 0から1に向かうように正規化
+
+    // 正規化した応答が初めてlevelに達する時刻（線形補間）
+    let crossing = |level: f64| -> f64 {
+        if e[0] >= level {
+            return time[0];
+        }
+        for i in 1..e.len() {
+            if e[i] >= level {
+                let r = (level - e[i - 1]) / (e[i] - e[i - 1]);
+                return time[i - 1] + r * (time[i] - time[i - 1]);
+            }
+        }
+        f64::NAN
+    };
+
+    let (imax, emax) = e.iter().enumerate()
+        .fold((0, f64::NEG_INFINITY), |(im, m), (i, v)| if *v > m { (i, *v) } else { (im, m) });
+    let emin = e.iter().cloned().fold(f64::INFINITY, f64::min);
+
+    let settling_time = match e.iter().rposition(|v| (v - 1.0).abs() > band) {
+        None => time[0],
+        Some(i) if i + 1 == e.len() => f64::NAN, // 最後まで許容幅に収まらない
+        Some(i) => { // 許容幅の境界を横切る時刻（線形補間）
+            let edge = if e[i] > 1.0 { 1.0 + band } else { 1.0 - band };
+            let r = (edge - e[i]) / (e[i + 1] - e[i]);
+            time[i] + r * (time[i + 1] - time[i])
+        },
+    };
+
+    Ok(StepInfo {
+        rise_time: crossing(0.9) - crossing(0.1),
+        peak_time: time[imax],
+        peak: y[imax],
+        overshoot: 100.0 * (emax - 1.0).max(0.0),
+        undershoot: if emin < 0.0 { -100.0 * emin } else { 0.0 },
+        settling_time: settling_time,
+        final_value: yfinal,
+        steady_state_error: yref - yfinal,
+    })
+}
+
+impl<T> Simulator<T>
+where T: Model
+{
+    /* 記録した信号signalの性能指標 */
+    pub fn stepinfo(&self, signal: &str, yref: f64, band: f64) -> Result<StepInfo, &'static str> {
+        let time = self.get_simdata("time").unwrap();
+        let y = self.get_simdata(signal).ok_or("指定した信号は記録されていません。")?;
+        stepinfo(time, y, yref, band)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+
+    /* 2次系 wn^2 / (s^2 + 2ζwn s + wn^2) */
+    fn second_order(wn: f64, zeta: f64) -> SpaceStateModel {
+        SpaceStateModel::from_tf(&[wn * wn], &[1.0, 2.0 * zeta * wn, wn * wn]).unwrap()
+    }
+
+    /* 入力を持たないモデル dx/dt = -x */
+    struct Autonomous {
+        x: DMatrix<f64>,
+    }
+
+    impl Model for Autonomous {
+        fn slopefunc(&self, _t: f64, x: &DMatrix<f64>) -> DMatrix<f64> {
+            -x
+        }
+
+        fn get_signals_info(&self) -> Vec<String> {
+            vec!["x".to_string()]
+        }
+
+        fn set_state(&mut self, newstate: DMatrix<f64>) {
+            self.x = newstate;
+        }
+
+        fn get_state(&self) -> &DMatrix<f64> {
+            &self.x
+        }
+
+        fn get_allsignals(&self) -> Vec<f64> {
+            vec![self.x[0]]
+        }
+    }
+
+    #[test]
+    fn step_and_impulse_of_first_order_lag() {
+        // 1 / (s + 1) のステップ応答 1 - e^(-t)、インパルス応答 e^(-t)
+        let lag = SpaceStateModel::from_tf(&[1.0], &[1.0, 1.0]).unwrap();
+        let sim = step(lag.clone(), 2.0).unwrap();
+        let (time, y) = (sim.get_simdata("time").unwrap(), sim.get_simdata("y_0").unwrap());
+        assert!(time.iter().zip(y.iter()).all(|(t, y)| (y - (1.0 - (-t).exp())).abs() < 1e-9));
+
+        let sim = impulse(lag, 2.0).unwrap();
+        let (time, y) = (sim.get_simdata("time").unwrap(), sim.get_simdata("y_0").unwrap());
+        assert!(time.iter().zip(y.iter()).all(|(t, y)| (y - (-t).exp()).abs() < 1e-9));
+    }
+
+    #[test]
+    fn stepinfo_matches_second_order_formulas() {
+        let (wn, zeta) = (2.0, 0.3);
+        let sim = step(second_order(wn, zeta), 20.0).unwrap();
+        let info = sim.stepinfo("y_0", 1.0, SETTLING_BAND).unwrap();
+
+        let wd = wn * (1.0 - zeta * zeta).sqrt();
+        let overshoot = 100.0 * (-zeta * PI / (1.0 - zeta * zeta).sqrt()).exp();
+        assert!((info.peak_time - PI / wd).abs() < 0.02);
+        assert!((info.overshoot - overshoot).abs() < 0.05);
+        assert!(info.steady_state_error.abs() < 1e-3);
+    }
+
+    #[test]
+    fn response_requires_model_input() {
+        let model = Autonomous { x: DMatrix::from_element(1, 1, 1.0) };
+        assert!(step(model, 1.0).is_err());
+
+        let lag = SpaceStateModel::from_tf(&[1.0], &[1.0, 1.0]).unwrap();
+        assert!(step_with(lag.clone(), 1, 1.0, 1.0, 0.01, SolverType::RungeKutta).is_err());
+        assert!(step_with(lag, 0, 1.0, 1.0, 2.0, SolverType::RungeKutta).is_err());
+    }
+}
@@ -0,0 +1,517 @@
+/* 入力信号源 */
+// 時刻tだけで値が決まる信号源を定義し、SourcedModelでモデルの入力ポートに接続する
+
+use std::fmt;
+use std::f64::consts::PI;
+
+extern crate nalgebra as na;
+use na::DMatrix;
+
+use super::simmodel::{*};
+
+pub trait Source: fmt::Debug {
+    fn get_value(&self, t: f64) -> f64;     // 時刻tにおける信号の値
+}
+
+/* ステップ信号 */
+#[derive(Debug, Clone)]
+pub struct Step {
+    step_time: f64,     // ステップが立ち上がる時刻（遅れ時間）
+    init_value: f64,    // 立ち上がる前の値
+    final_value: f64,   // 立ち上がった後の値
+}
+
+impl Step {
+    pub fn new(step_time: f64, init_value: f64, final_value: f64) -> Self {
+        Self {
+            step_time: step_time,
+            init_value: init_value,
+            final_value: final_value,
+        }
+    }
+}
+
+impl Source for Step {
+    fn get_value(&self, t: f64) -> f64 {
+        if t < self.step_time { self.init_value } else { self.final_value }
+    }
+}
+
+/* ランプ信号 */
+#[derive(Debug, Clone)]
+pub struct Ramp {
+    start_time: f64,    // 傾きを持ち始める時刻
+    slope: f64,         // 傾き
+    init_value: f64,    // 開始前の値
+}
+
+impl Ramp {
+    pub fn new(start_time: f64, slope: f64, init_value: f64) -> Self {
+        Self {
+            start_time: start_time,
+            slope: slope,
+            init_value: init_value,
+        }
+    }
+}
+
+impl Source for Ramp {
+    fn get_value(&self, t: f64) -> f64 {
+        if t < self.start_time {
+            self.init_value
+        } else {
+            self.init_value + self.slope * (t - self.start_time)
+        }
+    }
+}
+
+/* 正弦波 amplitude * sin(2πft + phase) + offset */
+#[derive(Debug, Clone)]
+pub struct Sine {
+    amplitude: f64, // 振幅
+    freq: f64,      // 周波数[Hz]
+    phase: f64,     // 初期位相[rad]
+    offset: f64,    // オフセット
+}
+
+impl Sine {
+    pub fn new(amplitude: f64, freq: f64, phase: f64, offset: f64) -> Self {
+        Self {
+            amplitude: amplitude,
+            freq: freq,
+            phase: phase,
+            offset: offset,
+        }
+    }
+}
+
+impl Source for Sine {
+    fn get_value(&self, t: f64) -> f64 {
+        self.amplitude * (2.0 * PI * self.freq * t + self.phase).sin() + self.offset
+    }
+}
+
+/* 矩形波（+amplitude と -amplitude を交互に出力する） */
+#[derive(Debug, Clone)]
+pub struct Square {
+    amplitude: f64, // 振幅
+    freq: f64,      // 周波数[Hz]
+    offset: f64,    // オフセット
+}
+
+impl Square {
+    pub fn new(amplitude: f64, freq: f64, offset: f64) -> Self {
+        Self {
+            amplitude: amplitude,
+            freq: freq,
+            offset: offset,
+        }
+    }
+}
+
+impl Source for Square {
+    fn get_value(&self, t: f64) -> f64 {
+        let phase = (t * self.freq).rem_euclid(1.0);
+        if phase < 0.5 { self.offset + self.amplitude } else { self.offset - self.amplitude }
+    }
+}
+
+/* パルス列（周期ごとにデューティ比の間だけamplitudeを出力し、それ以外は0） */
+#[derive(Debug, Clone)]
+pub struct Pulse {
+    amplitude: f64, // パルスの高さ
+    period: f64,    // 周期[s]
+    duty: f64,      // デューティ比（0～1）
+    delay: f64,     // 最初のパルスが立ち上がる時刻
+}
+
+impl Pulse {
+    pub fn new(amplitude: f64, period: f64, duty: f64, delay: f64) -> Result<Self, &'static str> {
+        if period <= 0.0 {
+            return Err("周期は正の値にしてください。");
+        }
+        if duty < 0.0 || duty > 1.0 {
+            return Err("デューティ比は0～1の範囲にしてください。");
+        }
+
+        Ok(Self {
+            amplitude: amplitude,
+            period: period,
+            duty: duty,
+            delay: delay,
+        })
+    }
+}
+
+impl Source for Pulse {
+    fn get_value(&self, t: f64) -> f64 {
+        if t < self.delay {
+            return 0.0;
+        }
+        let phase = ((t - self.delay) / self.period).rem_euclid(1.0);
+        if phase < self.duty { self.amplitude } else { 0.0 }
+    }
+}
+
+/* チャープ信号（周波数をf0からf1まで時間t1で線形に掃引する、t1以降はf1の正弦波） */
+#[derive(Debug, Clone)]
+pub struct Chirp {
+    amplitude: f64, // 振幅
+    f0: f64,        // 開始周波数[Hz]
+    f1: f64,        // 終了周波数[Hz]
+    t1: f64,        // 掃引時間[s]
+}
+
+impl Chirp {
+    pub fn new(amplitude: f64, f0: f64, f1: f64, t1: f64) -> Result<Self, &'static str> {
+        if t1 <= 0.0 {
+            return Err("掃引時間は正の値にしてください。");
+        }
+
+        Ok(Self {
+            amplitude: amplitude,
+            f0: f0,
+            f1: f1,
+            t1: t1,
+        })
+    }
+}
+
+impl Source for Chirp {
+    fn get_value(&self, t: f64) -> f64 {
+        let k = (self.f1 - self.f0) / self.t1; // 周波数の変化率
+        let phase = if t < self.t1 {
+            self.f0 * t + k * t * t / 2.0
+        } else {
+            // 掃引終了後は位相が連続になるようにf1で続ける
+            self.f0 * self.t1 + k * self.t1 * self.t1 / 2.0 + self.f1 * (t - self.t1)
+        };
+        self.amplitude * (2.0 * PI * phase).sin()
+    }
+}
+
+/* M系列による擬似ランダム2値信号 (PRBS)
+   order次の線形帰還シフトレジスタで周期 2^order - 1 ビットの系列を作り、bit_periodごとに ±amplitude を出力する */
+#[derive(Debug, Clone)]
+pub struct Prbs {
+    amplitude: f64,     // 振幅
+    bit_period: f64,    // 1ビットの長さ[s]
+    sequence: Vec<bool>,// 1周期分のビット列
+}
+
+impl Prbs {
+    pub fn new(amplitude: f64, bit_period: f64, order: usize) -> Result<Self, &'static str> {
+        // 最大周期となる帰還タップ（Xilinx XAPP052）
+        let taps: &[usize] = match order {
+            2 => &[2, 1],
+            3 => &[3, 2],
+            4 => &[4, 3],
+            5 => &[5, 3],
+            6 => &[6, 5],
+            7 => &[7, 6],
+            8 => &[8, 6, 5, 4],
+            9 => &[9, 5],
+            10 => &[10, 7],
+            11 => &[11, 9],
+            12 => &[12, 6, 4, 1],
+            13 => &[13, 4, 3, 1],
+            14 => &[14, 5, 3, 1],
+            15 => &[15, 14],
+            16 => &[16, 15, 13, 4],
+            _ => return Err("PRBSの次数は2～16の範囲にしてください。"),
+        };
+        if bit_period <= 0.0 {
+            return Err("ビット長は正の値にしてください。");
+        }
+
+        let len = (1usize << order) - 1;
+        let mask = len;
+        let mut reg: usize = 1;
+        let mut sequence = Vec::with_capacity(len);
+        for _ in 0..len {
+            sequence.push(reg & 1 == 1);
+            let fb = taps.iter().fold(0, |acc, k| acc ^ ((reg >> (k - 1)) & 1));
+            reg = ((reg << 1) | fb) & mask;
+        }
+
+        Ok(Self {
+            amplitude: amplitude,
+            bit_period: bit_period,
+            sequence: sequence,
+        })
+    }
+}
+
+impl Source for Prbs {
+    fn get_value(&self, t: f64) -> f64 {
+        let idx = (t.max(0.0) / self.bit_period) as usize % self.sequence.len();
+        if self.sequence[idx] { self.amplitude } else { -self.amplitude }
+    }
+}
+
+/* 折れ線テーブル（点の間は線形補間、範囲外は端の値を保持する） */
+#[derive(Debug, Clone)]
+pub struct PiecewiseLinear {
+    times: Vec<f64>,    // 時刻（単調増加）
+    values: Vec<f64>,   // 各時刻の値
+}
+
+impl PiecewiseLinear {
+    pub fn new(times: &[f64], values: &[f64]) -> Result<Self, &'static str> {
+        if times.len() < 1 || times.len() != values.len() {
+            return Err("時刻と値の個数が違います。");
+        }
+        if times.windows(2).any(|w| w[1] < w[0]) {
+            return Err("時刻が単調増加になっていません。");
+        }
+
+        Ok(Self {
+            times: times.to_vec(),
+            values: values.to_vec(),
+        })
+    }
+}
+
+impl Source for PiecewiseLinear {
+    fn get_value(&self, t: f64) -> f64 {
+        let n = self.times.len();
+        if t <= self.times[0] {
+            return self.values[0];
+        }
+        if t >= self.times[n - 1] {
+            return self.values[n - 1];
+        }
+
+        let i = self.times.iter().rposition(|tp| *tp <= t).unwrap(); // times[i] <= t < times[i + 1]
+        let (t0, t1) = (self.times[i], self.times[i + 1]);
+        let (v0, v1) = (self.values[i], self.values[i + 1]);
+        v0 + (v1 - v0) * (t - t0) / (t1 - t0)
+    }
+}
+
+/* 信号源を入力ポートに接続したモデル
+   ルンゲクッタ法などの各段では段の時刻で信号源を評価するので、時間変化する入力も正しく積分される
+   信号源の値は指定した名前の信号としてSimulatorに記録される */
+#[derive(Debug)]
+pub struct SourcedModel<T>
+where T: Model
+{
+    model: T,
+    sources: Vec<(String, usize, Box<dyn Source>)>, // (信号名, 入力ポート番号, 信号源)
+}
+
+impl<T> SourcedModel<T>
+where T: Model
+{
+    pub fn new(model: T) -> Self {
+        Self {
+            model: model,
+            sources: Vec::new(),
+        }
+    }
+
+    pub fn attach_source(&mut self, name: &str, port: usize, source: Box<dyn Source>) -> Result<(), &'static str> {
+        if port >= self.model.get_input_dim() {
+            return Err("入力ポートの番号が範囲外です。");
+        }
+        if self.model.get_input().nrows() != self.model.get_input_dim() { // set_inputに渡す入力ベクトルはget_inputから作るので、次数が合わないモデルは接続しない
+            return Err("モデルのget_inputの次数がget_input_dimと違います。");
+        }
+        if self.sources.iter().any(|(_, p, _)| *p == port) {
+            return Err("この入力ポートには既に信号源が接続されています。");
+        }
+
+        self.sources.push((name.to_string(), port, source));
+        self.update_input(0.0); // 時刻0の入力を反映しておく（Simulator::newで初期値が記録されるため）
+        Ok(())
+    }
+
+    pub fn get_model(&self) -> &T {
+        &self.model
+    }
+
+    fn calc_input(&self, t: f64) -> DMatrix<f64> { // 時刻tでの入力ベクトル（信号源が無いポートは現在の値のまま）
+        let mut u = self.model.get_input();
+        for (_, port, source) in self.sources.iter() {
+            u[*port] = source.get_value(t);
+        }
+        u
+    }
+
+    fn update_input(&mut self, t: f64) {
+        let u = self.calc_input(t);
+        self.model.set_input(u.as_slice());
+    }
+}
+
+impl<T> Model for SourcedModel<T>
+where T: Model
+{
+    fn slopefunc(&self, t: f64, x: &DMatrix<f64>) -> DMatrix<f64> {
+        self.model.slopefunc_u(t, x, &self.calc_input(t))
+    }
+
+    fn get_signals_info(&self) -> Vec<String> {
+        let mut series = self.model.get_signals_info();
+        series.append(&mut self.sources.iter().map(|(name, _, _)| name.to_string()).collect::<Vec<String>>());
+        series
+    }
+
+    fn set_state(&mut self, newstate: DMatrix<f64>) {
+        self.model.set_state(newstate);
+    }
+
+    fn get_state(&self) -> &DMatrix<f64> {
+        self.model.get_state()
+    }
+
+    fn get_allsignals(&self) -> Vec<f64> {
+        let u = self.model.get_input();
+        let mut signals = self.model.get_allsignals();
+        signals.append(&mut self.sources.iter().map(|(_, port, _)| u[*port]).collect::<Vec<f64>>());
+        signals
+    }
+
+    fn calc_nextstate(&mut self, t: f64, delta_t : f64, solvertype: &SolverType) {
+        self.update_input(t);
+
+        match solvertype {
+            SolverType::Exact => { // 厳密離散化は入力を区間内で一定とみなすので、内部のモデルにそのまま任せる
+                self.model.calc_nextstate(t, delta_t, solvertype);
+                self.update_input(t + delta_t); // 記録される入力・出力を新しい時刻に合わせる
+            },
+            _ => {
+                self.model.update_discrete(t, 0.0);
+                let newstate = solve_nextstate(self, t, delta_t, solvertype);
+                self.model.set_state(newstate);
+                self.update_input(t + delta_t);
+                self.model.update_discrete(t, delta_t); // 積分で変化しない状態は新しい時刻の入力で進める
+            },
+        }
+    }
+
+    fn get_input_dim(&self) -> usize {
+        self.model.get_input_dim()
+    }
+
+    fn get_input(&self) -> DMatrix<f64> {
+        self.model.get_input()
+    }
+
+    fn set_input(&mut self, u: &[f64]) {
+        self.model.set_input(u);
+    }
+
+    fn slopefunc_u(&self, t: f64, x: &DMatrix<f64>, u: &DMatrix<f64>) -> DMatrix<f64> {
+        self.model.slopefunc_u(t, x, u)
+    }
+
+    fn get_output_dim(&self) -> usize {
+        self.model.get_output_dim()
+    }
+
+    fn outputfunc(&self, t: f64, x: &DMatrix<f64>, u: &DMatrix<f64>) -> DMatrix<f64> {
+        self.model.outputfunc(t, x, u)
+    }
+
+    fn has_feedthrough(&self) -> bool {
+        self.model.has_feedthrough()
+    }
+
+    fn get_step_control(&self) -> StepControl {
+        self.model.get_step_control()
+    }
+
+    fn set_step_control(&mut self, control: StepControl) {
+        self.model.set_step_control(control);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* 入力を積分するだけのモデル dx/dt = u */
+    fn integrator() -> SpaceStateModel {
+        let mut model = SpaceStateModel::new(1, 1, 1);
+        model.set_mat_a(&[0.0]).unwrap();
+        model.set_mat_b(&[1.0]).unwrap();
+        model.set_mat_c(&[1.0]).unwrap();
+        model
+    }
+
+    /* 入力の次数とget_inputの次数が合わないモデル */
+    #[derive(Debug)]
+    struct BrokenInput {
+        x: DMatrix<f64>,
+    }
+
+    impl Model for BrokenInput {
+        fn slopefunc(&self, _t: f64, x: &DMatrix<f64>) -> DMatrix<f64> {
+            x * 0.0
+        }
+
+        fn get_signals_info(&self) -> Vec<String> {
+            vec!["x".to_string()]
+        }
+
+        fn set_state(&mut self, newstate: DMatrix<f64>) {
+            self.x = newstate;
+        }
+
+        fn get_state(&self) -> &DMatrix<f64> {
+            &self.x
+        }
+
+        fn get_allsignals(&self) -> Vec<f64> {
+            vec![self.x[0]]
+        }
+
+        fn get_input_dim(&self) -> usize {
+            2
+        }
+
+        fn get_input(&self) -> DMatrix<f64> {
+            DMatrix::zeros(1, 1)
+        }
+    }
+
+    #[test]
+    fn sources_give_expected_values() {
+        let step = Step::new(1.0, 0.0, 2.0);
+        assert_eq!((step.get_value(0.5), step.get_value(1.0)), (0.0, 2.0));
+
+        let ramp = Ramp::new(1.0, 2.0, 1.0);
+        assert_eq!((ramp.get_value(0.0), ramp.get_value(2.5)), (1.0, 4.0));
+
+        let pulse = Pulse::new(3.0, 1.0, 0.25, 0.5).unwrap();
+        assert_eq!((pulse.get_value(0.4), pulse.get_value(0.6), pulse.get_value(1.0)), (0.0, 3.0, 0.0));
+        assert!(Pulse::new(1.0, 0.0, 0.5, 0.0).is_err());
+
+        let pwl = PiecewiseLinear::new(&[0.0, 1.0, 3.0], &[0.0, 2.0, 0.0]).unwrap();
+        assert_eq!((pwl.get_value(-1.0), pwl.get_value(0.5), pwl.get_value(2.0), pwl.get_value(5.0)), (0.0, 1.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn sourced_model_integrates_time_varying_input() {
+        // ランプ入力 u = t の積分は t^2 / 2 （4次のルンゲクッタ法では段の時刻で評価するので厳密に一致する）
+        let mut model = SourcedModel::new(integrator());
+        model.attach_source("u", 0, Box::new(Ramp::new(0.0, 1.0, 0.0))).unwrap();
+        for i in 0..100 {
+            model.calc_nextstate(i as f64 * 0.01, 0.01, &SolverType::RungeKutta);
+        }
+        assert!((model.get_state()[0] - 0.5).abs() < 1e-12);
+        assert!((model.get_input()[0] - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn attach_source_checks_port_and_input_dimension() {
+        let mut model = SourcedModel::new(integrator());
+        assert!(model.attach_source("u", 1, Box::new(Step::new(0.0, 0.0, 1.0))).is_err());
+        model.attach_source("u", 0, Box::new(Step::new(0.0, 0.0, 1.0))).unwrap();
+        assert!(model.attach_source("v", 0, Box::new(Step::new(0.0, 0.0, 1.0))).is_err());
+
+        let mut broken = SourcedModel::new(BrokenInput { x: DMatrix::zeros(1, 1) });
+        assert!(broken.attach_source("u", 0, Box::new(Step::new(0.0, 0.0, 1.0))).is_err());
+    }
+}
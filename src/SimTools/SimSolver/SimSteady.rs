@@ -0,0 +1,315 @@
+/* 定常状態（平衡点）の計算 */
+// 長時間のシミュレーションで整定を待つ代わりに、slopefunc = 0 となる状態をレーベンバーグ・マーカート法で直接求める
+// 入力は固定し、状態の一部の固定や出力の目標値を拘束として加えられる（入力の一部を未知数にすることもできる）
+// 線形化の動作点を求めるときは trim を使う
+
+extern crate nalgebra as na;
+use na::DMatrix;
+
+use super::simmodel::{*};
+
+const LM_LAMBDA_INIT: f64 = 1e-3;   // 減衰係数の初期値
+const LM_LAMBDA_MAX: f64 = 1e16;    // これを超えたら残差がもう減らないとみなす
+
+/* 計算の終わり方 */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SteadyStateStatus {
+    Converged,      // 残差が許容値以下になった
+    MaxIterations,  // 反復回数の上限に達した
+    Stalled,        // 残差がそれ以上減らない（近くに平衡点が無いか、拘束が両立しない）
+}
+
+/* 定常状態の計算結果（収束しなかったときも最も残差の小さかった点を返す） */
+#[derive(Debug, Clone)]
+pub struct SteadyStateResult {
+    pub x: Vec<f64>,                // 状態（init_state にそのまま渡せる）
+    pub u: Vec<f64>,                // 入力
+    pub y: Vec<f64>,                // 出力
+    pub slope: Vec<f64>,            // 各状態の dx/dt（残差）
+    pub output_error: Vec<f64>,     // 出力の目標値との差（set_output_target で設定した順）
+    pub residual: f64,              // 残差の最大ノルム
+    pub iterations: usize,          // 反復回数
+    pub status: SteadyStateStatus,
+}
+
+impl SteadyStateResult {
+    pub fn is_converged(&self) -> bool {
+        self.status == SteadyStateStatus::Converged
+    }
+
+    /* 収束しなかった理由（収束したときはNone） */
+    pub fn get_failure_reason(&self) -> Option<&'static str> {
+        match self.status {
+            SteadyStateStatus::Converged => None,
+            SteadyStateStatus::MaxIterations => Some("反復回数の上限までに定常状態に収束しませんでした。"),
+            SteadyStateStatus::Stalled => Some("残差がそれ以上減りません。初期値の近くに定常状態が無いか、拘束が両立しません。"),
+        }
+    }
+
+    /* 求めた状態と入力をモデルに設定する（別のモデルで解いた結果など、次数が違うときは Err を返す） */
+    pub fn apply<M: Model + ?Sized>(&self, model: &mut M) -> Result<(), &'static str> {
+        if self.x.len() != model.get_state().nrows() {
+            return Err("状態ベクトルの次数が違います。");
+        }
+        if self.u.len() != model.get_input_dim() {
+            return Err("入力ベクトルの次数が違います。");
+        }
+        model.set_state(DMatrix::from_column_slice(self.x.len(), 1, &self.x));
+        model.set_input(&self.u);
+        Ok(())
+    }
+}
+
+/* 定常状態のソルバ
+   未知数は固定しない状態（と free_input で未知数にした入力）、方程式は全ての状態の dx/dt = 0 と出力の目標値
+   未知数と方程式の数が違っても最小二乗の意味で解くので、残差と status を確認して使う */
+#[derive(Debug, Clone)]
+pub struct SteadyStateSolver {
+    t: f64,                         // slopefunc を評価する時刻
+    fixed_x: Vec<(usize, f64)>,     // 固定する状態の (番号, 値)
+    free_u: Vec<usize>,             // 未知数にする入力の番号
+    target_y: Vec<(usize, f64)>,    // 出力の (番号, 目標値)
+    tol: f64,                       // 残差の許容値（最大ノルム）
+    max_iter: usize,
+}
+
+impl SteadyStateSolver {
+    pub fn new() -> Self {
+        Self {
+            t: 0.0,
+            fixed_x: Vec::new(),
+            free_u: Vec::new(),
+            target_y: Vec::new(),
+            tol: 1e-10,
+            max_iter: 200,
+        }
+    }
+
+    pub fn set_time(&mut self, t: f64) {
+        self.t = t;
+    }
+
+    pub fn set_tolerance(&mut self, tol: f64) -> Result<(), &'static str> {
+        if tol <= 0.0 {
+            return Err("許容値は正の値にしてください。");
+        }
+        self.tol = tol;
+        Ok(())
+    }
+
+    pub fn set_max_iter(&mut self, max_iter: usize) {
+        self.max_iter = max_iter;
+    }
+
+    /* 状態 x[index] を value に固定する */
+    pub fn fix_state(&mut self, index: usize, value: f64) {
+        self.fixed_x.retain(|(i, _)| *i != index);
+        self.fixed_x.push((index, value));
+    }
+
+    /* 入力 u[index] を固定せず未知数にする（出力の目標値を入力で合わせるとき） */
+    pub fn free_input(&mut self, index: usize) {
+        if !self.free_u.contains(&index) {
+            self.free_u.push(index);
+        }
+    }
+
+    /* 出力 y[index] の目標値を value にする */
+    pub fn set_output_target(&mut self, index: usize, value: f64) {
+        self.target_y.retain(|(i, _)| *i != index);
+        self.target_y.push((index, value));
+    }
+
+    /* 状態の初期値 x_guess と入力 u から定常状態を求める（固定した状態と未知数にした入力の初期値も x_guess, u から取る）
+       次数や番号の誤りと、初期値で slopefunc が有限にならないときは Err を返す */
+    pub fn solve<M: Model + ?Sized>(&self, model: &M, x_guess: &[f64], u: &[f64]) -> Result<SteadyStateResult, &'static str> {
+        let nx = model.get_state().nrows();
+        if x_guess.len() != nx {
+            return Err("状態ベクトルの次数が違います。");
+        }
+        if u.len() != model.get_input_dim() {
+            return Err("入力ベクトルの次数が違います。");
+        }
+        if self.fixed_x.iter().any(|(i, _)| *i >= nx) || self.free_u.iter().any(|i| *i >= u.len()) {
+            return Err("固定する状態または未知数にする入力の番号が次数を超えています。");
+        }
+        if self.target_y.iter().any(|(i, _)| *i >= model.get_output_dim()) {
+            return Err("目標値を設定した出力の番号が次数を超えています。");
+        }
+
+        // [x, u] を並べたベクトルのうち、未知数の番号
+        let mut xu = x_guess.iter().chain(u.iter()).copied().collect::<Vec<f64>>();
+        self.fixed_x.iter().for_each(|(i, v)| xu[*i] = *v);
+        let free = (0..nx).filter(|i| self.fixed_x.iter().all(|(j, _)| j != i))
+            .chain(self.free_u.iter().map(|i| i + nx))
+            .collect::<Vec<usize>>();
+
+        let split = |xu: &[f64]| (DMatrix::from_column_slice(nx, 1, &xu[..nx]), DMatrix::from_column_slice(xu.len() - nx, 1, &xu[nx..]));
+        let residual = |v: &DMatrix<f64>| {
+            let mut xu = xu.clone();
+            free.iter().zip(v.iter()).for_each(|(i, vi)| xu[*i] = *vi);
+            let (x, u) = split(&xu);
+            let slope = model.slopefunc_u(self.t, &x, &u);
+            let y = model.outputfunc(self.t, &x, &u);
+            DMatrix::from_iterator(nx + self.target_y.len(), 1,
+                slope.iter().copied().chain(self.target_y.iter().map(|(i, target)| y[*i] - target)))
+        };
+
+        let mut v = DMatrix::from_iterator(free.len(), 1, free.iter().map(|i| xu[*i]));
+        let mut res = residual(&v);
+        if !res.iter().all(|r| r.is_finite()) {
+            return Err("初期値で slopefunc または outputfunc が有限の値になりません。");
+        }
+
+        let mut lambda = LM_LAMBDA_INIT;
+        let mut iterations = 0;
+        let status = loop {
+            if res.amax() <= self.tol {
+                break SteadyStateStatus::Converged;
+            }
+            if free.is_empty() || lambda > LM_LAMBDA_MAX {
+                break SteadyStateStatus::Stalled;
+            }
+            if iterations >= self.max_iter {
+                break SteadyStateStatus::MaxIterations;
+            }
+            iterations += 1;
+
+            // (J^T J + λ diag(J^T J)) δ = -J^T r を解き、残差が減れば受け入れてλを小さくし、減らなければλを大きくして解き直す
+            let jac = central_jacobian(residual, &v);
+            let jtj = jac.transpose() * &jac;
+            let grad = jac.transpose() * &res;
+            loop {
+                let mut lhs = jtj.clone();
+                for k in 0..lhs.nrows() {
+                    lhs[(k, k)] += lambda * jtj[(k, k)].max(1e-12);
+                }
+                let trial = lhs.cholesky().map(|c| &v - c.solve(&grad)).map(|vt| (residual(&vt), vt));
+                match trial {
+                    Some((rt, vt)) if rt.iter().all(|r| r.is_finite()) && rt.norm() < res.norm() => {
+                        v = vt;
+                        res = rt;
+                        lambda = (lambda / 3.0).max(1e-12);
+                        break;
+                    },
+                    _ => {
+                        lambda *= 4.0;
+                        if lambda > LM_LAMBDA_MAX {
+                            break;
+                        }
+                    },
+                }
+            }
+        };
+
+        free.iter().zip(v.iter()).for_each(|(i, vi)| xu[*i] = *vi);
+        let (x, u) = split(&xu);
+        Ok(SteadyStateResult {
+            x: x.iter().copied().collect(),
+            u: u.iter().copied().collect(),
+            y: model.outputfunc(self.t, &x, &u).iter().copied().collect(),
+            slope: res.rows(0, nx).iter().copied().collect(),
+            output_error: res.rows(nx, self.target_y.len()).iter().copied().collect(),
+            residual: res.amax(),
+            iterations: iterations,
+            status: status,
+        })
+    }
+}
+
+/* 時刻tで dx/dt = 0 となる平衡点 (x0, u0) を求める
+   fixed_x, fixed_u に番号を入れた要素は初期値 x_guess, u_guess のまま固定し、残りを未知数として SteadyStateSolver で解く
+   残差や収束しなかった理由を詳しく見たいときは SteadyStateSolver を直接使う */
+pub fn trim<M: Model + ?Sized>(model: &M, t: f64, x_guess: &[f64], u_guess: &[f64], fixed_x: &[usize], fixed_u: &[usize]) -> Result<(Vec<f64>, Vec<f64>), &'static str> {
+    if fixed_x.iter().any(|i| *i >= x_guess.len()) || fixed_u.iter().any(|i| *i >= u_guess.len()) {
+        return Err("固定する要素の番号が次数を超えています。");
+    }
+
+    let mut solver = SteadyStateSolver::new();
+    solver.set_time(t);
+    fixed_x.iter().for_each(|i| solver.fix_state(*i, x_guess[*i]));
+    (0..u_guess.len()).filter(|i| !fixed_u.contains(i)).for_each(|i| solver.free_input(i));
+
+    let result = solver.solve(model, x_guess, u_guess)?;
+    match result.get_failure_reason() {
+        Some(reason) => Err(reason),
+        None => Ok((result.x, result.u)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+    use super::super::simfnmodel::{*};
+
+    /* 減衰のある振り子 dθ/dt = ω, dω/dt = -g sinθ - 0.5ω + u、出力 y = θ */
+    fn pendulum() -> FnModel {
+        let mut model = FnModel::new(&[0.0, 0.0], 1, |_t, x, u| DMatrix::from_column_slice(2, 1, &[x[1], -9.8 * x[0].sin() - 0.5 * x[1] + u[0]])).unwrap();
+        model.set_output(1, |_t, x, _u| x.rows(0, 1).into_owned()).unwrap();
+        model
+    }
+
+    #[test]
+    fn solver_finds_known_equilibrium() {
+        // u = g/2 のとき sinθ = 1/2
+        let result = SteadyStateSolver::new().solve(&pendulum(), &[0.3, 0.1], &[4.9]).unwrap();
+        assert!(result.is_converged());
+        assert!((result.x[0] - PI / 6.0).abs() < 1e-9);
+        assert!(result.x[1].abs() < 1e-9);
+        assert_eq!(result.u, vec![4.9]);
+        assert!((result.y[0] - result.x[0]).abs() < 1e-12);
+    }
+
+    #[test]
+    fn solver_matches_output_target_with_free_input() {
+        // θ = π/4 に保つ入力は g sin(π/4)
+        let mut solver = SteadyStateSolver::new();
+        solver.free_input(0);
+        solver.set_output_target(0, PI / 4.0);
+        let result = solver.solve(&pendulum(), &[0.0, 0.0], &[0.0]).unwrap();
+        assert!(result.is_converged());
+        assert!((result.u[0] - 9.8 * (PI / 4.0).sin()).abs() < 1e-8);
+        assert!(result.output_error[0].abs() < 1e-10);
+
+        // 状態を固定しても同じ入力になる
+        let mut solver = SteadyStateSolver::new();
+        solver.free_input(0);
+        solver.fix_state(0, PI / 4.0);
+        let result = solver.solve(&pendulum(), &[0.0, 0.0], &[0.0]).unwrap();
+        assert!((result.u[0] - 9.8 * (PI / 4.0).sin()).abs() < 1e-8);
+    }
+
+    #[test]
+    fn solver_reports_missing_equilibrium() {
+        // dx/dt = x^2 + 1 は平衡点を持たない
+        let model = FnModel::new(&[1.0], 0, |_t, x, _u| x.map(|v| v * v + 1.0)).unwrap();
+        let result = SteadyStateSolver::new().solve(&model, &[1.0], &[]).unwrap();
+        assert!(!result.is_converged());
+        assert!(result.get_failure_reason().is_some());
+        assert!((result.residual - 1.0).abs() < 1e-6);
+
+        assert!(SteadyStateSolver::new().solve(&model, &[1.0, 2.0], &[]).is_err());
+        let mut solver = SteadyStateSolver::new();
+        solver.fix_state(1, 0.0);
+        assert!(solver.solve(&model, &[1.0], &[]).is_err());
+    }
+
+    #[test]
+    fn trim_and_apply() {
+        let mut model = pendulum();
+        let (x0, u0) = trim(&model, 0.0, &[PI / 3.0, 0.0], &[0.0], &[0], &[]).unwrap();
+        assert_eq!(x0[0], PI / 3.0);
+        assert!((u0[0] - 9.8 * (PI / 3.0).sin()).abs() < 1e-8);
+        assert!(trim(&model, 0.0, &[0.0, 0.0], &[0.0], &[2], &[]).is_err());
+
+        let result = SteadyStateSolver::new().solve(&model, &[0.3, 0.1], &[4.9]).unwrap();
+        result.apply(&mut model).unwrap();
+        assert_eq!(model.get_state().as_slice(), result.x.as_slice());
+        assert_eq!(model.get_input()[0], 4.9);
+
+        let mut other = FnModel::new(&[0.0], 1, |_t, x, u| -x + u).unwrap();
+        assert!(result.apply(&mut other).is_err());
+    }
+}
@@ -26,8 +26,8 @@ impl NewModel {
 }
 
 impl Model for NewModel {
-    fn slopefunc(&self, x: &DMatrix<f64>) -> DMatrix<f64> {
-        self.model.slopefunc(x)
+    fn slopefunc(&self, t: f64, x: &DMatrix<f64>) -> DMatrix<f64> {
+        self.model.slopefunc(t, x)
     }
 
     fn get_signals_info(&self) -> Vec<String> {
@@ -42,10 +42,10 @@ impl Model for NewModel {
         &self.model.get_state()
     }
 
-    fn calc_nextstate(&mut self, delta_t : f64, solvertype: &SolverType) { 
+    fn calc_nextstate(&mut self, t: f64, delta_t : f64, solvertype: &SolverType) { 
         self.model.set_u(&[1.0]).unwrap();
 
-        self.model.calc_nextstate(delta_t, solvertype);
+        self.model.calc_nextstate(t, delta_t, solvertype);
     }
 
     fn get_allsignals(&self) -> Vec<f64> { 
@@ -79,8 +79,8 @@ impl RLCCircuit {
 }
 
 impl Model for RLCCircuit {
-    fn slopefunc(&self, x: &DMatrix<f64>) -> DMatrix<f64> {
-        self.model.slopefunc(x)
+    fn slopefunc(&self, t: f64, x: &DMatrix<f64>) -> DMatrix<f64> {
+        self.model.slopefunc(t, x)
     }
 
     fn get_signals_info(&self) -> Vec<String> {
@@ -96,7 +96,7 @@ impl Model for RLCCircuit {
         &self.model.get_state()
     }
 
-    fn calc_nextstate(&mut self, delta_t : f64, solvertype: &SolverType) {
+    fn calc_nextstate(&mut self, t: f64, delta_t : f64, solvertype: &SolverType) {
         let x = self.get_state();
         // let f = DMatrix::from_vec(1, 2, vec![1.0, -9.7e3]); // pole = [-50, -60]
         let f = DMatrix::from_vec(1, 2, vec![-6.00e+00, -9.95e+03]); // pole = [-20 + 10j, -20 - 10j]
@@ -105,7 +105,7 @@ impl Model for RLCCircuit {
         self.model.set_u(&u.iter().map(|x| *x).collect::<Vec<f64>>()).unwrap();
         //self.model.set_u(&[0.0]).unwrap();
 
-        self.model.calc_nextstate(delta_t, solvertype);
+        self.model.calc_nextstate(t, delta_t, solvertype);
     }
 
     fn get_allsignals(&self) -> Vec<f64> { 